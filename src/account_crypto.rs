@@ -0,0 +1,172 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::OnceLock;
+
+use crate::config::Config;
+
+// AES-GCM nonces are always 96 bits.
+const NONCE_LEN: usize = 12;
+
+struct Keys {
+    hash_key: Option<String>,
+    encryption_key: Option<String>,
+}
+
+static KEYS: OnceLock<Keys> = OnceLock::new();
+
+/// Sets the keys used by the functions below, called once at startup.
+pub fn configure(config: &Config) {
+    let _ = KEYS.set(Keys {
+        hash_key: config.account_hash_key.clone(),
+        encryption_key: config.account_encryption_key.clone(),
+    });
+}
+
+/// Keyed hash of a social account ID, used to match accounts without storing the ID itself.
+/// The key comes from `ACCOUNT_HASH_KEY` and must never change once accounts are stored.
+pub fn hash_account_id(account_id: &str) -> Result<String, String> {
+    match KEYS.get().and_then(|keys| keys.hash_key.as_ref()) {
+        Some(key) => hash_with(key, account_id),
+        None => Err("No ACCOUNT_HASH_KEY configured.".to_string()),
+    }
+}
+
+fn hash_with(key: &str, account_id: &str) -> Result<String, String> {
+    let mut mac = match <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()) {
+        Ok(mac) => mac,
+        Err(err) => return Err(format!("Invalid ACCOUNT_HASH_KEY - {:?}", err)),
+    };
+    mac.update(account_id.as_bytes());
+    Ok(format!("{:x}", mac.finalize().into_bytes()))
+}
+
+/// Encrypts the account ID if `ACCOUNT_ENCRYPTION_KEY` (base64, 32 bytes) is set.
+/// Returns `Ok(None)` when encryption is not configured.
+pub fn encrypt_account_id(account_id: &str) -> Result<Option<String>, String> {
+    match get_cipher()? {
+        Some(cipher) => Ok(Some(encrypt_with(&cipher, account_id)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reverses `encrypt_account_id`.
+pub fn decrypt_account_id(encrypted: &str) -> Result<String, String> {
+    match get_cipher()? {
+        Some(cipher) => decrypt_with(&cipher, encrypted),
+        None => Err("No ACCOUNT_ENCRYPTION_KEY configured.".to_string()),
+    }
+}
+
+fn encrypt_with(cipher: &Aes256Gcm, account_id: &str) -> Result<String, String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = match cipher.encrypt(&nonce, account_id.as_bytes()) {
+        Ok(ciphertext) => ciphertext,
+        Err(err) => return Err(format!("Could not encrypt account ID - {:?}", err)),
+    };
+    // Stored as base64(nonce || ciphertext).
+    let mut data = nonce.to_vec();
+    data.extend(ciphertext);
+    Ok(STANDARD.encode(data))
+}
+
+fn decrypt_with(cipher: &Aes256Gcm, encrypted: &str) -> Result<String, String> {
+    let data = match STANDARD.decode(encrypted) {
+        Ok(data) if data.len() > NONCE_LEN => data,
+        _ => return Err("Encrypted account ID is malformed.".to_string()),
+    };
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let plaintext = match cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+        Ok(plaintext) => plaintext,
+        Err(err) => return Err(format!("Could not decrypt account ID - {:?}", err)),
+    };
+    match String::from_utf8(plaintext) {
+        Ok(account_id) => Ok(account_id),
+        Err(err) => Err(format!(
+            "Decrypted account ID is not valid UTF-8 - {:?}",
+            err
+        )),
+    }
+}
+
+fn get_cipher() -> Result<Option<Aes256Gcm>, String> {
+    match KEYS.get().and_then(|keys| keys.encryption_key.as_ref()) {
+        Some(key) => Ok(Some(cipher_from(key)?)),
+        None => Ok(None),
+    }
+}
+
+fn cipher_from(key: &str) -> Result<Aes256Gcm, String> {
+    match STANDARD.decode(key) {
+        Ok(bytes) if bytes.len() == 32 => Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes))),
+        _ => Err("ACCOUNT_ENCRYPTION_KEY must be 32 bytes encoded as base64.".to_string()),
+    }
+}
+
+/// The account ID to show in replies, or a placeholder if it can't be decrypted.
+pub fn display_account_id(encrypted: &Option<String>) -> String {
+    match encrypted {
+        Some(encrypted) => match decrypt_account_id(encrypted) {
+            Ok(account_id) => account_id,
+            Err(_) => "*hidden*".to_string(),
+        },
+        None => "*hidden*".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(fill: u8) -> Aes256Gcm {
+        cipher_from(&STANDARD.encode([fill; 32])).unwrap()
+    }
+
+    #[test]
+    fn hash_is_deterministic_per_key() {
+        let hash = hash_with("key", "12345").unwrap();
+        assert_eq!(hash, hash_with("key", "12345").unwrap());
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, hash_with("other key", "12345").unwrap());
+        assert_ne!(hash, hash_with("key", "12346").unwrap());
+    }
+
+    #[test]
+    fn encryption_round_trips() {
+        let cipher = cipher(1);
+        let encrypted = encrypt_with(&cipher, "12345").unwrap();
+        assert!(!encrypted.contains("12345"));
+        assert_eq!(decrypt_with(&cipher, &encrypted).unwrap(), "12345");
+        // Every encryption uses a fresh nonce.
+        assert_ne!(encrypted, encrypt_with(&cipher, "12345").unwrap());
+    }
+
+    #[test]
+    fn wrong_key_is_an_error() {
+        let encrypted = encrypt_with(&cipher(1), "12345").unwrap();
+        assert!(decrypt_with(&cipher(2), &encrypted).is_err());
+    }
+
+    #[test]
+    fn malformed_ciphertext_is_an_error() {
+        let cipher = cipher(1);
+        for encrypted in ["", "not base64!", "c2hvcnQ=", &STANDARD.encode([0_u8; 40])] {
+            assert!(decrypt_with(&cipher, encrypted).is_err(), "{}", encrypted);
+        }
+        let mut tampered = STANDARD
+            .decode(encrypt_with(&cipher, "12345").unwrap())
+            .unwrap();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(decrypt_with(&cipher, &STANDARD.encode(tampered)).is_err());
+    }
+
+    #[test]
+    fn rejects_keys_that_are_not_32_bytes() {
+        assert!(cipher_from(&STANDARD.encode([1_u8; 16])).is_err());
+        assert!(cipher_from("not base64!").is_err());
+    }
+}
@@ -1,270 +1,715 @@
-use crate::commands::manage::*;
-use crate::commands::misc::ping::command as pingcommand;
-use crate::commands::verification::*;
-use mongodb::Client;
+use crate::commands::middleware::{self, Invocation};
+use crate::commands::registry;
+use crate::config::Config;
+use crate::metrics::metrics;
+use crate::state::AppState;
 use redis::AsyncCommands;
-use redis::RedisError;
+use serde_json::Value;
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandPermissionData};
+use serenity::http::Http;
+use serenity::json::hashmap_to_json_map;
 use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
 use serenity::model::application::interaction::message_component::MessageComponentInteraction;
 use serenity::model::prelude::command::Command;
 use serenity::model::prelude::command::CommandPermissionType;
-use serenity::model::prelude::interaction::Interaction;
-use serenity::model::prelude::CommandId;
-use serenity::model::prelude::CommandPermissionId;
-use serenity::model::prelude::GuildId;
-use serenity::model::prelude::RoleId;
+use serenity::model::prelude::interaction::{Interaction, MessageFlags};
+use serenity::model::prelude::{ChannelId, CommandId, GuildId};
 use serenity::prelude::Context;
+use serenity::utils::Colour;
+use std::collections::HashMap;
 use tracing::*;
 
-pub async fn register(ctx: &Context) {
-    // Do all command registrations here.
-    // If a command fails to register it will panic.
-    info!("Registering commands...");
-    setage::register(ctx).await;
-    setlogchannel::register(ctx).await;
-    setmodrole::register(ctx).await;
-    setverificaitonrole::register(ctx).await;
-    add_connection::register(ctx).await;
-    remove_connection::register(ctx).await;
-    verify::register(ctx).await;
-    currentsettings::register(ctx).await;
-    editverifysettings::register(ctx).await;
-    info!("Done.");
-
-    // Print out the currently registered commands.
-    if let Err(err) = Command::get_global_application_commands(&*ctx.http)
-        .await
-        .map(|commands| {
-            commands.iter().for_each(|command| {
-                info!(
-                    "Application command {} with ID {} is registered.",
-                    command.name, command.id
-                );
-            })
-        })
-    {
-        error!("Could not retrieve commands. {}", err.to_string())
-    }
+/// Where the commands are registered.
+#[derive(Clone, Copy, Debug)]
+pub enum CommandScope {
+    Global,
+    /// Guild commands update right away, global ones can take a while to show up.
+    Guild(GuildId),
 }
 
-pub async fn handle_interactions(
-    ctx: &Context,
-    intn: Interaction,
-    mongo_client: &mongodb::Client,
-    redis_client: &redis::Client,
-) {
-    match intn {
-        Interaction::Ping(_) => {}
-        Interaction::ApplicationCommand(a_command) => {
-            handle_commands(&ctx, &a_command, mongo_client, redis_client).await;
+impl CommandScope {
+    pub fn from_config(config: &Config) -> CommandScope {
+        match config.dev_guild_id {
+            Some(guild_id) => CommandScope::Guild(GuildId(guild_id)),
+            None => CommandScope::Global,
         }
-        Interaction::MessageComponent(m_component) => {
-            handle_components(&ctx, &m_component, mongo_client).await;
+    }
+}
+
+pub async fn registered_commands(http: &Http, scope: CommandScope) -> Result<Vec<Command>, String> {
+    let res = match scope {
+        CommandScope::Global => http.get_global_application_commands().await,
+        CommandScope::Guild(guild_id) => http.get_guild_application_commands(guild_id.0).await,
+    };
+    match res {
+        Ok(commands) => Ok(commands),
+        Err(err) => {
+            metrics().dependency_error("discord");
+            Err(format!("Could not retrieve commands. {}", err))
         }
-        _ => {}
     }
 }
 
-async fn handle_commands(
-    ctx: &&Context,
-    a_command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-    redis_client: &redis::Client,
-) {
-    info!(
-        "Application command '{}'({}) invoked by user '{}'({}) in Ch.{} Gld.{}",
-        a_command.data.name,
-        a_command.id.0,
-        a_command.user.name,
-        a_command.user.id,
-        a_command.channel_id.0,
-        a_command.guild_id.unwrap_or(GuildId(0))
-    );
+/// The commands as they should be registered, by name.
+fn desired_commands() -> Vec<(&'static str, Value)> {
+    registry::all()
+        .iter()
+        .map(|command| {
+            let mut create = CreateApplicationCommand::default();
+            command.definition(create.name(command.name()));
+            middleware::apply_permissions(command.guards(), &mut create);
+            (command.name(), Value::Object(hashmap_to_json_map(create.0)))
+        })
+        .collect()
+}
 
-    match a_command.data.name.as_str() {
-        "pingus" => {
-            pingcommand(ctx, a_command, mongo_client).await;
+/// Brings the registered commands in line with the registry. Only commands that are missing,
+/// changed or no longer exist are sent, and a failure is logged without stopping the rest.
+/// Returns the commands registered afterwards, `None` if they could not be looked up.
+#[instrument(skip(http))]
+pub async fn register(http: &Http, scope: CommandScope) -> Option<Vec<Command>> {
+    info!("Syncing commands...");
+    let registered = match registered_commands(http, scope).await {
+        Ok(registered) => registered,
+        Err(err) => {
+            error!("{}", err);
+            return None;
         }
-        "verify" => {
-            let mut conn = match redis_client.get_multiplexed_tokio_connection().await {
-                Ok(conn) => conn,
-                Err(err) => {
-                    panic!("REDIS ERROR - FAILED TO GET CONNECTION - {:?}", err);
+    };
+    let desired = desired_commands();
+
+    let mut synced = vec![];
+    let (mut created, mut updated, mut deleted, mut unchanged) = (0, 0, 0, 0);
+    for (name, definition) in &desired {
+        let current = registered.iter().find(|reg| reg.name == *name);
+        let res = match current {
+            None => {
+                info!("Creating command {}.", name);
+                created += 1;
+                match scope {
+                    CommandScope::Global => {
+                        http.create_global_application_command(definition).await
+                    }
+                    CommandScope::Guild(guild_id) => {
+                        http.create_guild_application_command(guild_id.0, definition)
+                            .await
+                    }
                 }
-            };
-            let val: Result<String, RedisError> = conn.get("hello").await;
-            debug!("{:?}", val);
-            verify::command(ctx, a_command, mongo_client, &mut conn).await;
-        }
-        "addconnection" => {
-            add_connection::command(ctx, a_command, mongo_client).await;
+            }
+            Some(current) if !is_up_to_date(definition, current) => {
+                info!("Updating command {}.", name);
+                updated += 1;
+                match scope {
+                    CommandScope::Global => {
+                        http.edit_global_application_command(current.id.0, definition)
+                            .await
+                    }
+                    CommandScope::Guild(guild_id) => {
+                        http.edit_guild_application_command(guild_id.0, current.id.0, definition)
+                            .await
+                    }
+                }
+            }
+            Some(current) => {
+                unchanged += 1;
+                Ok(current.clone())
+            }
+        };
+        match res {
+            Ok(command) => synced.push(command),
+            Err(err) => {
+                metrics().dependency_error("discord");
+                error!("Could not register the {} command! {:?}", name, err);
+                // A failed update leaves the old version registered.
+                if let Some(current) = current {
+                    synced.push(current.clone());
+                }
+            }
         }
-        "removeconnection" => {
-            remove_connection::command(ctx, a_command, mongo_client).await;
+    }
+
+    for stale in registered
+        .iter()
+        .filter(|reg| desired.iter().all(|(name, _)| *name != reg.name))
+    {
+        info!("Deleting stale command {} ({}).", stale.name, stale.id);
+        deleted += 1;
+        let res = match scope {
+            CommandScope::Global => http.delete_global_application_command(stale.id.0).await,
+            CommandScope::Guild(guild_id) => {
+                http.delete_guild_application_command(guild_id.0, stale.id.0)
+                    .await
+            }
+        };
+        if let Err(err) = res {
+            metrics().dependency_error("discord");
+            error!("Could not delete the {} command! {:?}", stale.name, err);
         }
-        "setminage" => {
-            setage::command(ctx, a_command, mongo_client).await;
+    }
+    info!(
+        "Commands synced: {} created, {} updated, {} deleted, {} unchanged.",
+        created, updated, deleted, unchanged
+    );
+    Some(synced)
+}
+
+/// Keeps the IDs of the registered commands for `sync_command_permissions`.
+pub fn remember_command_ids(state: &AppState, commands: &[Command]) {
+    let ids = commands
+        .iter()
+        .map(|command| (command.name.clone(), command.id))
+        .collect();
+    if let Ok(mut command_ids) = state.command_ids.write() {
+        *command_ids = Some(ids);
+    }
+}
+
+/// The IDs of the registered commands by name. Looked up once, unless this process registers
+/// them itself.
+async fn command_ids(http: &Http, state: &AppState) -> Result<HashMap<String, CommandId>, String> {
+    if let Ok(command_ids) = state.command_ids.read() {
+        if let Some(ids) = command_ids.as_ref() {
+            return Ok(ids.clone());
         }
-        "setlogchannel" => {
-            setlogchannel::command(ctx, a_command, mongo_client).await;
+    }
+    let registered = registered_commands(http, CommandScope::from_config(&state.config)).await?;
+    remember_command_ids(state, &registered);
+    Ok(registered
+        .into_iter()
+        .map(|command| (command.name, command.id))
+        .collect())
+}
+
+/// Compares the fields the bot sets. Discord leaves out some fields that are at their default,
+/// so a missing field matches false, null or an empty list.
+fn is_up_to_date(definition: &Value, current: &Command) -> bool {
+    let current = match serde_json::to_value(current) {
+        Ok(current) => current,
+        Err(_) => return false,
+    };
+    let definition = match definition.as_object() {
+        Some(definition) => definition,
+        None => return false,
+    };
+    // Fields the definition may leave out, so removing them from a command is noticed as well.
+    let defaults = [
+        ("options", Value::Array(vec![])),
+        ("default_member_permissions", Value::Null),
+        ("dm_permission", Value::Bool(true)),
+    ];
+    // Guild commands have no dm_permission, Discord ignores the one sent for them.
+    let guild_command = !matches!(current.get("guild_id"), None | Some(Value::Null));
+    for (key, default) in &defaults {
+        if guild_command && *key == "dm_permission" {
+            continue;
         }
-        "setmodrole" => {
-            setmodrole::command(ctx, a_command, mongo_client).await;
+        let want = definition.get(*key).unwrap_or(default);
+        let have = match (*key, current.get(*key)) {
+            ("dm_permission", None | Some(Value::Null)) => &Value::Bool(true),
+            (_, have) => have.unwrap_or(&Value::Null),
+        };
+        if !same_value(want, have) {
+            return false;
         }
-        "setverifiedrole" => {
-            setverificaitonrole::command(ctx, a_command, mongo_client).await;
+    }
+    definition
+        .iter()
+        .filter(|(key, _)| defaults.iter().all(|(default, _)| default != key))
+        .all(|(key, want)| same_value(want, current.get(key).unwrap_or(&Value::Null)))
+}
+
+fn same_value(want: &Value, have: &Value) -> bool {
+    match (want, have) {
+        (Value::Object(want), Value::Object(have)) => want
+            .iter()
+            .all(|(key, want)| same_value(want, have.get(key).unwrap_or(&Value::Null))),
+        (Value::Array(want), Value::Array(have)) => {
+            want.len() == have.len()
+                && want
+                    .iter()
+                    .zip(have)
+                    .all(|(want, have)| same_value(want, have))
         }
-        "currentsettings" => {
-            currentsettings::command(ctx, a_command, mongo_client).await;
+        (Value::Array(want), Value::Null) => want.is_empty(),
+        (Value::Bool(false), Value::Null) => true,
+        (want, have) => want == have,
+    }
+}
+
+pub async fn handle_interactions(ctx: &Context, intn: Interaction, state: &AppState) {
+    match intn {
+        Interaction::Ping(_) => {}
+        Interaction::ApplicationCommand(a_command) => {
+            handle_commands(ctx, &a_command, state).await;
         }
-        "editverifysettings" => {
-            editverifysettings::command(ctx, a_command, mongo_client).await;
+        Interaction::MessageComponent(m_component) => {
+            handle_components(ctx, &m_component, state).await;
         }
-        _ => {
-            warn!("Command not found.");
+        _ => {}
+    }
+}
+
+async fn handle_commands(
+    ctx: &Context,
+    a_command: &ApplicationCommandInteraction,
+    state: &AppState,
+) {
+    let command = match registry::find(&a_command.data.name) {
+        Some(command) => command,
+        None => {
+            warn!("Command '{}' not found.", a_command.data.name);
+            return;
         }
     };
+    middleware::dispatch(
+        ctx,
+        command,
+        Invocation::Command(a_command),
+        state,
+        command.run(ctx, a_command, state),
+    )
+    .await;
 }
 
 async fn handle_components(
-    ctx: &&Context,
+    ctx: &Context,
     m_component: &MessageComponentInteraction,
-    mongo_client: &Client,
+    state: &AppState,
 ) {
     let ids_split: Vec<&str> = m_component.data.custom_id.split(':').collect();
     let comp_type: &str = match ids_split.first() {
-        Some(str_type) => *str_type,
+        Some(str_type) => str_type,
         None => "none",
     };
-    // TODO possibly avoid another split here by using this split again, but for now I dont want to edit the signiture
-    match comp_type {
-        "HelpButton" => verify::help_callback(ctx, m_component, mongo_client).await,
-        "UndoAddConnection" => add_connection::undo_callback(ctx, m_component, mongo_client).await,
-        "UndoRemoveConnection" => {
-            remove_connection::undo_callback(ctx, m_component, mongo_client).await
-        }
-        _ => {
+    let command = match registry::find_component(comp_type) {
+        Some(command) => command,
+        None => {
             warn!("Interaction not found.");
+            return;
+        }
+    };
+    // Components go through the same guards as the command that sent them.
+    middleware::dispatch(
+        ctx,
+        command,
+        Invocation::Component(m_component),
+        state,
+        command.component(ctx, m_component, comp_type, state),
+    )
+    .await;
+}
+
+/// Deletes every registered command in the scope. The bot registers them again on its next start.
+pub async fn clear(http: &Http, scope: CommandScope) -> Result<usize, String> {
+    let registered = registered_commands(http, scope).await?;
+    for command in &registered {
+        info!("Deleting command '{}' with ID {}", command.name, command.id);
+        let res = match scope {
+            CommandScope::Global => http.delete_global_application_command(command.id.0).await,
+            CommandScope::Guild(guild_id) => {
+                http.delete_guild_application_command(guild_id.0, command.id.0)
+                    .await
+            }
+        };
+        if let Err(err) = res {
+            metrics().dependency_error("discord");
+            return Err(format!(
+                "Could not delete the {} command! {:?}",
+                command.name, err
+            ));
         }
     }
+    Ok(registered.len())
+}
+
+/// How long a log channel isn't told again about failing overrides.
+const SYNC_FAILURE_NOTICE_SECS: u64 = 24 * 60 * 60;
+
+/// Redis key holding the overrides last synced for the guild.
+fn synced_permissions_key(guild_id: &str) -> String {
+    format!("cmdperms:{}", guild_id)
+}
+
+/// Redis key set when a log channel was told that the guild's overrides could not be synced.
+fn sync_failure_notice_key(guild_id: &str) -> String {
+    format!("cmdperms_failed:{}", guild_id)
+}
+
+/// Keys `sync_command_permissions` and `report_sync_failure` keep for the guild.
+pub fn command_permission_keys(guild_id: &str) -> Vec<String> {
+    vec![
+        synced_permissions_key(guild_id),
+        sync_failure_notice_key(guild_id),
+    ]
 }
 
-// pub async fn clear(ctx: &Context) {
-//     info!("Clearing slash commands...");
-//     let mut commands_to_del: Vec<(CommandId, String)> = vec![];
-//     let _res = Command::get_global_application_commands(&*ctx.http)
-//         .await
-//         .map(|comms| {
-//             comms.iter().for_each(|comm| {
-//                 let name = comm.name.clone();
-//                 let id = comm.id.clone();
-//                 commands_to_del.push((id, name))
-//             })
-//         })
-//     info!(
-//         "There are {} command/s to be cleared.",
-//         commands_to_del.len()
-//     );
-//     for x in 0..commands_to_del.len() {
-//         info!(
-//             "Deleting command '{}' with ID {}",
-//             commands_to_del[x].1, commands_to_del[x].0
-//         );
-//         let _res =
-//             Command::delete_global_application_command(&*ctx.http, commands_to_del[x].0)
-//                 .await;
-//     }
-//     for guild in ctx.cache.guilds().await {
-//         let commands = match ctx.http.get_guild_application_commands(guild.0).await {
-//             Ok(commands) => commands,
-//             Err(e) => panic!("{}",e)
-//         };
-//         for command in commands {
-//             match ctx.http.delete_guild_application_command(guild.0, command.id.0).await {
-//                 Ok(_) => {}
-//                 Err(e) => panic!("{}", e)
-//             }
-//         };
-//     }
-//     info!("Commands cleared. Will now re-add commands.");
-// }
-
-#[instrument(skip(ctx, command))]
-pub async fn add_admins_to_perms(
+/// Hides the mod commands from the guild's members, except for its admins, owner and mod roles
+/// and the roles and users it granted commands to. Each command's overrides, hiding it included,
+/// are sent in one request, so a command that fails to sync stays visible and is only guarded
+/// by the bot. This replaces the overrides of those commands. Nothing is sent when the
+/// overrides match what was last synced for the guild.
+#[instrument(skip(ctx, state))]
+pub async fn sync_command_permissions(
     ctx: &Context,
-    command: Command,
+    state: &AppState,
     guild_id: GuildId,
-) -> serenity::static_assertions::_core::result::Result<(), &'static str> {
-    let mut admin_role_ids: Vec<RoleId> = vec![];
-    // Get roles with admin
-    match guild_id.roles(&*ctx.http).await {
-        Ok(role_map) => {
-            for role_tup in role_map {
-                let (role_id, role) = role_tup;
-                if role.permissions.administrator() && role.tags.bot_id == None {
-                    admin_role_ids.push(role_id);
-                }
+) -> Result<(), String> {
+    let http = match &state.command_permissions {
+        Some(http) => http,
+        None => return Ok(()),
+    };
+    let guild = match state.repos.guilds.get(&guild_id.0.to_string()).await? {
+        Some(guild) => guild,
+        None => return Err(format!("Could not find guild {} in database.", guild_id)),
+    };
+    // Without the owner and admin roles the commands would be hidden from them as well.
+    let (owner, admin_roles) = match ctx.cache.guild(guild_id) {
+        Some(cached) => (
+            cached.owner_id.0,
+            cached
+                .roles
+                .values()
+                .filter(|role| role.permissions.administrator())
+                .map(|role| role.id.0)
+                .collect::<Vec<u64>>(),
+        ),
+        None => return Err(format!("Guild {} is not in the cache.", guild_id)),
+    };
+    let command_ids = command_ids(&ctx.http, state).await?;
+
+    let mut overrides = vec![];
+    for command in registry::grantable() {
+        let command_id = match command_ids.get(command.name()) {
+            Some(command_id) => *command_id,
+            None => {
+                warn!(
+                    "{} is not registered, skipping its permissions.",
+                    command.name()
+                );
+                continue;
             }
+        };
+
+        let mut allowed: Vec<(&str, CommandPermissionType)> = guild
+            .mod_roles()
+            .into_iter()
+            .map(|role| (role, CommandPermissionType::Role))
+            .collect();
+        if let Some(grant) = guild.command_grants.get(command.name()) {
+            allowed.extend(
+                grant
+                    .role_IDs
+                    .iter()
+                    .map(|role| (role.as_str(), CommandPermissionType::Role)),
+            );
+            allowed.extend(
+                grant
+                    .user_IDs
+                    .iter()
+                    .map(|user| (user.as_str(), CommandPermissionType::User)),
+            );
         }
-        Err(_) => {
-            error!("Could not retrieve the guild roles.");
-            return Err("Could not retrieve the guild roles.");
-        }
-    };
-    for id in &admin_role_ids {
-        match guild_id
-            .create_application_command_permission(&*ctx.http, command.id, |perms| {
-                perms.create_permission(|perm_data| {
-                    perm_data
-                        .id(id.0)
-                        .permission(true)
-                        .kind(CommandPermissionType::Role)
-                })
-            })
-            .await
-        {
-            Ok(_) => {}
-            Err(_) => {
-                error!("Failed to create perm.");
-                return Err("Failed to create perm.");
+        // The @everyone role has the guild's ID.
+        let mut permissions: Vec<(u64, CommandPermissionType, bool)> =
+            vec![(guild_id.0, CommandPermissionType::Role, false)];
+        permissions.push((owner, CommandPermissionType::User, true));
+        permissions.extend(
+            admin_roles
+                .iter()
+                .map(|role| (*role, CommandPermissionType::Role, true)),
+        );
+        for (id, kind) in allowed {
+            match id.parse() {
+                Ok(id) => permissions.push((id, kind, true)),
+                Err(_) => warn!("Invalid {:?} ID {} in guild {}.", kind, id, guild_id),
             }
         }
+        overrides.push((command.name(), command_id, permissions));
     }
 
-    let perm = guild_id
-        .get_application_command_permissions(&ctx.http, command.id)
-        .await;
-    match perm {
+    let key = synced_permissions_key(&guild_id.0.to_string());
+    let fingerprint = overrides
+        .iter()
+        .map(|(_, command_id, permissions)| {
+            let permissions: Vec<String> = permissions
+                .iter()
+                .map(|(id, kind, allow)| format!("{:?}{}={}", kind, id, allow))
+                .collect();
+            format!("{}:{}", command_id, permissions.join(","))
+        })
+        .collect::<Vec<String>>()
+        .join(";");
+    let mut conn = state.redis.clone();
+    match conn.get::<&str, Option<String>>(&key).await {
+        Ok(Some(synced)) if synced == fingerprint => return Ok(()),
         Ok(_) => {}
-        Err(_) => {
-            return Err("Failed to get permissions.");
+        Err(err) => {
+            metrics().dependency_error("redis");
+            warn!("Could not read {} - {}", key, err);
+        }
+    }
+
+    // A failed command doesn't stop the rest, the guild is retried as a whole next time.
+    let mut failed = vec![];
+    for (name, command_id, permissions) in &overrides {
+        let permissions: Vec<CreateApplicationCommandPermissionData> = permissions
+            .iter()
+            .map(|(id, kind, allow)| {
+                let mut permission = CreateApplicationCommandPermissionData::default();
+                permission.id(*id).kind(*kind).permission(*allow);
+                permission
+            })
+            .collect();
+        let res = guild_id
+            .create_application_command_permission(http, *command_id, |perms| {
+                perms.set_permissions(permissions)
+            })
+            .await;
+        if let Err(err) = res {
+            metrics().dependency_error("discord");
+            error!(
+                "Could not update the permissions of {} in guild {} - {}",
+                name, guild_id, err
+            );
+            failed.push(format!("/{} ({})", name, err));
         }
     }
+    if !failed.is_empty() {
+        return Err(format!(
+            "Discord refused the overrides of {}.",
+            failed.join(", ")
+        ));
+    }
+    if let Err(err) = conn.set::<&str, String, ()>(&key, fingerprint).await {
+        metrics().dependency_error("redis");
+        warn!("Could not store {} - {}", key, err);
+    }
+    debug!("Command permissions of guild {} synced.", guild_id);
     Ok(())
 }
 
-#[instrument(skip(ctx))]
-pub async fn get_vec_of_perms(
+/// What the guild's admins are told when its overrides could not be synced.
+fn sync_failure_message(err: &str) -> String {
+    format!(
+        "Mod commands could not be hidden from regular members of this server, so they stay visible to everyone. The bot still only lets mods use them.\n\nThe user behind COMMAND_PERMISSIONS_TOKEN has to be able to manage this server, and the token has to be renewed before it expires.\n\nReason: {}",
+        err
+    )
+}
+
+/// Syncs the guild's overrides after a command changed its mod roles or grants, and tells the
+/// admin who used it when that failed.
+pub async fn sync_after_change(
     ctx: &Context,
-    command_id: &CommandId,
-    guild_id: &GuildId,
-) -> serenity::static_assertions::_core::result::Result<
-    Vec<(CommandPermissionId, bool)>,
-    &'static str,
-> {
-    let mut vec_of_roles: Vec<(CommandPermissionId, bool)> = vec![];
-    let perm = guild_id
-        .get_application_command_permissions(&ctx.http, *command_id)
+    state: &AppState,
+    interaction: &ApplicationCommandInteraction,
+) {
+    let guild_id = match interaction.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+    let err = match sync_command_permissions(ctx, state, guild_id).await {
+        Ok(()) => return,
+        Err(err) => err,
+    };
+    error!("{}", err);
+    let res = interaction
+        .create_followup_message(&ctx.http, |message| {
+            message
+                .flags(MessageFlags::EPHEMERAL)
+                .content(sync_failure_message(&err))
+        })
         .await;
-    match perm {
-        Ok(p) => {
-            for pe in p.permissions {
-                vec_of_roles.push((pe.id, pe.permission));
-            }
+    if let Err(err) = res {
+        metrics().dependency_error("discord");
+        error!("Could not send the sync failure follow up - {}", err);
+    }
+}
+
+/// Tells the guild's log channel that its overrides could not be synced, at most once a day so
+/// reconnects don't repeat it.
+pub async fn report_sync_failure(ctx: &Context, state: &AppState, guild_id: GuildId, err: &str) {
+    let guild = match state.repos.guilds.get(&guild_id.0.to_string()).await {
+        Ok(Some(guild)) => guild,
+        Ok(None) => return,
+        Err(err) => {
+            error!("{}", err);
+            return;
         }
-        Err(_) => {
-            return Err("Failed to get permissions.");
+    };
+    let channel_id: u64 = match guild.verification_logs_channel_ID.parse() {
+        Ok(0) | Err(_) => return,
+        Ok(channel_id) => channel_id,
+    };
+    let key = sync_failure_notice_key(&guild_id.0.to_string());
+    let mut conn = state.redis.clone();
+    let first: Result<bool, _> = redis::cmd("SET")
+        .arg(&key)
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(SYNC_FAILURE_NOTICE_SECS)
+        .query_async::<_, Option<String>>(&mut conn)
+        .await
+        .map(|set| set.is_some());
+    match first {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(err) => {
+            metrics().dependency_error("redis");
+            warn!("Could not set {} - {}", key, err);
+            return;
         }
     }
-    Ok(vec_of_roles)
+    let res = ChannelId(channel_id)
+        .send_message(&ctx.http, |message| {
+            message.embed(|embed| {
+                embed
+                    .title("Mod commands are visible to everyone")
+                    .description(sync_failure_message(err))
+                    .color(Colour::ORANGE)
+            })
+        })
+        .await;
+    if let Err(err) = res {
+        metrics().dependency_error("discord");
+        warn!(
+            "Could not tell guild {} about the sync failure - {}",
+            guild_id, err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use serenity::model::Permissions;
+
+    use super::*;
+
+    /// A command as Discord returns it, with `changes` applied on top.
+    fn registered(changes: Value) -> Command {
+        let mut command = json!({
+            "id": "1",
+            "application_id": "2",
+            "type": 1,
+            "name": "setminage",
+            "description": "Set the minimum account age",
+            "default_member_permissions": null,
+            "dm_permission": true,
+            "version": "3",
+            "options": [{
+                "type": 4,
+                "name": "days",
+                "description": "Minimum age in days",
+                "required": true,
+                "choices": [{"name": "week", "value": 7}]
+            }]
+        });
+        for (key, value) in changes.as_object().unwrap() {
+            match value {
+                Value::Null => command.as_object_mut().unwrap().remove(key),
+                value => command
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(key.clone(), value.clone()),
+            };
+        }
+        serde_json::from_value(command).unwrap()
+    }
+
+    /// The definition the registry would send for the command `registered` starts from.
+    fn definition(extra: impl FnOnce(&mut CreateApplicationCommand)) -> Value {
+        let mut create = CreateApplicationCommand::default();
+        create
+            .name("setminage")
+            .description("Set the minimum account age")
+            .create_option(|opt| {
+                opt.name("days")
+                    .description("Minimum age in days")
+                    .kind(serenity::model::application::command::CommandOptionType::Integer)
+                    .required(true)
+                    .add_int_choice("week", 7)
+            });
+        extra(&mut create);
+        Value::Object(hashmap_to_json_map(create.0))
+    }
+
+    #[test]
+    fn unchanged_command_is_up_to_date() {
+        assert!(is_up_to_date(&definition(|_| {}), &registered(json!({}))));
+        // Discord leaves out fields at their default.
+        let bare = registered(json!({"default_member_permissions": null, "dm_permission": null}));
+        assert!(is_up_to_date(&definition(|_| {}), &bare));
+    }
+
+    #[test]
+    fn changed_description_is_noticed() {
+        let definition = definition(|create| {
+            create.description("Something else");
+        });
+        assert!(!is_up_to_date(&definition, &registered(json!({}))));
+    }
+
+    #[test]
+    fn changed_options_are_noticed() {
+        let renamed = registered(json!({"options": [{
+            "type": 4,
+            "name": "age",
+            "description": "Minimum age in days",
+            "required": true,
+            "choices": [{"name": "week", "value": 7}]
+        }]}));
+        assert!(!is_up_to_date(&definition(|_| {}), &renamed));
+
+        let other_choice = registered(json!({"options": [{
+            "type": 4,
+            "name": "days",
+            "description": "Minimum age in days",
+            "required": true,
+            "choices": [{"name": "week", "value": 8}]
+        }]}));
+        assert!(!is_up_to_date(&definition(|_| {}), &other_choice));
+
+        let no_options = registered(json!({"options": []}));
+        assert!(!is_up_to_date(&definition(|_| {}), &no_options));
+
+        // An option that is no longer defined has to be removed as well.
+        let without_options = Value::Object(hashmap_to_json_map({
+            let mut create = CreateApplicationCommand::default();
+            create
+                .name("setminage")
+                .description("Set the minimum account age");
+            create.0
+        }));
+        assert!(!is_up_to_date(&without_options, &registered(json!({}))));
+        assert!(is_up_to_date(&without_options, &no_options));
+    }
+
+    #[test]
+    fn changed_member_permissions_are_noticed() {
+        let admin_only = definition(|create| {
+            create.default_member_permissions(Permissions::ADMINISTRATOR);
+        });
+        let registered_admin_only = registered(json!({"default_member_permissions": "8"}));
+
+        assert!(!is_up_to_date(&admin_only, &registered(json!({}))));
+        assert!(is_up_to_date(&admin_only, &registered_admin_only));
+        assert!(!is_up_to_date(&definition(|_| {}), &registered_admin_only));
+    }
+
+    #[test]
+    fn dm_permission_of_guild_commands() {
+        let guild_only = definition(|create| {
+            create.dm_permission(false);
+        });
+        assert!(!is_up_to_date(&guild_only, &registered(json!({}))));
+        assert!(is_up_to_date(
+            &guild_only,
+            &registered(json!({"dm_permission": false}))
+        ));
+        // Commands registered to a guild come back without dm_permission, whatever was sent.
+        let guild_command = registered(json!({"guild_id": "3", "dm_permission": null}));
+        assert!(is_up_to_date(&definition(|_| {}), &guild_command));
+        assert!(is_up_to_date(&guild_only, &guild_command));
+    }
 }
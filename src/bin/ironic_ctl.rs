@@ -0,0 +1,421 @@
+//! Maintenance tasks for operators. Reads the same config as the bot, so it can be run next to it
+//! with the same environment.
+
+use std::collections::HashSet;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs, io, process};
+
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serenity::http::Http;
+use tracing::{error, info};
+use tracing_subscriber::filter::LevelFilter;
+
+use ironic_bot::account_crypto;
+use ironic_bot::application_commands::{self, CommandScope};
+use ironic_bot::config::{Config, StorageBackend};
+use ironic_bot::dbmodels::guild::Guild;
+use ironic_bot::dead_letters::{self, dead_letter_key, failing_key};
+use ironic_bot::repository::cached::CachedGuildRepository;
+use ironic_bot::repository::Repositories;
+
+/// Keys used within this many seconds are never purged, they may belong to a guild the bot has
+/// just joined and not stored yet.
+const PURGE_GRACE_SECS: u64 = 60 * 60;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Task<'a> {
+    SyncCommands,
+    ClearCommands,
+    ListGuilds,
+    DumpGuild(&'a str),
+    RestoreGuild(&'a str),
+    ListDeadLetters,
+    /// Every dead letter when no key is given.
+    ReplayDeadLetters(Option<&'a str>),
+    PurgeRedis {
+        delete: bool,
+    },
+}
+
+fn parse<'a>(args: &[&'a str]) -> Option<Task<'a>> {
+    match *args {
+        ["commands", "sync"] => Some(Task::SyncCommands),
+        ["commands", "clear"] => Some(Task::ClearCommands),
+        ["guilds", "list"] => Some(Task::ListGuilds),
+        ["guild", "dump", guild_id] => Some(Task::DumpGuild(guild_id)),
+        ["guild", "restore", path] => Some(Task::RestoreGuild(path)),
+        ["deadletters", "list"] => Some(Task::ListDeadLetters),
+        ["deadletters", "replay", "--all"] => Some(Task::ReplayDeadLetters(None)),
+        ["deadletters", "replay", key] if !key.starts_with("--") => {
+            Some(Task::ReplayDeadLetters(Some(key)))
+        }
+        ["redis", "purge"] => Some(Task::PurgeRedis { delete: false }),
+        ["redis", "purge", "--yes"] => Some(Task::PurgeRedis { delete: true }),
+        _ => None,
+    }
+}
+
+const USAGE: &str = "Usage: ironic_ctl <command>
+
+Commands:
+    commands sync                 Register the commands, changing only what differs
+    commands clear                Delete every registered command
+    guilds list                   List the stored guilds and their settings
+    guild dump <guild id>         Print a guild's settings document as JSON
+    guild restore <file>          Save a document printed by 'guild dump', '-' reads stdin
+    deadletters list              Show the completions that kept failing
+    deadletters replay <key>      Put a dead lettered completion back in the queue
+    deadletters replay --all      Put every dead lettered completion back in the queue
+    redis purge [--yes]           Show leftover keys of removed guilds and keys without a TTL,
+                                  --yes deletes them. Keys used in the last hour are kept.
+
+Commands go to DEV_GUILD_ID when it is set, like the bot's own registration.";
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_max_level(LevelFilter::INFO)
+        .init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if matches!(args.as_slice(), [] | ["help" | "--help" | "-h"]) {
+        println!("{}", USAGE);
+        return;
+    }
+    let task = match parse(&args) {
+        Some(task) => task,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    };
+    account_crypto::configure(&config);
+
+    let res = match task {
+        Task::SyncCommands => sync_commands(&config).await,
+        Task::ClearCommands => clear_commands(&config).await,
+        Task::ListGuilds => list_guilds(&config).await,
+        Task::DumpGuild(guild_id) => dump_guild(&config, guild_id).await,
+        Task::RestoreGuild(path) => restore_guild(&config, path).await,
+        Task::ListDeadLetters => list_dead_letters(&config).await,
+        Task::ReplayDeadLetters(key) => replay_dead_letters(&config, key).await,
+        Task::PurgeRedis { delete } => purge_redis(&config, delete).await,
+    };
+    if let Err(err) = res {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn discord_http(config: &Config) -> Http {
+    Http::new_with_application_id(&config.discord_token, config.application_id)
+}
+
+/// Unlike the bot this gives up right away when redis is unreachable.
+async fn redis_conn(config: &Config) -> Result<ConnectionManager, String> {
+    let client = match redis::Client::open(config.redis_url()) {
+        Ok(client) => client,
+        Err(err) => return Err(format!("Invalid redis settings - {}", err)),
+    };
+    match ConnectionManager::new(client).await {
+        Ok(conn) => Ok(conn),
+        Err(err) => Err(format!("Could not connect to redis - {}", err)),
+    }
+}
+
+/// Writes to the guilds go through the cache layer when the bot caches them, so every replica
+/// drops its copy.
+async fn repositories(config: &Config) -> Result<Repositories, String> {
+    let (mut repos, _) = Repositories::connect(config).await?;
+    if config.guild_cache_ttl_secs > 0 {
+        repos.guilds = Arc::new(CachedGuildRepository::new(
+            Arc::clone(&repos.guilds),
+            Duration::from_secs(config.guild_cache_ttl_secs),
+            redis_conn(config).await?,
+        ));
+    }
+    Ok(repos)
+}
+
+async fn sync_commands(config: &Config) -> Result<(), String> {
+    let registered =
+        application_commands::register(&discord_http(config), CommandScope::from_config(config))
+            .await;
+    match registered {
+        Some(_) => Ok(()),
+        None => Err("Could not sync the commands.".to_string()),
+    }
+}
+
+async fn clear_commands(config: &Config) -> Result<(), String> {
+    let deleted =
+        application_commands::clear(&discord_http(config), CommandScope::from_config(config))
+            .await?;
+    info!("Deleted {} commands.", deleted);
+    Ok(())
+}
+
+async fn list_guilds(config: &Config) -> Result<(), String> {
+    let repos = repositories(config).await?;
+    for guild in repos.guilds.list().await? {
+        println!(
+            "{} {} mod roles: [{}] verified role: {} logs: {} min age: {} days grants: {}",
+            guild.guild_ID,
+            if guild.active { "active" } else { "inactive" },
+            guild.mod_roles().join(", "),
+            guild.verification_role_ID,
+            guild.verification_logs_channel_ID,
+            guild.verification_age,
+            guild.command_grants.len()
+        );
+    }
+    Ok(())
+}
+
+async fn dump_guild(config: &Config, guild_id: &str) -> Result<(), String> {
+    let repos = repositories(config).await?;
+    let guild = match repos.guilds.get(guild_id).await? {
+        Some(guild) => guild,
+        None => return Err(format!("Could not find guild {} in database.", guild_id)),
+    };
+    match serde_json::to_string_pretty(&guild) {
+        Ok(json) => {
+            println!("{}", json);
+            Ok(())
+        }
+        Err(err) => Err(format!("Could not serialize guild {} - {}", guild_id, err)),
+    }
+}
+
+async fn restore_guild(config: &Config, path: &str) -> Result<(), String> {
+    let contents = if path == "-" {
+        let mut contents = String::new();
+        match io::stdin().read_to_string(&mut contents) {
+            Ok(_) => contents,
+            Err(err) => return Err(format!("Could not read stdin - {}", err)),
+        }
+    } else {
+        match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => return Err(format!("Could not read {} - {}", path, err)),
+        }
+    };
+    let guild: Guild = match serde_json::from_str(&contents) {
+        Ok(guild) => guild,
+        Err(err) => return Err(format!("{} is not a valid guild document - {}", path, err)),
+    };
+    let repos = repositories(config).await?;
+    repos.guilds.save(&guild).await?;
+    info!("Restored guild {}.", guild.guild_ID);
+    Ok(())
+}
+
+async fn list_dead_letters(config: &Config) -> Result<(), String> {
+    let mut conn = redis_conn(config).await?;
+    let dead_letters = dead_letters::list(&mut conn).await?;
+    for dead_letter in &dead_letters {
+        println!(
+            "{} {}",
+            dead_letter.key,
+            dead_letter.value.as_deref().unwrap_or("(gone)")
+        );
+    }
+    info!("{} dead letters.", dead_letters.len());
+    Ok(())
+}
+
+async fn replay_dead_letters(config: &Config, key: Option<&str>) -> Result<(), String> {
+    let mut conn = redis_conn(config).await?;
+    let keys = match key {
+        Some(key) => vec![key.to_string()],
+        None => dead_letters::list(&mut conn)
+            .await?
+            .into_iter()
+            .map(|dead_letter| dead_letter.key)
+            .collect(),
+    };
+    for key in keys {
+        dead_letters::replay(&mut conn, &key).await?;
+        info!("Queued {} again.", key);
+    }
+    Ok(())
+}
+
+/// Finds completion keys of guilds that are no longer stored, failure markers whose completion
+/// is gone, and short lived keys that somehow lost their TTL. They are only deleted with `delete`.
+async fn purge_redis(config: &Config, delete: bool) -> Result<(), String> {
+    // A fresh in-memory store has no guilds, which would make every completion look stale.
+    if config.storage_backend == StorageBackend::Memory {
+        return Err("Purging needs the bot's database, not the in-memory backend.".to_string());
+    }
+    let repos = repositories(config).await?;
+    let mut conn = redis_conn(config).await?;
+    let guild_ids: HashSet<String> = repos
+        .guilds
+        .list()
+        .await?
+        .into_iter()
+        .map(|guild| guild.guild_ID)
+        .collect();
+
+    let mut stale: Vec<(String, &str)> = vec![];
+    for pattern in [
+        "complete:*".to_string(),
+        dead_letter_key("complete:*"),
+        failing_key("complete:*"),
+    ] {
+        for key in scan(&mut conn, &pattern).await? {
+            // complete:{user}:{guild}[:n], possibly behind a prefix.
+            let completion = &key[key.find("complete:").unwrap_or(0)..];
+            let guild_id = completion.split(':').nth(2).unwrap_or_default();
+            if !guild_ids.contains(guild_id) {
+                stale.push((key, "guild is not stored"));
+                continue;
+            }
+            if key.starts_with(&failing_key("")) && !exists(&mut conn, completion).await? {
+                stale.push((key, "completion is gone"));
+            }
+        }
+    }
+    for pattern in ["uuid:*", "undo:*", "claim:*"] {
+        for key in scan(&mut conn, pattern).await? {
+            let ttl: i64 = match conn.ttl(&key).await {
+                Ok(ttl) => ttl,
+                Err(err) => return Err(format!("Could not read the TTL of {} - {:?}", key, err)),
+            };
+            // -1 means no expiry, -2 that the key expired during the scan.
+            if ttl == -1 {
+                stale.push((key, "no TTL"));
+            }
+        }
+    }
+
+    let mut purged = 0;
+    for (key, reason) in &stale {
+        if recently_used(&mut conn, key).await {
+            println!("{} ({}, kept as it was used recently)", key, reason);
+            continue;
+        }
+        println!("{} ({})", key, reason);
+        purged += 1;
+        if !delete {
+            continue;
+        }
+        if let Err(err) = conn.del::<&str, u64>(key).await {
+            return Err(format!("Could not delete {} - {:?}", key, err));
+        }
+    }
+    if delete {
+        info!("Deleted {} stale keys.", purged);
+    } else {
+        info!(
+            "Found {} stale keys, run with --yes to delete them.",
+            purged
+        );
+    }
+    Ok(())
+}
+
+async fn scan(conn: &mut ConnectionManager, pattern: &str) -> Result<Vec<String>, String> {
+    let mut keys: Vec<String> = vec![];
+    match conn.scan_match::<&str, String>(pattern).await {
+        Ok(mut iter) => {
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+        }
+        Err(err) => return Err(format!("Redis error in scan - {:?}", err)),
+    }
+    Ok(keys)
+}
+
+/// Whether the key was read or written within the grace period. Keys whose idle time can't be
+/// read, for example under an LFU eviction policy, count as used.
+async fn recently_used(conn: &mut ConnectionManager, key: &str) -> bool {
+    let res: Result<Option<u64>, redis::RedisError> = redis::cmd("OBJECT")
+        .arg("IDLETIME")
+        .arg(key)
+        .query_async(conn)
+        .await;
+    match res {
+        Ok(Some(idle)) => idle < PURGE_GRACE_SECS,
+        // Gone since the scan, nothing left to delete.
+        Ok(None) => true,
+        Err(_) => true,
+    }
+}
+
+async fn exists(conn: &mut ConnectionManager, key: &str) -> Result<bool, String> {
+    match conn.exists(key).await {
+        Ok(exists) => Ok(exists),
+        Err(err) => Err(format!("Could not check {} - {:?}", key, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tasks() {
+        assert_eq!(parse(&["commands", "sync"]), Some(Task::SyncCommands));
+        assert_eq!(parse(&["commands", "clear"]), Some(Task::ClearCommands));
+        assert_eq!(parse(&["guilds", "list"]), Some(Task::ListGuilds));
+        assert_eq!(
+            parse(&["guild", "dump", "123"]),
+            Some(Task::DumpGuild("123"))
+        );
+        assert_eq!(
+            parse(&["guild", "restore", "-"]),
+            Some(Task::RestoreGuild("-"))
+        );
+        assert_eq!(parse(&["deadletters", "list"]), Some(Task::ListDeadLetters));
+        assert_eq!(
+            parse(&["deadletters", "replay", "complete:1:2"]),
+            Some(Task::ReplayDeadLetters(Some("complete:1:2")))
+        );
+        assert_eq!(
+            parse(&["deadletters", "replay", "--all"]),
+            Some(Task::ReplayDeadLetters(None))
+        );
+    }
+
+    #[test]
+    fn purge_only_deletes_when_asked() {
+        assert_eq!(
+            parse(&["redis", "purge"]),
+            Some(Task::PurgeRedis { delete: false })
+        );
+        assert_eq!(
+            parse(&["redis", "purge", "--yes"]),
+            Some(Task::PurgeRedis { delete: true })
+        );
+        assert_eq!(parse(&["redis", "purge", "--dry-run"]), None);
+        assert_eq!(parse(&["redis", "purge", "yes"]), None);
+    }
+
+    #[test]
+    fn rejects_unknown_and_incomplete_commands() {
+        assert_eq!(parse(&[]), None);
+        assert_eq!(parse(&["commands"]), None);
+        assert_eq!(parse(&["commands", "sync", "now"]), None);
+        assert_eq!(parse(&["guild", "dump"]), None);
+        assert_eq!(parse(&["guilds", "dump", "123"]), None);
+        assert_eq!(parse(&["deadletters", "replay"]), None);
+        // A mistyped flag is not taken for a key.
+        assert_eq!(parse(&["deadletters", "replay", "--al"]), None);
+    }
+}
@@ -9,6 +9,8 @@ use serenity::{
 };
 use tracing::{error, warn};
 
+use crate::metrics::metrics;
+
 pub async fn interaction_error(
     err_message: &str,
     command: &ApplicationCommandInteraction,
@@ -34,6 +36,7 @@ pub async fn interaction_error(
         .await;
 
     if let Err(err) = res {
+        metrics().dependency_error("discord");
         error!(
             "An error occurred while sending an error interaction reply. {}",
             err
@@ -66,6 +69,7 @@ pub async fn interaction_error_comp(
         .await;
 
     if let Err(err) = res {
+        metrics().dependency_error("discord");
         error!(
             "An error occurred while sending an error interaction reply. {}",
             err
@@ -89,6 +93,7 @@ pub async fn channel_message_error(
         })
     }).await;
     if let Err(err) = res {
+        metrics().dependency_error("discord");
         error!(
             "An error occurred while sending an error interaction reply. {}",
             err
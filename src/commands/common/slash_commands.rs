@@ -1,72 +1,288 @@
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
 use serenity::model::prelude::interaction::application_command::CommandDataOptionValue;
 use serenity::model::prelude::{PartialChannel, Role};
-use serenity::model::{
-    application::interaction::application_command::CommandDataOption, user::User,
-};
-
-pub async fn extract_vec(options: &[CommandDataOption]) -> Vec<(&str, CommandDataOptionValue)> {
-    let mut params: Vec<(&str, CommandDataOptionValue)> = vec![];
-    options.iter().for_each(|opt| {
-        let value: Option<CommandDataOptionValue> = opt.to_owned().resolved;
+use serenity::model::user::User;
+use serenity::prelude::Context;
+
+use super::interaction_error::interaction_error;
+
+/// The resolved options of a command interaction, looked up by name.
+pub struct Options<'a> {
+    values: Vec<(&'a str, &'a CommandDataOptionValue)>,
+}
+
+impl<'a> Options<'a> {
+    pub fn new(command: &'a ApplicationCommandInteraction) -> Options<'a> {
+        Options {
+            values: command
+                .data
+                .options
+                .iter()
+                .filter_map(|opt| opt.resolved.as_ref().map(|val| (opt.name.as_str(), val)))
+                .collect(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&'a CommandDataOptionValue> {
+        self.values
+            .iter()
+            .find(|(opt_name, _)| *opt_name == name)
+            .map(|(_, val)| *val)
+    }
+
+    pub fn required<T: OptionValue>(&self, name: &str) -> Result<T, String> {
+        match self.optional(name)? {
+            Some(val) => Ok(val),
+            None => Err(format!("'{}' param is missing.", name)),
+        }
+    }
+
+    pub fn optional<T: OptionValue>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(val) => match T::from_value(val) {
+                Some(val) => Ok(Some(val)),
+                None => Err(format!("'{}' param must be {}.", name, T::DESCRIPTION)),
+            },
+        }
+    }
+}
+
+/// A type a single option can be converted into.
+pub trait OptionValue: Sized {
+    /// Used in the error message when the option has the wrong type.
+    const DESCRIPTION: &'static str;
+    fn from_value(value: &CommandDataOptionValue) -> Option<Self>;
+}
+
+impl OptionValue for String {
+    const DESCRIPTION: &'static str = "text";
+    fn from_value(value: &CommandDataOptionValue) -> Option<String> {
         match value {
-            None => {}
-            Some(value) => params.push((opt.name.as_str(), value)),
+            CommandDataOptionValue::String(string) => Some(string.clone()),
+            _ => None,
         }
-    });
-    params
-}
-#[allow(dead_code)]
-pub async fn get_number(option_value: CommandDataOptionValue) -> Option<f64> {
-    let value: Option<f64> = match option_value {
-        CommandDataOptionValue::Number(num) => Some(num),
-        _ => None,
-    };
-    value
+    }
 }
-#[allow(dead_code)]
-pub async fn get_role(option_value: CommandDataOptionValue) -> Option<Role> {
-    let value: Option<Role> = match option_value {
-        CommandDataOptionValue::Role(role) => Some(role),
-        _ => None,
-    };
-    value
+
+impl OptionValue for i64 {
+    const DESCRIPTION: &'static str = "an integer (no decimal)";
+    fn from_value(value: &CommandDataOptionValue) -> Option<i64> {
+        match value {
+            CommandDataOptionValue::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
 }
 
-pub async fn get_channel(option_value: CommandDataOptionValue) -> Option<PartialChannel> {
-    let value: Option<PartialChannel> = match option_value {
-        CommandDataOptionValue::Channel(chan) => Some(chan),
-        _ => None,
-    };
-    value
+impl OptionValue for f64 {
+    const DESCRIPTION: &'static str = "a number";
+    fn from_value(value: &CommandDataOptionValue) -> Option<f64> {
+        match value {
+            CommandDataOptionValue::Number(num) => Some(*num),
+            _ => None,
+        }
+    }
 }
 
-pub async fn get_user(option_value: CommandDataOptionValue) -> Option<User> {
-    let value: Option<User> = match option_value {
-        CommandDataOptionValue::User(user, _) => Some(user),
-        _ => None,
-    };
-    value
+impl OptionValue for bool {
+    const DESCRIPTION: &'static str = "true or false";
+    fn from_value(value: &CommandDataOptionValue) -> Option<bool> {
+        match value {
+            CommandDataOptionValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
 }
-#[allow(dead_code)]
-pub async fn get_bool(option_value: CommandDataOptionValue) -> Option<bool> {
-    let value: Option<bool> = match option_value {
-        CommandDataOptionValue::Boolean(bool) => Some(bool),
-        _ => None,
-    };
-    value
+
+impl OptionValue for Role {
+    const DESCRIPTION: &'static str = "a role";
+    fn from_value(value: &CommandDataOptionValue) -> Option<Role> {
+        match value {
+            CommandDataOptionValue::Role(role) => Some(role.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl OptionValue for User {
+    const DESCRIPTION: &'static str = "a user";
+    fn from_value(value: &CommandDataOptionValue) -> Option<User> {
+        match value {
+            CommandDataOptionValue::User(user, _) => Some(user.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl OptionValue for PartialChannel {
+    const DESCRIPTION: &'static str = "a channel";
+    fn from_value(value: &CommandDataOptionValue) -> Option<PartialChannel> {
+        match value {
+            CommandDataOptionValue::Channel(chan) => Some(chan.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// A field of a `command_options!` struct. Plain types are required options, `Option`s are
+/// optional ones.
+pub trait OptionField: Sized {
+    fn extract(options: &Options, name: &str) -> Result<Self, String>;
 }
 
-pub async fn get_string(option_value: CommandDataOptionValue) -> Option<String> {
-    let value: Option<String> = match option_value {
-        CommandDataOptionValue::String(string) => Some(string),
-        _ => None,
+impl<T: OptionValue> OptionField for Option<T> {
+    fn extract(options: &Options, name: &str) -> Result<Option<T>, String> {
+        options.optional(name)
+    }
+}
+
+macro_rules! required_option_fields {
+    ($($ty:ty),*) => {
+        $(
+            impl OptionField for $ty {
+                fn extract(options: &Options, name: &str) -> Result<$ty, String> {
+                    options.required(name)
+                }
+            }
+        )*
     };
-    value
 }
-pub async fn get_int(option_value: CommandDataOptionValue) -> Option<i64> {
-    let value: Option<i64> = match option_value {
-        CommandDataOptionValue::Integer(i) => Some(i),
-        _ => None,
+
+required_option_fields!(String, i64, f64, bool, Role, User, PartialChannel);
+
+/// The options of one command, parsed into a struct.
+pub trait CommandOptions: Sized {
+    fn parse(options: &Options) -> Result<Self, String>;
+}
+
+/// Declares a struct holding a command's options. Each field is filled from the option with the
+/// same name, so adding an option to a command is a one line change.
+///
+/// ```ignore
+/// command_options! {
+///     struct SetAgeOptions {
+///         age: i64,
+///         reason: Option<String>,
+///     }
+/// }
+/// ```
+macro_rules! command_options {
+    ($(#[$meta:meta])* $vis:vis struct $name:ident { $($field:ident: $ty:ty),* $(,)? }) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($field: $ty),*
+        }
+
+        impl $crate::commands::common::slash_commands::CommandOptions for $name {
+            fn parse(
+                options: &$crate::commands::common::slash_commands::Options,
+            ) -> Result<Self, String> {
+                use $crate::commands::common::slash_commands::OptionField;
+                Ok($name {
+                    $($field: <$ty as OptionField>::extract(options, stringify!($field))?),*
+                })
+            }
+        }
     };
-    value
+}
+pub(crate) use command_options;
+
+/// Parses the command's options, replying with the problem and returning `None` when they are
+/// invalid.
+pub async fn parse_options<T: CommandOptions>(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Option<T> {
+    match T::parse(&Options::new(command)) {
+        Ok(options) => Some(options),
+        Err(err) => {
+            interaction_error(&err, command, ctx).await;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    command_options! {
+        #[derive(Debug, PartialEq)]
+        struct TestOptions {
+            age: i64,
+            reason: Option<String>,
+            bonus: Option<f64>,
+            enabled: bool,
+        }
+    }
+
+    fn parse(values: &[(&'static str, CommandDataOptionValue)]) -> Result<TestOptions, String> {
+        TestOptions::parse(&Options {
+            values: values.iter().map(|(name, val)| (*name, val)).collect(),
+        })
+    }
+
+    #[test]
+    fn parses_required_and_optional_options() {
+        let options = parse(&[
+            ("age", CommandDataOptionValue::Integer(7)),
+            ("reason", CommandDataOptionValue::String("alts".to_string())),
+            ("bonus", CommandDataOptionValue::Number(1.5)),
+            ("enabled", CommandDataOptionValue::Boolean(true)),
+        ])
+        .unwrap();
+        assert_eq!(
+            options,
+            TestOptions {
+                age: 7,
+                reason: Some("alts".to_string()),
+                bonus: Some(1.5),
+                enabled: true,
+            }
+        );
+    }
+
+    #[test]
+    fn optional_options_can_be_left_out() {
+        let options = parse(&[
+            ("age", CommandDataOptionValue::Integer(7)),
+            ("enabled", CommandDataOptionValue::Boolean(false)),
+        ])
+        .unwrap();
+        assert_eq!(options.reason, None);
+        assert_eq!(options.bonus, None);
+    }
+
+    #[test]
+    fn missing_required_option_is_an_error() {
+        let err = parse(&[("enabled", CommandDataOptionValue::Boolean(false))]).unwrap_err();
+        assert_eq!(err, "'age' param is missing.");
+    }
+
+    #[test]
+    fn wrong_type_is_an_error() {
+        let enabled = ("enabled", CommandDataOptionValue::Boolean(false));
+        let err = parse(&[
+            ("age", CommandDataOptionValue::String("seven".to_string())),
+            enabled.clone(),
+        ])
+        .unwrap_err();
+        assert_eq!(err, "'age' param must be an integer (no decimal).");
+
+        let err = parse(&[
+            ("age", CommandDataOptionValue::Integer(7)),
+            ("bonus", CommandDataOptionValue::Integer(1)),
+            enabled,
+        ])
+        .unwrap_err();
+        assert_eq!(err, "'bonus' param must be a number.");
+
+        let err = parse(&[
+            ("age", CommandDataOptionValue::Integer(7)),
+            ("enabled", CommandDataOptionValue::Integer(1)),
+        ])
+        .unwrap_err();
+        assert_eq!(err, "'enabled' param must be true or false.");
+    }
 }
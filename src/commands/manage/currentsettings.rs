@@ -1,34 +1,17 @@
-use super::super::super::dbmodels::guild::Guild as GuildStruct;
 use super::super::common::interaction_error::{channel_message_error, interaction_error};
-use crate::commands::common::permissions_check::check_if_mod;
-use mongodb::bson::doc;
-use mongodb::*;
-use serenity::model::application::command::Command;
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::dbmodels::guild::{CommandGrant, Guild};
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
 use serenity::prelude::Context;
-use tracing::{error, info, instrument, warn};
-
-#[instrument(skip(ctx, mongo_client))]
-pub async fn command(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-) {
-    // Check if the user is a mod.
-    match check_if_mod(ctx, command, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error("You must be a mod to use this command.", command, ctx).await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error(err, command, ctx).await;
-            return;
-        }
-    }
+use tracing::{error, instrument};
 
+#[instrument(skip(ctx, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
     // Extract the Guild ID as a string.
     let guild_id_str = match command.guild_id {
         None => {
@@ -38,11 +21,7 @@ pub async fn command(
         Some(x) => x.0.to_string(),
     };
 
-    let collection: Collection<GuildStruct> = mongo_client.database("botdb").collection("guilds");
-    let settings_doc = match collection
-        .find_one(doc! {"guild_ID": guild_id_str}, None)
-        .await
-    {
+    let settings_doc = match repos.guilds.get(&guild_id_str).await {
         Ok(res) => match res {
             None => {
                 interaction_error(
@@ -56,7 +35,7 @@ pub async fn command(
             Some(doc) => doc,
         },
         Err(err) => {
-            error!("{:?}", err);
+            error!("{}", err);
             interaction_error("Could not get guild from the database.", command, ctx).await;
             return;
         }
@@ -70,7 +49,7 @@ pub async fn command(
                     message.embed(|embed| {
                         embed
                             .title("Current Server Settings")
-                            .field("Mod Role:", format!("**{}**\n<@&{}>", settings_doc.mod_role_ID, settings_doc.mod_role_ID), true)
+                            .field("Mod Roles:", mod_roles_field(&settings_doc), true)
                             .field("Verification Age:", format!("\n**{}** days", settings_doc.verification_age), true)
                             .field("Logs Channel:", format!("**{}**\n<#{}>", settings_doc.verification_logs_channel_ID, settings_doc.verification_logs_channel_ID), true)
                             .field("Verification Settings:", format!(
@@ -82,6 +61,7 @@ pub async fn command(
                                 settings_doc.guild_settings.premium_bonus,
                                 settings_doc.guild_settings.zero_point),
                             false)
+                            .field("Command Grants:", grants_field(&settings_doc), false)
                             .footer(|footer| footer.text("Powered by Open/Alt.ID"))
                     })
                 })
@@ -94,21 +74,66 @@ pub async fn command(
     }
 }
 
-#[instrument(skip(ctx))]
-pub async fn register(ctx: &Context) {
-    let result = Command::create_global_application_command(&*ctx.http, |command| {
-        command
-            .name("currentsettings")
-            .description("Gets the current settings of the server.")
-    })
-    .await;
+/// Lists the guild's mod roles for a settings embed.
+pub fn mod_roles_field(guild: &Guild) -> String {
+    let roles = guild.mod_roles();
+    if roles.is_empty() {
+        return "None".to_string();
+    }
+    roles
+        .iter()
+        .map(|role| format!("**{}**\n<@&{}>", role, role))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
 
-    match result {
-        Ok(command) => {
-            info!("Command {:?} registered successfully.", command);
-        }
-        Err(error) => {
-            error!("Could not create guild command! {:?}", error);
-        }
-    };
+fn grants_field(guild: &Guild) -> String {
+    // Revoking leaves the emptied grant behind.
+    if guild.command_grants.values().all(CommandGrant::is_empty) {
+        return "None".to_string();
+    }
+    guild
+        .command_grants
+        .iter()
+        .filter(|(_, grant)| !grant.is_empty())
+        .map(|(command, grant)| {
+            let holders: Vec<String> = grant
+                .role_IDs
+                .iter()
+                .map(|role| format!("<@&{}>", role))
+                .chain(grant.user_IDs.iter().map(|user| format!("<@{}>", user)))
+                .collect();
+            format!("/{}: {}", command, holders.join(", "))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub struct CurrentSettings;
+
+#[async_trait]
+impl SlashCommand for CurrentSettings {
+    fn name(&self) -> &'static str {
+        "currentsettings"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.description("Gets the current settings of the server.")
+    }
+
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Mod]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+    }
 }
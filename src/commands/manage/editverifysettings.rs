@@ -1,38 +1,35 @@
-use mongodb::bson;
 use mongodb::bson::doc;
-use mongodb::Collection;
-use serenity::model::application::command::Command;
 use serenity::model::application::command::CommandOptionType;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
 use serenity::prelude::Context;
-use tracing::{error, info, instrument, warn};
+use tracing::{error, instrument};
 
 use crate::commands::common::interaction_error::{channel_message_error, interaction_error};
-use crate::commands::common::permissions_check::check_if_mod;
-use crate::dbmodels::guild::Guild as GuildStruct;
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::manage::currentsettings::mod_roles_field;
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 
-#[instrument(skip(ctx, mongo_client))]
-pub async fn command(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-) {
-    // Check if the user is a mod.
-    match check_if_mod(ctx, command, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error("You must be a mod to use this command.", command, ctx).await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error(err, command, ctx).await;
-            return;
-        }
+command_options! {
+    struct EditVerifySettingsOptions {
+        zero_point: Option<i64>,
+        difficulty_addition: Option<i64>,
+        mfa_bonus: Option<i64>,
+        premium_bonus: Option<i64>,
+        preferred_num_of_accounts: Option<i64>,
     }
+}
 
-    let command_options = command.data.options.clone();
+#[instrument(skip(ctx, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
+    let options = match parse_options::<EditVerifySettingsOptions>(ctx, command).await {
+        Some(options) => options,
+        None => return,
+    };
     // Extract the Guild ID as a string.
     let guild_id_str = match command.guild_id {
         None => {
@@ -42,68 +39,51 @@ pub async fn command(
         Some(x) => x.0.to_string(),
     };
 
+    // preferred_num_of_accounts is stored as a u8, so check it before touching the database.
+    let preferred_num_of_accounts = match options.preferred_num_of_accounts.map(u8::try_from) {
+        None => None,
+        Some(Ok(num)) => Some(num),
+        Some(Err(_)) => {
+            interaction_error(
+                "'preferred_num_of_accounts' must be between 0 and 255.",
+                command,
+                ctx,
+            )
+            .await;
+            return;
+        }
+    };
+
+    // Only the given settings are written, the rest of the guild is left alone.
     let mut values_to_update = doc! {};
-    let accept_args = [
-        "zero_point",
-        "difficulty_addition",
-        "mfa_bonus",
-        "premium_bonus",
-        "preferred_num_of_accounts",
-    ];
-    for tup in super::super::common::slash_commands::extract_vec(&command_options).await {
-        if accept_args.contains(&tup.0) {
-            if let Some(x) = super::super::common::slash_commands::get_int(tup.1).await {
-                let bson_val = match bson::to_bson(&x) {
-                    Ok(bson_val) => bson_val,
-                    Err(err) => {
-                        error!("Could not convert val ({:?}) to bson. {:?}", x, err);
-                        interaction_error("Could not convert inputs.", command, ctx).await;
-                        return;
-                    }
-                };
-                values_to_update.insert(format!("guild_settings.{}", &tup.0), bson_val);
-            } else {
-                interaction_error("param was invalid.", command, ctx).await;
-                return;
-            }
+    for (name, value) in [
+        ("zero_point", options.zero_point),
+        ("difficulty_addition", options.difficulty_addition),
+        ("mfa_bonus", options.mfa_bonus),
+        ("premium_bonus", options.premium_bonus),
+        (
+            "preferred_num_of_accounts",
+            preferred_num_of_accounts.map(i64::from),
+        ),
+    ] {
+        if let Some(value) = value {
+            values_to_update.insert(format!("guild_settings.{}", name), value);
         }
     }
 
-    let update_statement = doc! {"$set": values_to_update};
-
-    let collection: Collection<GuildStruct> = mongo_client.database("botdb").collection("guilds");
-    let _ = match collection
-        .update_one(doc! {"guild_ID": &guild_id_str}, update_statement, None)
+    let settings_doc = match repos
+        .guilds
+        .set_fields(&guild_id_str, values_to_update)
         .await
     {
-        Ok(res) => res,
-        Err(err) => {
-            error!("{:?}", err);
-            interaction_error("Could not update the database.", command, ctx).await;
+        Ok(Some(doc)) => doc,
+        Ok(None) => {
+            interaction_error("Could not find guild in database.", command, ctx).await;
             return;
         }
-    };
-
-    let collection: Collection<GuildStruct> = mongo_client.database("botdb").collection("guilds");
-    let settings_doc = match collection
-        .find_one(doc! {"guild_ID": guild_id_str}, None)
-        .await
-    {
-        Ok(res) => match res {
-            None => {
-                interaction_error(
-                    "Could not find guild in the database. This really shouldn't ever happen.",
-                    command,
-                    ctx,
-                )
-                .await;
-                return;
-            }
-            Some(doc) => doc,
-        },
         Err(err) => {
-            error!("{:?}", err);
-            interaction_error("Could not get guild from the database.", command, ctx).await;
+            error!("{}", err);
+            interaction_error("Could not update the database.", command, ctx).await;
             return;
         }
     };
@@ -116,7 +96,7 @@ pub async fn command(
                     message.embed(|embed| {
                         embed
                             .title("New Server Settings")
-                            .field("Mod Role:", format!("**{}**\n<@&{}>", settings_doc.mod_role_ID, settings_doc.mod_role_ID), true)
+                            .field("Mod Roles:", mod_roles_field(&settings_doc), true)
                             .field("Verification Age:", format!("\n**{}** days", settings_doc.verification_age), true)
                             .field("Logs Channel:", format!("**{}**\n<#{}>", settings_doc.verification_logs_channel_ID, settings_doc.verification_logs_channel_ID), true)
                             .field("Verification Settings:", format!(
@@ -140,11 +120,19 @@ pub async fn command(
     }
 }
 
-#[instrument(skip(ctx))]
-pub async fn register(ctx: &Context) {
-    let result = Command::create_global_application_command(&*ctx.http, |command| {
+pub struct EditVerifySettings;
+
+#[async_trait]
+impl SlashCommand for EditVerifySettings {
+    fn name(&self) -> &'static str {
+        "editverifysettings"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
         command
-            .name("editverifysettings")
             .description("Set the settings for the verification algorithm.")
             .create_option(|opt| {
                 opt.name("zero_point")
@@ -178,15 +166,18 @@ pub async fn register(ctx: &Context) {
                     .kind(CommandOptionType::Integer)
                     .required(false)
             })
-    })
-    .await;
+    }
 
-    match result {
-        Ok(command) => {
-            info!("Command {:?} registered successfully.", command);
-        }
-        Err(error) => {
-            error!("Could not create guild command! {:?}", error);
-        }
-    };
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Mod]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+    }
 }
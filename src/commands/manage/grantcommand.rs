@@ -0,0 +1,171 @@
+use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::interaction::MessageFlags;
+use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
+use serenity::model::prelude::Role;
+use serenity::model::user::User;
+use serenity::prelude::Context;
+use tracing::debug;
+use tracing::{error, info, instrument};
+
+use crate::application_commands::sync_after_change;
+use crate::commands::common::interaction_error::{channel_message_error, interaction_error};
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::{self, SlashCommand};
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+
+command_options! {
+    struct GrantCommandOptions {
+        command: String,
+        role: Option<Role>,
+        user: Option<User>,
+    }
+}
+
+#[instrument(skip(ctx, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
+    let options = match parse_options::<GrantCommandOptions>(ctx, command).await {
+        Some(options) => options,
+        None => return,
+    };
+    let role = options.role.map(|role| role.id.0.to_string());
+    let user = options.user.map(|user| user.id.0.to_string());
+    if role.is_none() && user.is_none() {
+        interaction_error(
+            "Pick a role or a user to grant the command to.",
+            command,
+            ctx,
+        )
+        .await;
+        return;
+    }
+
+    let guild_id_str = match command.guild_id {
+        None => {
+            interaction_error("This command must be run in a guild.", command, ctx).await;
+            return;
+        }
+        Some(id) => id.0.to_string(),
+    };
+
+    // The command becomes part of a field path, so only known names get this far.
+    if registry::grantable().all(|grantable| grantable.name() != options.command) {
+        interaction_error("That command can't be granted.", command, ctx).await;
+        return;
+    }
+    for (list, id) in grant_lists(&role, &user) {
+        let field = format!("command_grants.{}.{}", options.command, list);
+        match repos.guilds.add_to_list(&guild_id_str, &field, id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                interaction_error("Could not find guild in database.", command, ctx).await;
+                return;
+            }
+            Err(err) => {
+                error!("{}", err);
+                interaction_error("Could not update the database.", command, ctx).await;
+                return;
+            }
+        }
+    }
+
+    let mut holders = vec![];
+    if let Some(role) = &role {
+        holders.push(format!("<@&{}>", role));
+    }
+    if let Some(user) = &user {
+        holders.push(format!("<@{}>", user));
+    }
+    debug!("Creating response...");
+    let res = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message.flags(MessageFlags::EPHEMERAL);
+                    message.content(format!(
+                        "{} can now use /{}.",
+                        holders.join(" and "),
+                        options.command
+                    ))
+                })
+        })
+        .await;
+    if let Err(err) = res {
+        error!("{}", err);
+        channel_message_error("Could not send interaction message.", command, ctx).await;
+    } else {
+        info!("Response created.");
+    }
+}
+
+/// The grant lists to update, and the ID that goes in each.
+pub fn grant_lists<'a>(
+    role: &'a Option<String>,
+    user: &'a Option<String>,
+) -> Vec<(&'static str, &'a str)> {
+    let mut lists = vec![];
+    if let Some(role) = role {
+        lists.push(("role_IDs", role.as_str()));
+    }
+    if let Some(user) = user {
+        lists.push(("user_IDs", user.as_str()));
+    }
+    lists
+}
+
+pub struct GrantCommand;
+
+#[async_trait]
+impl SlashCommand for GrantCommand {
+    fn name(&self) -> &'static str {
+        "grantcommand"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Let a role or user use a mod command. Admin only command.")
+            .create_option(|opt| {
+                opt.name("command")
+                    .description("The command to grant.")
+                    .kind(CommandOptionType::String)
+                    .required(true);
+                for grantable in registry::grantable() {
+                    opt.add_string_choice(grantable.name(), grantable.name());
+                }
+                opt
+            })
+            .create_option(|opt| {
+                opt.name("role")
+                    .description("The role that may use the command.")
+                    .kind(CommandOptionType::Role)
+                    .required(false)
+            })
+            .create_option(|opt| {
+                opt.name("user")
+                    .description("The user that may use the command.")
+                    .kind(CommandOptionType::User)
+                    .required(false)
+            })
+    }
+
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Admin]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+        sync_after_change(ctx, state, interaction).await;
+    }
+}
@@ -1,5 +1,8 @@
 pub mod currentsettings;
 pub mod editverifysettings;
+pub mod grantcommand;
+pub mod removemodrole;
+pub mod revokecommand;
 pub mod setage;
 pub mod setlogchannel;
 pub mod setmodrole;
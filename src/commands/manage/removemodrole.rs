@@ -0,0 +1,126 @@
+use mongodb::bson::doc;
+use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::interaction::MessageFlags;
+use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
+use serenity::model::prelude::Role;
+use serenity::prelude::Context;
+use tracing::debug;
+use tracing::{error, info, instrument};
+
+use crate::application_commands::sync_after_change;
+use crate::commands::common::interaction_error::{channel_message_error, interaction_error};
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+
+command_options! {
+    struct RemoveModRoleOptions {
+        role: Role,
+    }
+}
+
+#[instrument(skip(ctx, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
+    let role = match parse_options::<RemoveModRoleOptions>(ctx, command).await {
+        Some(options) => options.role.id.0.to_string(),
+        None => return,
+    };
+
+    let guild_id_str = match command.guild_id {
+        None => {
+            interaction_error("This command must be run in a guild.", command, ctx).await;
+            return;
+        }
+        Some(id) => id.0.to_string(),
+    };
+
+    let res = repos
+        .guilds
+        .remove_from_list(&guild_id_str, "mod_role_IDs", &role)
+        .await;
+    let guild = match res {
+        Ok(Some(guild)) => guild,
+        Ok(None) => {
+            interaction_error("Could not find guild in database.", command, ctx).await;
+            return;
+        }
+        Err(err) => {
+            error!("{}", err);
+            interaction_error("Could not update the database.", command, ctx).await;
+            return;
+        }
+    };
+    // The role set by older versions is only ever cleared, so this can't undo another change.
+    if guild.mod_role_ID == role {
+        let res = repos
+            .guilds
+            .set_fields(&guild_id_str, doc! {"mod_role_ID": "0"})
+            .await;
+        if let Err(err) = res {
+            error!("{}", err);
+            interaction_error("Could not update the database.", command, ctx).await;
+            return;
+        }
+    }
+    debug!("Creating response...");
+    let res = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message.flags(MessageFlags::EPHEMERAL);
+                    message.content(format!(
+                        "<@&{}> ID: {} is no longer a mod role.",
+                        &role, &role
+                    ))
+                })
+        })
+        .await;
+    if let Err(err) = res {
+        error!("{}", err);
+        channel_message_error("Could not send interaction message.", command, ctx).await;
+    } else {
+        info!("Response created.");
+    }
+}
+
+pub struct RemoveModRole;
+
+#[async_trait]
+impl SlashCommand for RemoveModRole {
+    fn name(&self) -> &'static str {
+        "removemodrole"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Stop a role from acting as mod. Admin only command.")
+            .create_option(|opt| {
+                opt.name("role")
+                    .description("The role you want to remove.")
+                    .kind(CommandOptionType::Role)
+                    .required(true)
+            })
+    }
+
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Admin]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+        sync_after_change(ctx, state, interaction).await;
+    }
+}
@@ -1,72 +1,40 @@
-use mongodb::bson;
 use mongodb::bson::doc;
-use mongodb::bson::Bson;
-use mongodb::Collection;
-use serenity::model::application::command::Command;
 use serenity::model::application::command::CommandOptionType;
 use serenity::model::application::interaction::MessageFlags;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
 use serenity::prelude::Context;
-use tracing::{error, info, instrument, warn};
+use tracing::{error, instrument};
 
 use crate::commands::common::interaction_error::{channel_message_error, interaction_error};
-use crate::commands::common::permissions_check::check_if_mod;
-use crate::commands::common::slash_commands::extract_vec;
-use crate::commands::common::slash_commands::get_int;
-use crate::dbmodels::guild::Guild as GuildStruct;
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 
-#[instrument(skip(ctx, mongo_client))]
-pub async fn command(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-) {
-    // Check if the user is a mod.
-    match check_if_mod(ctx, command, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error("You must be a mod to use this command.", command, ctx).await;
-                return;
-            }
-        }
+command_options! {
+    struct SetAgeOptions {
+        age: i64,
+    }
+}
+
+#[instrument(skip(ctx, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
+    let age = match parse_options::<SetAgeOptions>(ctx, command).await {
+        Some(options) => options.age,
+        None => return,
+    };
+    // Negative ages make no sense.
+    let num_days = match u64::try_from(age) {
+        Ok(num_days) => num_days,
         Err(err) => {
-            warn!("{}", err);
-            interaction_error(err, command, ctx).await;
+            error!("{:?}", err);
+            interaction_error("The age can not be negative.", command, ctx).await;
             return;
         }
-    }
-    let command_options = command.data.options.clone();
-    let mut num_days_bson: Bson = bson::Bson::Boolean(false);
-    for tup in extract_vec(&command_options).await {
-        match tup.0 {
-            "age" => {
-                // Extract an int from the options and convert it into bson.
-                if let Some(num_days) = get_int(tup.1).await {
-                    num_days_bson = match bson::to_bson(&num_days) {
-                        Ok(bson_data) => bson_data,
-                        Err(err) => {
-                            error!("{:?}", err);
-                            interaction_error("Could not convert input properly.", command, ctx)
-                                .await;
-                            return;
-                        }
-                    }
-                } else {
-                    interaction_error(
-                        "'num_days' param was invalid, make sure you gave an integer (no decimal).",
-                        command,
-                        ctx,
-                    )
-                    .await;
-                    return;
-                }
-            }
-            _ => {
-                warn!("Unrecognized parameter given.");
-                return;
-            }
-        }
-    }
+    };
 
     // Extract the Guild ID as a string.
     let guild_id_str = match command.guild_id {
@@ -77,22 +45,22 @@ pub async fn command(
         Some(x) => x.0.to_string(),
     };
 
-    let collection: Collection<GuildStruct> = mongo_client.database("botdb").collection("guilds");
-    let _ = match collection
-        .update_one(
-            doc! {"guild_ID": guild_id_str},
-            doc! {"$set": {"verification_age": &num_days_bson}},
-            None,
-        )
-        .await
-    {
-        Ok(res) => res,
+    let res = repos
+        .guilds
+        .set_fields(&guild_id_str, doc! {"verification_age": age})
+        .await;
+    match res {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            interaction_error("Could not find guild in database.", command, ctx).await;
+            return;
+        }
         Err(err) => {
-            error!("{:?}", err);
+            error!("{}", err);
             interaction_error("Could not update the database.", command, ctx).await;
             return;
         }
-    };
+    }
 
     let res = command
         .create_interaction_response(&ctx.http, |response| {
@@ -102,7 +70,7 @@ pub async fn command(
                     message.flags(MessageFlags::EPHEMERAL);
                     message.content(format!(
                         "The minimum age to bypass verification is now set to: {} days",
-                        num_days_bson
+                        num_days
                     ))
                 })
         })
@@ -114,11 +82,19 @@ pub async fn command(
     }
 }
 
-#[instrument(skip(ctx))]
-pub async fn register(ctx: &Context) {
-    let result = Command::create_global_application_command(&*ctx.http, |command| {
+pub struct SetMinAge;
+
+#[async_trait]
+impl SlashCommand for SetMinAge {
+    fn name(&self) -> &'static str {
+        "setminage"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
         command
-            .name("setminage")
             .description("Set the minimum age to avoid verification")
             .create_option(|opt| {
                 opt.name("age")
@@ -126,15 +102,18 @@ pub async fn register(ctx: &Context) {
                     .kind(CommandOptionType::Integer)
                     .required(true)
             })
-    })
-    .await;
+    }
 
-    match result {
-        Ok(command) => {
-            info!("Command {:?} registered successfully.", command);
-        }
-        Err(error) => {
-            error!("Could not create guild command! {:?}", error);
-        }
-    };
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Mod]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+    }
 }
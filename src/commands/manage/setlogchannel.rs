@@ -1,50 +1,31 @@
 use crate::commands::common::interaction_error::interaction_error;
-use crate::commands::common::permissions_check::check_if_mod;
-use crate::dbmodels::guild::Guild as GuildStruct;
-use mongodb::bson;
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::repository::Repositories;
+use crate::state::AppState;
 use mongodb::bson::doc;
-use mongodb::Collection;
-use serenity::model::application::command::Command;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 use serenity::model::application::command::CommandOptionType;
 use serenity::model::application::interaction::MessageFlags;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
+use serenity::model::prelude::PartialChannel;
 use serenity::prelude::Context;
-use tracing::debug;
-use tracing::{error, info, instrument, warn};
+use tracing::{error, info, instrument};
 
-#[instrument(skip(ctx, mongo_client))]
-pub async fn command(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-) {
-    // Check if mod already.
-    match check_if_mod(ctx, command, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error("You must be a mod to use this command.", command, ctx).await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error(err, command, ctx).await;
-            return;
-        }
+command_options! {
+    struct SetLogChannelOptions {
+        channel: PartialChannel,
     }
+}
 
-    let options = command.data.options.clone();
-    let mut channel_id_string: String = "".to_string();
-    for tup in super::super::common::slash_commands::extract_vec(&options).await {
-        if tup.0 == "channel" {
-            if let Some(x) = super::super::common::slash_commands::get_channel(tup.1).await {
-                channel_id_string = x.id.0.to_string();
-            } else {
-                interaction_error("'channel' param was invalid.", command, ctx).await;
-                return;
-            }
-        }
-    }
+#[instrument(skip(ctx, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
+    let channel_id_string = match parse_options::<SetLogChannelOptions>(ctx, command).await {
+        Some(options) => options.channel.id.0.to_string(),
+        None => return,
+    };
 
     let guild_id_str = match command.guild_id {
         None => {
@@ -54,32 +35,25 @@ pub async fn command(
         Some(x) => x.0.to_string(),
     };
 
-    let channel_bson = match bson::to_bson(&channel_id_string) {
-        Ok(bson) => bson,
-        Err(err) => {
-            error!("{}", err);
-            interaction_error("Could not convert role ID to bson.", command, ctx).await;
+    let res = repos
+        .guilds
+        .set_fields(
+            &guild_id_str,
+            doc! {"verification_logs_channel_ID": &channel_id_string},
+        )
+        .await;
+    match res {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            interaction_error("Could not find guild in database.", command, ctx).await;
             return;
         }
-    };
-
-    let collection: Collection<GuildStruct> = mongo_client.database("botdb").collection("guilds");
-    let update_res = match collection
-        .update_one(
-            doc! {"guild_ID": guild_id_str},
-            doc! {"$set": {"verification_logs_channel_ID": &channel_bson}},
-            None,
-        )
-        .await
-    {
-        Ok(res) => res,
         Err(err) => {
-            error!("{:?}", err);
+            error!("{}", err);
             interaction_error("Could not update the database.", command, ctx).await;
             return;
         }
-    };
-    debug!("{:?}", update_res);
+    }
 
     info!("Creating response...");
     let res = command
@@ -108,11 +82,19 @@ pub async fn command(
     }
 }
 
-#[instrument(skip(ctx))]
-pub async fn register(ctx: &Context) {
-    let result = Command::create_global_application_command(&*ctx.http, |command| {
+pub struct SetLogChannel;
+
+#[async_trait]
+impl SlashCommand for SetLogChannel {
+    fn name(&self) -> &'static str {
+        "setlogchannel"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
         command
-            .name("setlogchannel")
             .description(
                 "Set which channel to send logs to, omit channel to disable. Mod only command.",
             )
@@ -122,15 +104,18 @@ pub async fn register(ctx: &Context) {
                     .kind(CommandOptionType::Channel)
                     .required(true)
             })
-    })
-    .await;
+    }
 
-    match result {
-        Ok(command) => {
-            info!("Command {:?} registered successfully.", command);
-        }
-        Err(error) => {
-            error!("Could not create guild command! {:?}", error);
-        }
-    };
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Mod]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+    }
 }
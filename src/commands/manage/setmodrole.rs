@@ -1,61 +1,32 @@
-use mongodb::bson;
-use mongodb::bson::doc;
-use mongodb::Collection;
-use serenity::model::application::command::Command;
 use serenity::model::application::command::CommandOptionType;
 use serenity::model::application::interaction::MessageFlags;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
 use serenity::model::prelude::Role;
 use serenity::prelude::Context;
 use tracing::debug;
-use tracing::{error, info, instrument, warn};
+use tracing::{error, info, instrument};
 
+use crate::application_commands::sync_after_change;
 use crate::commands::common::interaction_error::{channel_message_error, interaction_error};
-use crate::commands::common::permissions_check::check_if_mod;
-use crate::commands::common::slash_commands::extract_vec;
-use crate::dbmodels::guild::Guild as GuildStruct;
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 
-#[instrument(skip(ctx, mongo_client))]
-pub async fn command(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-) {
-    // Check if mod already.
-    match check_if_mod(ctx, command, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error("You must be a mod to use this command.", command, ctx).await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error(err, command, ctx).await;
-            return;
-        }
-    }
-
-    let options = command.data.options.clone();
-    let mut role_opt: Option<Role> = None;
-    for tup in extract_vec(&options).await {
-        if tup.0 == "role" {
-            if let Some(x) = super::super::common::slash_commands::get_role(tup.1).await {
-                role_opt = Some(x)
-            } else {
-                interaction_error("'role' param was invalid.", command, ctx).await;
-                return;
-            }
-        }
+command_options! {
+    struct SetModRoleOptions {
+        role: Role,
     }
+}
 
-    // Check to make sure its there!
-    let role = match role_opt {
-        None => {
-            interaction_error("No role provided.", command, ctx).await;
-            return;
-        }
-        Some(role) => role.id.0.to_string(),
+#[instrument(skip(ctx, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
+    let role = match parse_options::<SetModRoleOptions>(ctx, command).await {
+        Some(options) => options.role.id.0.to_string(),
+        None => return,
     };
 
     let guild_id_str = match command.guild_id {
@@ -66,32 +37,22 @@ pub async fn command(
         Some(id) => id.0.to_string(),
     };
 
-    let role_bson = match bson::to_bson(&role) {
-        Ok(bson) => bson,
-        Err(err) => {
-            error!("{}", err);
-            interaction_error("Could not convert role ID to bson.", command, ctx).await;
+    let res = repos
+        .guilds
+        .add_to_list(&guild_id_str, "mod_role_IDs", &role)
+        .await;
+    match res {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            interaction_error("Could not find guild in database.", command, ctx).await;
             return;
         }
-    };
-
-    let collection: Collection<GuildStruct> = mongo_client.database("botdb").collection("guilds");
-    let update_res = match collection
-        .update_one(
-            doc! {"guild_ID": guild_id_str},
-            doc! {"$set": {"mod_role_ID": &role_bson}},
-            None,
-        )
-        .await
-    {
-        Ok(res) => res,
         Err(err) => {
-            error!("{:?}", err);
+            error!("{}", err);
             interaction_error("Could not update the database.", command, ctx).await;
             return;
         }
-    };
-    debug!("{:?}", update_res);
+    }
     debug!("Creating response...");
     let res = command
         .create_interaction_response(&ctx.http, |response| {
@@ -99,10 +60,7 @@ pub async fn command(
                 .kind(InteractionResponseType::ChannelMessageWithSource)
                 .interaction_response_data(|message| {
                     message.flags(MessageFlags::EPHEMERAL);
-                    message.content(format!(
-                        "The mod role is now set to <@&{}> ID: {}",
-                        &role, &role
-                    ))
+                    message.content(format!("<@&{}> ID: {} is now a mod role.", &role, &role))
                 })
         })
         .await;
@@ -114,27 +72,40 @@ pub async fn command(
     }
 }
 
-#[instrument(skip(ctx))]
-pub async fn register(ctx: &Context) {
-    let result = Command::create_global_application_command(&*ctx.http, |command| {
+pub struct SetModRole;
+
+#[async_trait]
+impl SlashCommand for SetModRole {
+    fn name(&self) -> &'static str {
+        "setmodrole"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
         command
-            .name("setmodrole")
-            .description("Add an additional role to be able to act as mod. Mod only command.")
+            .description("Add an additional role to be able to act as mod. Admin only command.")
             .create_option(|opt| {
                 opt.name("role")
-                    .description("The role you want to set.")
+                    .description("The role you want to add.")
                     .kind(CommandOptionType::Role)
                     .required(true)
             })
-    })
-    .await;
+    }
 
-    match result {
-        Ok(command) => {
-            info!("Command {:?} registered successfully.", command);
-        }
-        Err(error) => {
-            error!("Could not create guild command! {:?}", error);
-        }
-    };
+    fn guards(&self) -> &'static [Guard] {
+        // Mods could otherwise hand the mod role to anyone.
+        &[Guard::GuildOnly, Guard::Admin]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+        sync_after_change(ctx, state, interaction).await;
+    }
 }
@@ -1,68 +1,31 @@
-use mongodb::bson;
 use mongodb::bson::doc;
-use mongodb::Collection;
-use serenity::model::application::command::Command;
 use serenity::model::application::command::CommandOptionType;
 use serenity::model::application::interaction::MessageFlags;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
 use serenity::model::prelude::Role;
 use serenity::prelude::Context;
-use tracing::{error, info, instrument, warn};
+use tracing::{error, info, instrument};
 
 use crate::commands::common::interaction_error::interaction_error;
-use crate::commands::common::permissions_check::check_if_mod;
-use crate::dbmodels::guild::Guild as GuildStruct;
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 
-#[instrument(skip(ctx, mongo_client))]
-pub async fn command(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-) {
-    // Check if mod already.
-    match check_if_mod(ctx, command, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error("You must be a mod to use this command.", command, ctx).await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error(err, command, ctx).await;
-            return;
-        }
-    }
-
-    let options = command.data.options.clone();
-    let mut role_opt: Option<Role> = None;
-    for tup in super::super::common::slash_commands::extract_vec(&options).await {
-        if tup.0 == "role" {
-            if let Some(x) = super::super::common::slash_commands::get_role(tup.1).await {
-                role_opt = Some(x)
-            } else {
-                super::super::common::interaction_error::interaction_error(
-                    "'role' param was invalid.",
-                    command,
-                    ctx,
-                )
-                .await;
-                return;
-            }
-        }
+command_options! {
+    struct SetVerifiedRoleOptions {
+        role: Role,
     }
+}
 
-    let role = match role_opt {
-        None => {
-            super::super::common::interaction_error::interaction_error(
-                "No role provided.",
-                command,
-                ctx,
-            )
-            .await;
-            return;
-        }
-        Some(role) => role.id.0.to_string(),
+#[instrument(skip(ctx, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
+    let role = match parse_options::<SetVerifiedRoleOptions>(ctx, command).await {
+        Some(options) => options.role.id.0.to_string(),
+        None => return,
     };
 
     let guild_id_str = match command.guild_id {
@@ -73,32 +36,22 @@ pub async fn command(
         Some(id) => id.0.to_string(),
     };
 
-    // TODO set the role in the database
-    let role_bson = match bson::to_bson(&role) {
-        Ok(bson) => bson,
-        Err(err) => {
-            error!("{}", err);
-            interaction_error("Could not convert role ID to bson.", command, ctx).await;
+    let res = repos
+        .guilds
+        .set_fields(&guild_id_str, doc! {"verification_role_ID": &role})
+        .await;
+    match res {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            interaction_error("Could not find guild in database.", command, ctx).await;
             return;
         }
-    };
-
-    let collection: Collection<GuildStruct> = mongo_client.database("botdb").collection("guilds");
-    let _ = match collection
-        .update_one(
-            doc! {"guild_ID": guild_id_str},
-            doc! {"$set": {"verification_role_ID": &role_bson}},
-            None,
-        )
-        .await
-    {
-        Ok(res) => res,
         Err(err) => {
-            error!("{:?}", err);
+            error!("{}", err);
             interaction_error("Could not update the database.", command, ctx).await;
             return;
         }
-    };
+    }
 
     info!("Creating response...");
     let res = command
@@ -123,11 +76,20 @@ pub async fn command(
         info!("Response created.");
     }
 }
-#[instrument(skip(ctx))]
-pub async fn register(ctx: &Context) {
-    let result = Command::create_global_application_command(&*ctx.http, |command| {
+
+pub struct SetVerifiedRole;
+
+#[async_trait]
+impl SlashCommand for SetVerifiedRole {
+    fn name(&self) -> &'static str {
+        "setverifiedrole"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
         command
-            .name("setverifiedrole")
             .description(
                 "Set the verification role, this role is given when verified. Mod only command.",
             )
@@ -137,15 +99,18 @@ pub async fn register(ctx: &Context) {
                     .kind(CommandOptionType::Role)
                     .required(true)
             })
-    })
-    .await;
+    }
 
-    match result {
-        Ok(command) => {
-            info!("Command {:?} registered successfully.", command);
-        }
-        Err(error) => {
-            error!("Could not create guild command! {:?}", error);
-        }
-    };
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Mod]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+    }
 }
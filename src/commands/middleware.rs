@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serenity::builder::CreateApplicationCommand;
+use serenity::futures::FutureExt;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::prelude::{GuildId, Member, Permissions, UserId};
+use serenity::model::user::User;
+use serenity::prelude::Context;
+use tracing::*;
+
+use crate::commands::common::interaction_error::{interaction_error, interaction_error_comp};
+use crate::commands::common::permissions_check::check_if_mod;
+use crate::commands::registry::SlashCommand;
+use crate::metrics::metrics;
+use crate::state::AppState;
+
+/// A check that has to pass before a command, or a component on one of its messages, is
+/// handled. Commands list theirs in `SlashCommand::guards`.
+#[derive(Clone, Copy, Debug)]
+pub enum Guard {
+    GuildOnly,
+    /// Server admins, members with one of the guild's mod roles and the roles and users the
+    /// guild granted the command to.
+    Mod,
+    /// Members with the administrator permission.
+    Admin,
+    /// Only the owner of the server.
+    #[allow(dead_code)]
+    Owner,
+    /// How long a user has to wait between two uses of the command. Components are not limited.
+    Cooldown(Duration),
+}
+
+impl Guard {
+    fn label(&self) -> &'static str {
+        match self {
+            Guard::GuildOnly => "guild_only",
+            Guard::Mod => "mod",
+            Guard::Admin => "admin",
+            Guard::Owner => "owner",
+            Guard::Cooldown(_) => "cooldown",
+        }
+    }
+}
+
+/// Hides the command from members its guards would reject anyway. Discord can't know about mod
+/// roles, so mod commands stay visible here and are only hidden per guild, by the overrides
+/// `sync_command_permissions` sets once the guild's mod roles and grants are synced. Server
+/// admins can change what is shown, the guards are checked either way.
+pub fn apply_permissions<'a>(
+    guards: &[Guard],
+    command: &'a mut CreateApplicationCommand,
+) -> &'a mut CreateApplicationCommand {
+    let mut required = Permissions::empty();
+    for guard in guards {
+        match guard {
+            Guard::GuildOnly => {
+                command.dm_permission(false);
+            }
+            Guard::Mod => {}
+            // The owner has every permission, this hides the command from everyone else but admins.
+            Guard::Admin | Guard::Owner => required |= Permissions::ADMINISTRATOR,
+            Guard::Cooldown(_) => {}
+        }
+    }
+    if !required.is_empty() {
+        command.default_member_permissions(required);
+    }
+    command
+}
+
+/// The owner of the guild, from the cache or else from Discord.
+async fn guild_owner(ctx: &Context, guild_id: GuildId) -> Result<UserId, String> {
+    if let Some(owner_id) = ctx.cache.guild_field(guild_id, |guild| guild.owner_id) {
+        return Ok(owner_id);
+    }
+    match guild_id.to_partial_guild(&ctx.http).await {
+        Ok(guild) => Ok(guild.owner_id),
+        Err(err) => {
+            metrics().dependency_error("discord");
+            error!("Could not look up the owner of {} - {}", guild_id, err);
+            Err("Could not check who owns this server.".to_string())
+        }
+    }
+}
+
+fn check_owner(owner_id: UserId, user_id: UserId) -> Result<(), String> {
+    if owner_id == user_id {
+        Ok(())
+    } else {
+        Err("Only the server owner can use this command.".to_string())
+    }
+}
+
+/// The interaction a command is handling.
+#[derive(Clone, Copy)]
+pub enum Invocation<'a> {
+    Command(&'a ApplicationCommandInteraction),
+    Component(&'a MessageComponentInteraction),
+}
+
+impl Invocation<'_> {
+    fn guild_id(&self) -> Option<GuildId> {
+        match self {
+            Invocation::Command(command) => command.guild_id,
+            Invocation::Component(component) => component.guild_id,
+        }
+    }
+
+    fn member(&self) -> Option<&Member> {
+        match self {
+            Invocation::Command(command) => command.member.as_ref(),
+            Invocation::Component(component) => component.member.as_ref(),
+        }
+    }
+
+    fn user(&self) -> &User {
+        match self {
+            Invocation::Command(command) => &command.user,
+            Invocation::Component(component) => &component.user,
+        }
+    }
+
+    async fn reply_error(&self, ctx: &Context, message: &str) {
+        match self {
+            Invocation::Command(command) => interaction_error(message, command, ctx).await,
+            Invocation::Component(component) => {
+                interaction_error_comp(message, component, ctx).await
+            }
+        }
+    }
+}
+
+/// When each user last used each command.
+#[derive(Default)]
+pub struct Cooldowns {
+    last_used: Mutex<HashMap<(&'static str, u64), Instant>>,
+}
+
+impl Cooldowns {
+    /// Records a use, or returns how long is left if the user used the command too recently.
+    fn try_use(&self, command: &'static str, user_id: u64, cooldown: Duration) -> Result<(), u64> {
+        let mut last_used = match self.last_used.lock() {
+            Ok(last_used) => last_used,
+            // A poisoned lock only loses cooldowns, it shouldn't stop the command.
+            Err(_) => return Ok(()),
+        };
+        let now = Instant::now();
+        // Drops expired entries now and then so the map doesn't grow forever.
+        if last_used.len() > 10_000 {
+            last_used.retain(|_, used| now.duration_since(*used) < Duration::from_secs(60 * 60));
+        }
+        if let Some(used) = last_used.get(&(command, user_id)) {
+            let elapsed = now.duration_since(*used);
+            if elapsed < cooldown {
+                return Err((cooldown - elapsed).as_secs() + 1);
+            }
+        }
+        last_used.insert((command, user_id), now);
+        Ok(())
+    }
+}
+
+/// Runs the command's guards and then `handler`. Every use is logged and timed, and a panic in
+/// the handler is answered with an error instead of leaving the interaction hanging.
+pub async fn dispatch(
+    ctx: &Context,
+    command: &dyn SlashCommand,
+    invocation: Invocation<'_>,
+    state: &AppState,
+    handler: impl Future<Output = ()> + Send,
+) {
+    let name = command.name();
+    let user = invocation.user();
+    info!(
+        "{} '{}' invoked by user '{}'({}) in Gld.{}",
+        match invocation {
+            Invocation::Command(_) => "Application command",
+            Invocation::Component(_) => "Component of",
+        },
+        name,
+        user.name,
+        user.id,
+        invocation.guild_id().unwrap_or(GuildId(0))
+    );
+    if let Invocation::Command(_) = invocation {
+        metrics().commands.with_label_values(&[name]).inc();
+    }
+
+    for guard in command.guards() {
+        if let Err(reason) = check(ctx, guard, name, invocation, state).await {
+            debug!("{} was stopped by the {} guard", name, guard.label());
+            metrics()
+                .command_rejections
+                .with_label_values(&[name, guard.label()])
+                .inc();
+            invocation.reply_error(ctx, &reason).await;
+            return;
+        }
+    }
+
+    let timer = metrics()
+        .command_seconds
+        .with_label_values(&[name])
+        .start_timer();
+    let res = AssertUnwindSafe(handler).catch_unwind().await;
+    timer.observe_duration();
+    if res.is_err() {
+        error!("The {} command panicked.", name);
+        metrics().command_errors.with_label_values(&[name]).inc();
+        invocation
+            .reply_error(ctx, "An unexpected error occurred.")
+            .await;
+    }
+}
+
+async fn check(
+    ctx: &Context,
+    guard: &Guard,
+    name: &'static str,
+    invocation: Invocation<'_>,
+    state: &AppState,
+) -> Result<(), String> {
+    match guard {
+        Guard::GuildOnly => match invocation.guild_id() {
+            Some(_) => Ok(()),
+            None => Err("This command must be run in a guild.".to_string()),
+        },
+        Guard::Mod => {
+            let res = check_if_mod(
+                invocation.member(),
+                invocation.guild_id(),
+                name,
+                &state.repos,
+            )
+            .await;
+            match res {
+                Ok(true) => Ok(()),
+                Ok(false) => Err("You don't have permission to use this command.".to_string()),
+                Err(err) => {
+                    warn!("{}", err);
+                    Err(err.to_string())
+                }
+            }
+        }
+        Guard::Admin => {
+            let is_admin = invocation
+                .member()
+                .and_then(|member| member.permissions)
+                .map(|perms| perms.administrator())
+                .unwrap_or(false);
+            if is_admin {
+                Ok(())
+            } else {
+                Err("You must be an admin to use this command.".to_string())
+            }
+        }
+        Guard::Owner => {
+            let guild_id = match invocation.guild_id() {
+                Some(guild_id) => guild_id,
+                None => return Err("This command must be run in a guild.".to_string()),
+            };
+            check_owner(guild_owner(ctx, guild_id).await?, invocation.user().id)
+        }
+        Guard::Cooldown(cooldown) => {
+            if let Invocation::Component(_) = invocation {
+                return Ok(());
+            }
+            match state
+                .cooldowns
+                .try_use(name, invocation.user().id.0, *cooldown)
+            {
+                Ok(()) => Ok(()),
+                Err(left) => Err(format!(
+                    "You are using this command too often, try again in {} seconds.",
+                    left
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn required_permissions(guards: &[Guard]) -> Option<serde_json::Value> {
+        let mut command = CreateApplicationCommand::default();
+        apply_permissions(guards, &mut command);
+        command.0.get("default_member_permissions").cloned()
+    }
+
+    #[test]
+    fn owner_guard_only_lets_the_owner_through() {
+        assert!(check_owner(UserId(1), UserId(1)).is_ok());
+        assert_eq!(
+            check_owner(UserId(1), UserId(2)).unwrap_err(),
+            "Only the server owner can use this command."
+        );
+    }
+
+    #[test]
+    fn owner_and_admin_commands_are_hidden_from_members() {
+        let admin = Some(serde_json::json!(Permissions::ADMINISTRATOR
+            .bits()
+            .to_string()));
+        assert_eq!(required_permissions(&[Guard::Owner]), admin);
+        assert_eq!(
+            required_permissions(&[Guard::GuildOnly, Guard::Admin]),
+            admin
+        );
+        // Mod commands are hidden per guild by the synced overrides instead.
+        assert_eq!(required_permissions(&[Guard::GuildOnly, Guard::Mod]), None);
+    }
+
+    #[test]
+    fn cooldowns_are_per_user_and_command() {
+        let cooldowns = Cooldowns::default();
+        let cooldown = Duration::from_secs(60);
+        assert!(cooldowns.try_use("a", 1, cooldown).is_ok());
+        assert_eq!(cooldowns.try_use("a", 1, cooldown), Err(60));
+        assert!(cooldowns.try_use("a", 2, cooldown).is_ok());
+        assert!(cooldowns.try_use("b", 1, cooldown).is_ok());
+    }
+}
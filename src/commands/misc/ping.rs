@@ -1,11 +1,16 @@
 use serenity::framework::standard::macros::command;
 use serenity::framework::standard::CommandResult;
-use serenity::model::application::command::Command;
 use serenity::model::application::interaction::MessageFlags;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
 use serenity::model::prelude::Message;
 use serenity::prelude::Context;
-use tracing::{error, info};
+use tracing::info;
+
+use crate::commands::registry::SlashCommand;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 
 #[command]
 pub async fn ping_msg(ctx: &Context, msg: &Message) -> CommandResult {
@@ -14,11 +19,7 @@ pub async fn ping_msg(ctx: &Context, msg: &Message) -> CommandResult {
 }
 
 #[allow(unused)]
-pub async fn command(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-) {
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
     info!("Creating response...");
     let _res = command
         .create_interaction_response(&ctx.http, |response| {
@@ -32,14 +33,28 @@ pub async fn command(
         .await;
     info!("Response created.");
 }
-#[allow(dead_code)]
-pub async fn register(ctx: &Context) {
-    if let Err(err) = Command::create_global_application_command(&*ctx.http, |command| {
-        command.name("pingus").description("An amazing command")
-    })
-    .await
-    {
-        error!("Could not register pingus command! {}", err.to_string());
-        panic!()
+
+pub struct Ping;
+
+#[async_trait]
+impl SlashCommand for Ping {
+    fn name(&self) -> &'static str {
+        "pingus"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.description("An amazing command")
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
     }
 }
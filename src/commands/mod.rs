@@ -1,5 +1,8 @@
 pub mod common;
 pub mod manage;
+pub mod middleware;
 pub mod misc;
 pub mod music;
+pub mod privacy;
+pub mod registry;
 pub mod verification;
@@ -0,0 +1,198 @@
+use mongodb::bson::DateTime;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::MessageFlags;
+use serenity::model::prelude::component::ButtonStyle;
+use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
+use serenity::prelude::Context;
+use tracing::{debug, error, info, instrument, warn};
+
+use super::user_data::delete_user_data;
+use crate::commands::common::interaction_error::{channel_message_error, interaction_error_comp};
+use crate::commands::registry::SlashCommand;
+use crate::dbmodels::audit::PrivacyAuditRecord;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+
+const CONFIRM_BUTTON: &str = "ForgetMeConfirm";
+const CANCEL_BUTTON: &str = "ForgetMeCancel";
+
+#[instrument(skip(ctx))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction) {
+    info!("Creating response...");
+    let res = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message.flags(MessageFlags::EPHEMERAL);
+                    message
+                        .embed(|embed| {
+                            embed
+                                .title("Delete all of your data?")
+                                .description("This removes your connected accounts, your verification history and any pending verifications. This cannot be undone, and you may need to verify again in the future.")
+                                .footer(|footer| footer.text("Powered by Open/Alt.ID"))
+                        })
+                        .components(|components| {
+                            components.create_action_row(|row| {
+                                row.create_button(|button| {
+                                    button
+                                        .style(ButtonStyle::Danger)
+                                        .label("Delete my data")
+                                        .custom_id(format!("{}:{}", CONFIRM_BUTTON, command.user.id.0))
+                                });
+                                row.create_button(|button| {
+                                    button
+                                        .style(ButtonStyle::Secondary)
+                                        .label("Cancel")
+                                        .custom_id(CANCEL_BUTTON)
+                                })
+                            })
+                        })
+                })
+        })
+        .await;
+    if let Err(err) = res {
+        error!("{}", err);
+        channel_message_error("Could not send interaction message.", command, ctx).await;
+    } else {
+        info!("Response created.");
+    }
+}
+
+pub async fn confirm_callback(
+    ctx: &Context,
+    interaction: &MessageComponentInteraction,
+    repos: &Repositories,
+    redis_conn: &mut redis::aio::ConnectionManager,
+) {
+    let ids_split: Vec<&str> = interaction.data.custom_id.split(':').collect();
+    let user_id = match ids_split.get(1).and_then(|id| id.parse::<u64>().ok()) {
+        Some(id) => id,
+        None => {
+            error!("Invalid interaction data in ForgetMe callback.");
+            interaction_error_comp("Invalid button data.", interaction, ctx).await;
+            return;
+        }
+    };
+
+    // Only the user that asked can confirm the deletion of their own data.
+    if user_id != interaction.user.id.0 {
+        interaction_error_comp("This button is not for you.", interaction, ctx).await;
+        return;
+    }
+
+    let counts = match delete_user_data(repos, redis_conn, user_id).await {
+        Ok(counts) => counts,
+        Err(err) => {
+            error!("{}", err);
+            interaction_error_comp("Could not delete your data.", interaction, ctx).await;
+            return;
+        }
+    };
+    debug!("{:?}", counts);
+
+    if let Err(err) = repos
+        .audit
+        .record(PrivacyAuditRecord {
+            action: "forgetme".to_string(),
+            guild_ID: interaction.guild_id.map(|id| id.0.to_string()),
+            accounts_deleted: counts.accounts,
+            attempts_deleted: counts.attempts,
+            redis_keys_deleted: counts.redis_keys,
+            timestamp: DateTime::now(),
+        })
+        .await
+    {
+        error!("{}", err);
+    }
+    info!(
+        "Privacy deletion completed. Accounts: {} Attempts: {} Redis keys: {}",
+        counts.accounts, counts.attempts, counts.redis_keys
+    );
+
+    let _res = interaction
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|message| {
+                    message
+                        .embed(|embed| {
+                            embed
+                                .title("Your data has been deleted")
+                                .field("Connected accounts", counts.accounts, true)
+                                .field("Verification history", counts.attempts, true)
+                                .field("Pending verifications", counts.redis_keys, true)
+                                .footer(|footer| footer.text("Powered by Open/Alt.ID"))
+                        })
+                        .components(|components| components)
+                })
+        })
+        .await;
+}
+
+pub async fn cancel_callback(ctx: &Context, interaction: &MessageComponentInteraction) {
+    let _res = interaction
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|message| {
+                    message
+                        .embed(|embed| {
+                            embed
+                                .title("No changes made")
+                                .description("Your data has not been deleted.")
+                                .footer(|footer| footer.text("Powered by Open/Alt.ID"))
+                        })
+                        .components(|components| components)
+                })
+        })
+        .await;
+}
+
+pub struct ForgetMe;
+
+#[async_trait]
+impl SlashCommand for ForgetMe {
+    fn name(&self) -> &'static str {
+        "forgetme"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.description("Deletes all of the data the bot has stored about you.")
+    }
+
+    fn components(&self) -> &'static [&'static str] {
+        &[CONFIRM_BUTTON, CANCEL_BUTTON]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        _state: &AppState,
+    ) {
+        command(ctx, interaction).await;
+    }
+
+    async fn component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+        kind: &str,
+        state: &AppState,
+    ) {
+        match kind {
+            CONFIRM_BUTTON => {
+                let mut conn = state.redis.clone();
+                confirm_callback(ctx, component, &state.repos, &mut conn).await
+            }
+            CANCEL_BUTTON => cancel_callback(ctx, component).await,
+            _ => warn!("{} has no handler for component {}.", self.name(), kind),
+        }
+    }
+}
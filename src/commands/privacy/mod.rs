@@ -0,0 +1,3 @@
+pub mod forgetme;
+pub mod mydata;
+mod user_data;
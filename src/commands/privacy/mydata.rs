@@ -0,0 +1,122 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use serenity::model::application::interaction::MessageFlags;
+use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
+use serenity::model::prelude::AttachmentType;
+use serenity::prelude::Context;
+use tracing::{error, info, instrument};
+
+use super::user_data::export_user_data;
+use crate::commands::common::interaction_error::{channel_message_error, interaction_error};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+
+#[instrument(skip(ctx, repos, redis_conn))]
+pub async fn command(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    repos: &Repositories,
+    redis_conn: &mut redis::aio::ConnectionManager,
+) {
+    let export = match export_user_data(repos, redis_conn, command.user.id.0).await {
+        Ok(export) => export,
+        Err(err) => {
+            error!("{}", err);
+            interaction_error("Could not collect your data.", command, ctx).await;
+            return;
+        }
+    };
+
+    let data = match serde_json::to_vec_pretty(&export) {
+        Ok(data) => data,
+        Err(err) => {
+            error!("{:?}", err);
+            interaction_error("Could not create the export file.", command, ctx).await;
+            return;
+        }
+    };
+
+    let dm = command
+        .user
+        .direct_message(&ctx.http, |message| {
+            message
+                .embed(|embed| {
+                    embed
+                        .title("Your Data")
+                        .description("Attached is everything Open/Alt.ID has stored about you. Use /forgetme to delete it.")
+                        .footer(|footer| footer.text("Powered by Open/Alt.ID"))
+                })
+                .add_file(AttachmentType::Bytes {
+                    data: Cow::from(data),
+                    filename: "mydata.json".to_string(),
+                })
+        })
+        .await;
+
+    if let Err(err) = dm {
+        error!("{:?}", err);
+        interaction_error(
+            "Could not send you a DM, make sure you allow direct messages from this server.",
+            command,
+            ctx,
+        )
+        .await;
+        return;
+    }
+
+    info!("Creating response...");
+    let res = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message.flags(MessageFlags::EPHEMERAL);
+                    message.content("Your data export has been sent to your DMs.")
+                })
+        })
+        .await;
+    if let Err(err) = res {
+        error!("{}", err);
+        channel_message_error("Could not send interaction message.", command, ctx).await;
+    } else {
+        info!("Response created.");
+    }
+}
+
+/// Every use builds a full export, so users have to wait this long between two.
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+pub struct MyData;
+
+#[async_trait]
+impl SlashCommand for MyData {
+    fn name(&self) -> &'static str {
+        "mydata"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.description("DMs you a copy of all the data the bot has stored about you.")
+    }
+
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::Cooldown(COOLDOWN)]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        let mut conn = state.redis.clone();
+        command(ctx, interaction, &state.repos, &mut conn).await;
+    }
+}
@@ -0,0 +1,135 @@
+use crate::account_crypto::decrypt_account_id;
+use crate::dead_letters::{dead_letter_key, failing_key};
+use crate::repository::Repositories;
+use mongodb::bson::{self, Bson};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde_json::{json, Value};
+use tracing::*;
+
+/// Counts of everything removed by `delete_user_data`.
+#[derive(Debug, Default)]
+pub struct DeletedCounts {
+    pub accounts: u64,
+    pub attempts: u64,
+    pub redis_keys: u64,
+}
+
+/// Collects everything stored about the user into a single JSON value.
+#[instrument(skip(repos, redis_conn))]
+pub async fn export_user_data(
+    repos: &Repositories,
+    redis_conn: &mut ConnectionManager,
+    user_id: u64,
+) -> Result<Value, String> {
+    let accounts = to_json(
+        repos
+            .connections
+            .list_for_user(&user_id.to_string())
+            .await?,
+    )?;
+    let attempts = to_json(repos.attempts.list_for_user(&user_id.to_string()).await?)?;
+
+    let mut pending: Vec<Value> = vec![];
+    for key in pending_keys(redis_conn, user_id).await? {
+        match redis_conn.get::<&str, Option<String>>(&key).await {
+            Ok(Some(val)) => pending.push(json!({"key": key, "value": val})),
+            Ok(None) => {}
+            Err(err) => return Err(format!("Could not read key {} - {:?}", key, err)),
+        }
+    }
+
+    Ok(json!({
+        "user_ID": user_id.to_string(),
+        "social_media_accounts": accounts,
+        "verification_history": attempts,
+        "pending_verifications": pending,
+    }))
+}
+
+/// Deletes everything stored about the user.
+#[instrument(skip(repos, redis_conn))]
+pub async fn delete_user_data(
+    repos: &Repositories,
+    redis_conn: &mut ConnectionManager,
+    user_id: u64,
+) -> Result<DeletedCounts, String> {
+    let mut counts = DeletedCounts::default();
+
+    let keys = pending_keys(redis_conn, user_id).await?;
+    if !keys.is_empty() {
+        counts.redis_keys = match redis_conn.del::<&Vec<String>, u64>(&keys).await {
+            Ok(num) => num,
+            Err(err) => return Err(format!("Could not delete redis keys - {:?}", err)),
+        };
+    }
+
+    counts.accounts = repos
+        .connections
+        .delete_for_user(&user_id.to_string())
+        .await?;
+    counts.attempts = repos.attempts.delete_for_user(&user_id.to_string()).await?;
+
+    Ok(counts)
+}
+
+/// Finds the pending code keys (`uuid:{code}` -> `{user}:{guild}`) and unprocessed completion keys
+/// (`complete:{user}:{guild}`), dead lettered ones included, that belong to the user.
+async fn pending_keys(
+    redis_conn: &mut ConnectionManager,
+    user_id: u64,
+) -> Result<Vec<String>, String> {
+    let mut keys: Vec<String> = vec![];
+    let completions = format!("complete:{}:*", user_id);
+    for pattern in [
+        dead_letter_key(&completions),
+        failing_key(&completions),
+        completions,
+    ] {
+        match redis_conn.scan_match::<String, String>(pattern).await {
+            Ok(mut iter) => {
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+            }
+            Err(err) => return Err(format!("Redis error in scan - {:?}", err)),
+        }
+    }
+
+    let mut code_keys: Vec<String> = vec![];
+    match redis_conn.scan_match::<&str, String>("uuid:*").await {
+        Ok(mut iter) => {
+            while let Some(key) = iter.next_item().await {
+                code_keys.push(key);
+            }
+        }
+        Err(err) => return Err(format!("Redis error in scan - {:?}", err)),
+    }
+    let prefix = format!("{}:", user_id);
+    for key in code_keys {
+        match redis_conn.get::<&str, Option<String>>(&key).await {
+            Ok(Some(val)) if val.starts_with(&prefix) => keys.push(key),
+            Ok(_) => {}
+            Err(err) => return Err(format!("Could not read key {} - {:?}", key, err)),
+        }
+    }
+    Ok(keys)
+}
+
+fn to_json<T: serde::Serialize>(items: Vec<T>) -> Result<Vec<Value>, String> {
+    let mut docs: Vec<Value> = vec![];
+    for item in items {
+        let mut doc = match bson::to_document(&item) {
+            Ok(doc) => doc,
+            Err(err) => return Err(format!("Could not serialize document - {:?}", err)),
+        };
+        // Hand the user their account ID back instead of the stored ciphertext.
+        if let Ok(encrypted) = doc.get_str("account_id_enc") {
+            if let Ok(account_id) = decrypt_account_id(encrypted) {
+                doc.insert("account_id", account_id);
+            }
+        }
+        docs.push(Bson::Document(doc).into_relaxed_extjson());
+    }
+    Ok(docs)
+}
@@ -0,0 +1,99 @@
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::prelude::Context;
+use tracing::warn;
+
+use crate::commands::manage::*;
+use crate::commands::middleware::Guard;
+use crate::commands::misc::ping;
+use crate::commands::privacy::*;
+use crate::commands::verification::*;
+use crate::state::AppState;
+
+/// A slash command along with everything needed to register and dispatch it.
+#[async_trait]
+pub trait SlashCommand: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Describes the command to Discord. The name is filled in by the registry.
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand;
+
+    /// Checked in order before the command runs. Components on the command's messages go through
+    /// the same guards.
+    fn guards(&self) -> &'static [Guard] {
+        &[]
+    }
+
+    /// The `custom_id` prefixes, the part before the first ':', of the components this command
+    /// puts on its messages.
+    fn components(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    async fn run(&self, ctx: &Context, command: &ApplicationCommandInteraction, state: &AppState);
+
+    /// Handles a click on one of `components`, `kind` is the matched prefix.
+    async fn component(
+        &self,
+        _ctx: &Context,
+        _component: &MessageComponentInteraction,
+        kind: &str,
+        _state: &AppState,
+    ) {
+        warn!("{} has no handler for component {}.", self.name(), kind);
+    }
+}
+
+/// Every slash command the bot has. Registration, dispatch and component routing all go by this
+/// list, so a new command only needs to be added here.
+static COMMANDS: &[&dyn SlashCommand] = &[
+    &ping::Ping,
+    &verify::Verify,
+    &add_connection::AddConnection,
+    &remove_connection::RemoveConnection,
+    &setage::SetMinAge,
+    &setlogchannel::SetLogChannel,
+    &setmodrole::SetModRole,
+    &removemodrole::RemoveModRole,
+    &grantcommand::GrantCommand,
+    &revokecommand::RevokeCommand,
+    &setverificaitonrole::SetVerifiedRole,
+    &currentsettings::CurrentSettings,
+    &editverifysettings::EditVerifySettings,
+    &mydata::MyData,
+    &forgetme::ForgetMe,
+];
+
+pub fn all() -> &'static [&'static dyn SlashCommand] {
+    COMMANDS
+}
+
+pub fn find(name: &str) -> Option<&'static dyn SlashCommand> {
+    COMMANDS
+        .iter()
+        .copied()
+        .find(|command| command.name() == name)
+}
+
+/// The mod commands a guild can grant to roles and users that aren't mods.
+pub fn grantable() -> impl Iterator<Item = &'static dyn SlashCommand> {
+    COMMANDS.iter().copied().filter(|command| {
+        command
+            .guards()
+            .iter()
+            .any(|guard| matches!(guard, Guard::Mod))
+    })
+}
+
+/// Finds the command that owns components with the given `custom_id` prefix.
+pub fn find_component(kind: &str) -> Option<&'static dyn SlashCommand> {
+    COMMANDS
+        .iter()
+        .copied()
+        .find(|command| command.components().contains(&kind))
+}
@@ -1,7 +1,4 @@
-use mongodb::bson::doc;
-use mongodb::bson::Document;
-use mongodb::Collection;
-use serenity::model::application::command::Command;
+use mongodb::bson::oid::ObjectId;
 use serenity::model::application::command::CommandOptionType;
 use serenity::model::application::interaction::message_component::MessageComponentInteraction;
 use serenity::model::prelude::component::ButtonStyle;
@@ -11,106 +8,84 @@ use serenity::prelude::Context;
 use tracing::debug;
 use tracing::{error, info, instrument, warn};
 
+use crate::account_crypto::{display_account_id, encrypt_account_id, hash_account_id};
 use crate::commands::common::interaction_error::interaction_error;
 use crate::commands::common::interaction_error::interaction_error_comp;
-use crate::commands::common::permissions_check::check_if_mod;
-use crate::commands::common::permissions_check::check_if_mod_comp;
-use crate::commands::common::slash_commands;
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::dbmodels::guild::SocialMediaAccounts;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 
-#[instrument(skip(ctx, mongo_client))]
-pub async fn command(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-) {
-    match check_if_mod(ctx, command, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error("You must be a mod to use this command.", command, ctx).await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error(err, command, ctx).await;
-            return;
-        }
+const UNDO_BUTTON: &str = "UndoAddConnection";
+
+command_options! {
+    struct AddConnectionOptions {
+        user: User,
+        account_type: String,
+        account_id: String,
     }
+}
 
-    let options = command.data.options.clone();
+#[instrument(skip(ctx, command, repos))]
+pub async fn command(ctx: &Context, command: &ApplicationCommandInteraction, repos: &Repositories) {
+    let AddConnectionOptions {
+        user,
+        account_type,
+        account_id,
+    } = match parse_options(ctx, command).await {
+        Some(options) => options,
+        None => return,
+    };
 
-    let mut user: User = User::default();
-    let mut account_type: String = "".to_string();
-    let mut account_id: String = "".to_string();
-    let mut params_received = 0;
+    // The account ID itself is never logged.
+    info!("User {}, Account {}", user.name, account_type);
 
-    for tup in slash_commands::extract_vec(&options).await {
-        match tup.0 {
-            "user" => {
-                if let Some(x) = slash_commands::get_user(tup.1).await {
-                    params_received += 1;
-                    user = x
-                } else {
-                    interaction_error("'user' param is invalid.", command, ctx).await;
-                    return;
-                }
-            }
-            "account_type" => {
-                if let Some(x) = slash_commands::get_string(tup.1).await {
-                    params_received += 1;
-                    account_type = x
-                } else {
-                    interaction_error("'account_type' param is invalid.", command, ctx).await;
-                    return;
-                }
-            }
-            "account_id" => {
-                if let Some(x) = slash_commands::get_string(tup.1).await {
-                    params_received += 1;
-                    account_id = x
-                } else {
-                    interaction_error("'account_id' param is invalid.", command, ctx).await;
-                    return;
-                }
-            }
-            _ => {}
+    let account_id_hash = match hash_account_id(&account_id) {
+        Ok(hash) => hash,
+        Err(err) => {
+            error!("{}", err);
+            interaction_error("Account ID hashing is not configured.", command, ctx).await;
+            return;
         }
-    }
-
-    if params_received != 3 {
-        interaction_error("Incorrect number of parameters.", command, ctx).await;
-        return;
-    }
-
-    info!(
-        "User {}, Account {}, ID {}",
-        user.name, account_type, account_id
-    );
+    };
+    let account_id_enc = match encrypt_account_id(&account_id) {
+        Ok(enc) => enc,
+        Err(err) => {
+            error!("{}", err);
+            interaction_error("Could not encrypt the account ID.", command, ctx).await;
+            return;
+        }
+    };
 
     let user_id: i64 = user.id.0 as i64;
-    let collection: Collection<Document> = mongo_client
-        .database("verification_data")
-        .collection("socialmediaaccounts ");
-    let insert_res = collection
-        .insert_one(
-            doc! {
-                "user_ID": &user_id.to_string(),
-                "account_type": &account_type,
-                "account_id": &account_id,
-            },
-            None,
-        )
+    let insert_res = repos
+        .connections
+        .add(SocialMediaAccounts {
+            user_ID: user_id.to_string(),
+            account_type: account_type.clone(),
+            account_id_hash,
+            account_id_enc,
+        })
         .await;
 
-    if insert_res.is_err() {
-        super::super::common::interaction_error::interaction_error(
-            "Could not insert account into database.",
-            command,
-            ctx,
-        )
-        .await;
-        return;
-    }
+    // The undo button refers to the new document so the account ID stays out of the custom_id.
+    let inserted_id = match insert_res {
+        Ok(id) => id,
+        Err(err) => {
+            error!("{}", err);
+            super::super::common::interaction_error::interaction_error(
+                "Could not insert account into database.",
+                command,
+                ctx,
+            )
+            .await;
+            return;
+        }
+    };
 
     info!("Creating response...");
     let res = command
@@ -130,12 +105,10 @@ pub async fn command(
                         .components(|components| {
                             components.create_action_row(|row| {
                                 row.create_button(|button| {
-                                    button.style(ButtonStyle::Danger).label("UNDO").custom_id(
-                                        format!(
-                                            "UndoAddConnection:{}:{}:{}",
-                                            &user_id, &account_type, &account_id
-                                        ),
-                                    )
+                                    button
+                                        .style(ButtonStyle::Danger)
+                                        .label("UNDO")
+                                        .custom_id(format!("{}:{}", UNDO_BUTTON, inserted_id))
                                 })
                             })
                         })
@@ -155,90 +128,61 @@ pub async fn command(
 pub async fn undo_callback(
     ctx: &Context,
     interaction: &MessageComponentInteraction,
-    mongo_client: &mongodb::Client,
+    repos: &Repositories,
 ) {
-    match check_if_mod_comp(ctx, interaction, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error_comp("You must be a mod to use this command.", interaction, ctx)
-                    .await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error_comp(err, interaction, ctx).await;
-            return;
-        }
-    }
-
     let ids_split: Vec<&str> = interaction.data.custom_id.split(':').collect();
     debug!("{:?}", ids_split);
 
-    let user_id = match ids_split.get(1) {
-        Some(val) => *val,
-        None => {
-            error!("Invalid interaction data in UNDO callback.");
-            "error"
-        }
-    };
-    let account_type = match ids_split.get(2) {
-        Some(val) => *val,
-        None => {
-            error!("Invalid interaction data in UNDO callback.");
-            "error"
+    let inserted_id = match ids_split[..] {
+        [_, id] if ObjectId::parse_str(id).is_ok() => id,
+        // Buttons sent before accounts were stored hashed carry `user:type:id` instead.
+        [_, _, _, _] => {
+            interaction_error_comp(
+                "This button has expired, use /removeconnection instead.",
+                interaction,
+                ctx,
+            )
+            .await;
+            return;
         }
-    };
-    let account_id = match ids_split.get(3) {
-        Some(val) => *val,
-        None => {
+        _ => {
             error!("Invalid interaction data in UNDO callback.");
-            "error"
+            interaction_error_comp("Invalid button data.", interaction, ctx).await;
+            return;
         }
     };
 
-    debug!("{} - {} - {}", user_id, account_type, account_id);
-
-    let collection: Collection<Document> = mongo_client
-        .database("verification_data")
-        .collection("socialmediaaccounts ");
-    let delete_res = collection
-        .delete_one(
-            doc! {
-                "user_ID": user_id,
-                "account_type": account_type,
-                "account_id": account_id,
-            },
-            None,
-        )
-        .await;
+    let delete_res = repos.connections.take_by_id(inserted_id).await;
 
-    match delete_res {
-        Ok(doc) => {
-            if doc.deleted_count < 1 {
-                debug!("delete_res - {:?}", doc);
-                let _res = interaction
-                    .create_interaction_response(&ctx.http, |response| {
-                        response
-                            .kind(InteractionResponseType::ChannelMessageWithSource)
-                            .interaction_response_data(|message| {
-                                message.embed(|embed| {
-                                    embed
-                                        .title("No changes made")
-                                        .description("No database entries matched the given data.")
-                                        .footer(|footer| footer.text("Powered by Open/Alt.ID"))
-                                })
+    let account = match delete_res {
+        Ok(Some(account)) => account,
+        Ok(None) => {
+            debug!("No document with id {}", inserted_id);
+            let _res = interaction
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| {
+                            message.embed(|embed| {
+                                embed
+                                    .title("No changes made")
+                                    .description("No database entries matched the given data.")
+                                    .footer(|footer| footer.text("Powered by Open/Alt.ID"))
                             })
-                    })
-                    .await;
-                return;
-            }
+                        })
+                })
+                .await;
+            return;
         }
         Err(err) => {
-            error!("Could not remove doc - {:?}", err);
+            error!("{}", err);
+            interaction_error_comp("Could not remove the connection.", interaction, ctx).await;
             return;
         }
-    }
+    };
+    let user_id = account.user_ID.as_str();
+    let account_type = account.account_type.as_str();
+    let account_id = display_account_id(&account.account_id_enc);
 
     let _res = interaction
         .create_interaction_response(&ctx.http, |response| {
@@ -253,7 +197,7 @@ pub async fn undo_callback(
                             )
                             .field("User:", format!("<@{}>\n{}", &user_id, &user_id), false)
                             .field("Account:", account_type, false)
-                            .field("Account ID:", account_id, false)
+                            .field("Account ID:", &account_id, false)
                             .footer(|footer| footer.text("Powered by Open/Alt.ID"))
                     })
                 })
@@ -261,10 +205,19 @@ pub async fn undo_callback(
         .await;
 }
 
-pub async fn register(ctx: &Context) {
-    if let Err(err) = Command::create_global_application_command(&*ctx.http, |command| {
+pub struct AddConnection;
+
+#[async_trait]
+impl SlashCommand for AddConnection {
+    fn name(&self) -> &'static str {
+        "addconnection"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
         command
-            .name("addconnection")
             .description("Manually add connections to the database")
             .create_option(|opt| {
                 opt.name("user")
@@ -284,10 +237,35 @@ pub async fn register(ctx: &Context) {
                     .kind(CommandOptionType::String)
                     .required(true)
             })
-    })
-    .await
-    {
-        error!("Could not register verify command! {}", err.to_string());
-        panic!()
+    }
+
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Mod]
+    }
+
+    fn components(&self) -> &'static [&'static str] {
+        &[UNDO_BUTTON]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        command(ctx, interaction, &state.repos).await;
+    }
+
+    async fn component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+        kind: &str,
+        state: &AppState,
+    ) {
+        match kind {
+            UNDO_BUTTON => undo_callback(ctx, component, &state.repos).await,
+            _ => warn!("{} has no handler for component {}.", self.name(), kind),
+        }
     }
 }
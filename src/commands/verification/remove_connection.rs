@@ -1,127 +1,81 @@
-use mongodb::bson::doc;
-use mongodb::bson::Document;
-use mongodb::Collection;
-use serenity::model::application::command::Command;
+use rand::distributions;
+use rand::thread_rng;
+use rand::Rng;
+use redis::{AsyncCommands, Script};
 use serenity::model::application::command::CommandOptionType;
 use serenity::model::application::interaction::message_component::MessageComponentInteraction;
 use serenity::model::prelude::component::ButtonStyle;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
 use serenity::model::user::User;
 use serenity::prelude::Context;
-use tracing::debug;
 use tracing::{error, info, instrument, warn};
 
+use crate::account_crypto::{display_account_id, hash_account_id};
 use crate::commands::common::interaction_error::interaction_error;
 use crate::commands::common::interaction_error::interaction_error_comp;
-use crate::commands::common::permissions_check::check_if_mod;
-use crate::commands::common::permissions_check::check_if_mod_comp;
-use crate::commands::common::slash_commands;
+use crate::commands::common::slash_commands::{command_options, parse_options};
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::dbmodels::guild::SocialMediaAccounts;
+use crate::repository::Repositories;
+use crate::state::AppState;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 
-#[instrument(skip(ctx, mongo_client))]
+const UNDO_BUTTON: &str = "UndoRemoveConnection";
+
+/// How long removed accounts are kept in redis so the removal can be undone.
+const UNDO_TTL_SECS: usize = 15 * 60;
+
+fn undo_key(token: &str) -> String {
+    format!("undo:{}", token)
+}
+
+command_options! {
+    struct RemoveConnectionOptions {
+        user: User,
+        account_type: String,
+        account_id: String,
+    }
+}
+
+#[instrument(skip(ctx, command, repos, redis_conn))]
 pub async fn command(
     ctx: &Context,
     command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
+    repos: &Repositories,
+    redis_conn: &mut redis::aio::ConnectionManager,
 ) {
-    match check_if_mod(ctx, command, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error("You must be a mod to use this command.", command, ctx).await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error(err, command, ctx).await;
-            return;
-        }
-    }
-
-    let options = command.data.options.clone();
+    let RemoveConnectionOptions {
+        user,
+        account_type,
+        account_id,
+    } = match parse_options(ctx, command).await {
+        Some(options) => options,
+        None => return,
+    };
 
-    let mut user: User = User::default();
-    let mut account_type: String = "".to_string();
-    let mut account_id: String = "".to_string();
-    let mut params_received = 0;
+    // The account ID itself is never logged.
+    info!("User {}, Account {}", user.name, account_type);
 
-    for tup in slash_commands::extract_vec(&options).await {
-        match tup.0 {
-            "user" => {
-                if let Some(x) = slash_commands::get_user(tup.1).await {
-                    params_received += 1;
-                    user = x
-                } else {
-                    interaction_error("'user' param is invalid.", command, ctx).await;
-                    return;
-                }
-            }
-            "account_type" => {
-                if let Some(x) = slash_commands::get_string(tup.1).await {
-                    params_received += 1;
-                    account_type = x
-                } else {
-                    interaction_error("'account_type' param is invalid.", command, ctx).await;
-                    return;
-                }
-            }
-            "account_id" => {
-                if let Some(x) = slash_commands::get_string(tup.1).await {
-                    params_received += 1;
-                    account_id = x
-                } else {
-                    interaction_error("'account_id' param is invalid.", command, ctx).await;
-                    return;
-                }
-            }
-            _ => {}
+    let account_id_hash = match hash_account_id(&account_id) {
+        Ok(hash) => hash,
+        Err(err) => {
+            error!("{}", err);
+            interaction_error("Account ID hashing is not configured.", command, ctx).await;
+            return;
         }
-    }
-
-    if params_received != 3 {
-        interaction_error("Incorrect number of parameters.", command, ctx).await;
-        return;
-    }
-
-    info!(
-        "User {}, Account {}, ID {}",
-        user.name, account_type, account_id
-    );
+    };
 
     let user_id: i64 = user.id.0 as i64;
-    let collection: Collection<Document> = mongo_client
-        .database("verification_data")
-        .collection("socialmediaaccounts ");
-    let delete_res = collection
-        .delete_many(
-            doc! {
-                "user_ID": &user_id.to_string(),
-                "account_type": &account_type,
-                "account_id": &account_id,
-            },
-            None,
-        )
-        .await;
 
-    match delete_res {
-        Ok(doc) => {
-            if doc.deleted_count < 1 {
-                let _res = command
-                    .create_interaction_response(&ctx.http, |response| {
-                        response
-                            .kind(InteractionResponseType::ChannelMessageWithSource)
-                            .interaction_response_data(|message| {
-                                message.embed(|embed| {
-                                    embed
-                                        .title("No changes made")
-                                        .description("No database entries matched the given data.")
-                                        .footer(|footer| footer.text("Powered by Open/Alt.ID"))
-                                })
-                            })
-                    })
-                    .await;
-                return;
-            }
-        }
+    // Keep a copy of what gets removed, only the hash and encrypted ID can restore it.
+    let removed = match repos
+        .connections
+        .take_matching(&user_id.to_string(), &account_type, &account_id_hash)
+        .await
+    {
+        Ok(removed) => removed,
         Err(err) => {
             super::super::common::interaction_error::interaction_error(
                 "Could not remove account from database.",
@@ -129,38 +83,85 @@ pub async fn command(
                 ctx,
             )
             .await;
-            error!("Could not remove doc - {:?}", err);
+            error!("{}", err);
             return;
         }
+    };
+
+    if removed.is_empty() {
+        let _res = command
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| {
+                        message.embed(|embed| {
+                            embed
+                                .title("No changes made")
+                                .description("No database entries matched the given data.")
+                                .footer(|footer| footer.text("Powered by Open/Alt.ID"))
+                        })
+                    })
+            })
+            .await;
+        return;
     }
 
+    let undo_token: String = thread_rng()
+        .sample_iter(&distributions::Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+    // Without a stored copy there is nothing the button could restore, so it is left out.
+    let undoable = match serde_json::to_string(&removed) {
+        Ok(removed_json) => {
+            let res: redis::RedisResult<()> = redis_conn
+                .set_ex(undo_key(&undo_token), removed_json, UNDO_TTL_SECS)
+                .await;
+            match res {
+                Ok(()) => true,
+                Err(err) => {
+                    error!("Could not store removed accounts for undo - {:?}", err);
+                    false
+                }
+            }
+        }
+        Err(err) => {
+            error!("Could not serialize removed accounts - {:?}", err);
+            false
+        }
+    };
+
     info!("Creating response...");
     let res = command
         .create_interaction_response(&ctx.http, |response| {
             response
                 .kind(InteractionResponseType::ChannelMessageWithSource)
                 .interaction_response_data(|message| {
-                    message
-                        .embed(|embed| {
-                            embed
-                                .title("Connection removed from database")
-                                .description("You can undo this by clicking the button below.")
-                                .field("User:", format!("<@{}>\n{}", &user.id, &user.id), false)
-                                .field("Account:", &account_type, false)
-                                .field("Account ID:", &account_id, false)
-                        })
-                        .components(|components| {
+                    message.embed(|embed| {
+                        embed
+                            .title("Connection removed from database")
+                            .description(if undoable {
+                                "You can undo this by clicking the button below."
+                            } else {
+                                "This can't be undone, add the connection again if needed."
+                            })
+                            .field("User:", format!("<@{}>\n{}", &user.id, &user.id), false)
+                            .field("Account:", &account_type, false)
+                            .field("Account ID:", &account_id, false)
+                    });
+                    if undoable {
+                        message.components(|components| {
                             components.create_action_row(|row| {
                                 row.create_button(|button| {
-                                    button.style(ButtonStyle::Danger).label("UNDO").custom_id(
-                                        format!(
-                                            "UndoRemoveConnection:{}:{}:{}",
-                                            &user_id, &account_type, &account_id
-                                        ),
-                                    )
+                                    button
+                                        .style(ButtonStyle::Danger)
+                                        .label("UNDO")
+                                        .custom_id(format!("{}:{}", UNDO_BUTTON, undo_token))
                                 })
                             })
-                        })
+                        });
+                    }
+                    message
                 })
         })
         .await;
@@ -177,67 +178,75 @@ pub async fn command(
 pub async fn undo_callback(
     ctx: &Context,
     interaction: &MessageComponentInteraction,
-    mongo_client: &mongodb::Client,
+    repos: &Repositories,
+    redis_conn: &mut redis::aio::ConnectionManager,
 ) {
-    match check_if_mod_comp(ctx, interaction, mongo_client).await {
-        Ok(is_mod) => {
-            if !is_mod {
-                interaction_error_comp("You must be a mod to use this command.", interaction, ctx)
-                    .await;
-                return;
-            }
-        }
-        Err(err) => {
-            warn!("{}", err);
-            interaction_error_comp(err, interaction, ctx).await;
-            return;
-        }
-    }
-
     let ids_split: Vec<&str> = interaction.data.custom_id.split(':').collect();
 
-    let user_id = match ids_split.get(1) {
-        Some(val) => *val,
+    let undo_key = match ids_split.get(1) {
+        Some(token) => undo_key(token),
         None => {
             error!("Invalid interaction data in UNDO callback.");
-            "error"
+            interaction_error_comp("Invalid button data.", interaction, ctx).await;
+            return;
+        }
+    };
+
+    // Taken in one step, so clicking twice can't restore the accounts twice.
+    let res: redis::RedisResult<Option<String>> = Script::new(
+        r"local val = redis.call('GET', KEYS[1]) redis.call('DEL', KEYS[1]) return val",
+    )
+    .key(&undo_key)
+    .invoke_async(redis_conn)
+    .await;
+    let removed_json = match res {
+        Ok(val) => val,
+        Err(err) => {
+            error!("Could not get removed accounts - {:?}", err);
+            interaction_error_comp("Could not reach the undo store.", interaction, ctx).await;
+            return;
         }
     };
-    let account_type = match ids_split.get(2) {
-        Some(val) => *val,
+    let removed: Vec<SocialMediaAccounts> = match removed_json.as_deref().map(serde_json::from_str)
+    {
+        Some(Ok(removed)) => removed,
+        Some(Err(err)) => {
+            error!("Could not parse removed accounts - {:?}", err);
+            interaction_error_comp("The undo data is corrupt.", interaction, ctx).await;
+            return;
+        }
         None => {
-            error!("Invalid interaction data in UNDO callback.");
-            "error"
+            interaction_error_comp("This undo has expired.", interaction, ctx).await;
+            return;
         }
     };
-    let account_id = match ids_split.get(3) {
-        Some(val) => *val,
+    let (user_id, account_type) = match removed.first() {
+        Some(account) => (account.user_ID.clone(), account.account_type.clone()),
         None => {
-            error!("Invalid interaction data in UNDO callback.");
-            "error"
+            interaction_error_comp("There is nothing to undo.", interaction, ctx).await;
+            return;
         }
     };
+    let account_id = display_account_id(&removed[0].account_id_enc);
 
-    let account_collection: Collection<Document> = mongo_client
-        .database("verification_data")
-        .collection("socialmediaaccounts ");
-    let delete_res = account_collection
-        .insert_one(
-            doc! {
-                "user_ID": &user_id.to_string(),
-                "account_type": &account_type,
-                "account_id": &account_id,
-            },
-            None,
+    if let Err(err) = repos.connections.restore(removed).await {
+        error!("{}", err);
+        // Put the copy back so the undo can be tried again.
+        if let Some(removed_json) = removed_json {
+            let res: redis::RedisResult<()> = redis_conn
+                .set_ex(&undo_key, removed_json, UNDO_TTL_SECS)
+                .await;
+            if let Err(err) = res {
+                error!("Could not keep the removed accounts for undo - {:?}", err);
+            }
+        }
+        interaction_error_comp(
+            "Could not restore the connection, try again.",
+            interaction,
+            ctx,
         )
         .await;
-
-    match delete_res {
-        Ok(doc) => debug!("{:?}", doc),
-        Err(err) => {
-            error!("Could not reinsert doc - {:?}", err);
-            return;
-        }
+        return;
     }
 
     let _res = interaction
@@ -250,8 +259,8 @@ pub async fn undo_callback(
                             .title("Changes reverted")
                             .description("The connection was added back into the database.")
                             .field("User:", format!("<@{}>\n{}", &user_id, &user_id), false)
-                            .field("Account:", account_type, false)
-                            .field("Account ID:", account_id, false)
+                            .field("Account:", &account_type, false)
+                            .field("Account ID:", &account_id, false)
                             .footer(|footer| footer.text("Powered by Open/Alt.ID"))
                     })
                 })
@@ -259,10 +268,19 @@ pub async fn undo_callback(
         .await;
 }
 
-pub async fn register(ctx: &Context) {
-    if let Err(err) = Command::create_global_application_command(&*ctx.http, |command| {
+pub struct RemoveConnection;
+
+#[async_trait]
+impl SlashCommand for RemoveConnection {
+    fn name(&self) -> &'static str {
+        "removeconnection"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
         command
-            .name("removeconnection")
             .description("Manually remove connections to the database")
             .create_option(|opt| {
                 opt.name("user")
@@ -282,10 +300,39 @@ pub async fn register(ctx: &Context) {
                     .kind(CommandOptionType::String)
                     .required(true)
             })
-    })
-    .await
-    {
-        error!("Could not register verify command! {}", err.to_string());
-        panic!()
+    }
+
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Mod]
+    }
+
+    fn components(&self) -> &'static [&'static str] {
+        &[UNDO_BUTTON]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        let mut conn = state.redis.clone();
+        command(ctx, interaction, &state.repos, &mut conn).await;
+    }
+
+    async fn component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+        kind: &str,
+        state: &AppState,
+    ) {
+        match kind {
+            UNDO_BUTTON => {
+                let mut conn = state.redis.clone();
+                undo_callback(ctx, component, &state.repos, &mut conn).await
+            }
+            _ => warn!("{} has no handler for component {}.", self.name(), kind),
+        }
     }
 }
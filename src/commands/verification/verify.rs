@@ -1,32 +1,40 @@
 use crate::commands::common::interaction_error::interaction_error;
+use crate::commands::middleware::Guard;
+use crate::commands::registry::SlashCommand;
+use crate::config::Config;
 use crate::dbmodels::guild::Guild as GuildDoc;
+use crate::metrics::metrics;
+use crate::repository::Repositories;
+use crate::state::AppState;
 use chrono::Duration;
 use chrono::Utc;
-use mongodb::bson::doc;
 use rand::distributions;
 use rand::thread_rng;
 use rand::Rng;
 use redis::AsyncCommands;
 use redis::RedisResult;
 use redis::Value;
-use serenity::model::application::command::Command;
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
 use serenity::model::application::interaction::message_component::MessageComponentInteraction;
 use serenity::model::application::interaction::MessageFlags;
 use serenity::model::prelude::component::ButtonStyle;
 use serenity::model::prelude::interaction::{application_command::*, InteractionResponseType};
 use serenity::prelude::Context;
 use serenity::utils::Colour;
-use std::env;
 use tracing::debug;
 use tracing::{error, info, instrument, warn};
 
+const HELP_BUTTON: &str = "HelpButton";
+
 #[allow(unused)]
-#[instrument(skip(ctx, mongo_client, redis_conn))]
+#[instrument(skip(ctx, repos, redis_conn, config))]
 pub async fn command(
     ctx: &Context,
     command: &ApplicationCommandInteraction,
-    mongo_client: &mongodb::Client,
-    mut redis_conn: &mut redis::aio::MultiplexedConnection,
+    repos: &Repositories,
+    mut redis_conn: &mut redis::aio::ConnectionManager,
+    config: &Config,
 ) {
     let guild_id = match &command.guild_id {
         Some(id) => id.0,
@@ -40,15 +48,10 @@ pub async fn command(
 
     // get guild settings from mongodb
     // if the server has no verification role set, log an error and return.
-    let guild_doc_opt: Option<GuildDoc> = match mongo_client
-        .database("botdb")
-        .collection("guilds")
-        .find_one(doc! {"guild_ID": guild_id.to_string()}, None)
-        .await
-    {
+    let guild_doc_opt: Option<GuildDoc> = match repos.guilds.get(&guild_id.to_string()).await {
         Ok(col_opt) => col_opt,
         Err(err) => {
-            error!("{:?}", err);
+            error!("{}", err);
             return;
         }
     };
@@ -93,6 +96,10 @@ pub async fn command(
     );
 
     if member_of_command.user.id.created_at().unix_timestamp() < min_time.timestamp() {
+        metrics()
+            .age_bypasses
+            .with_label_values(&[&guild_id.to_string()])
+            .inc();
         let verification_role_id: u64 = match guild_doc.verification_role_ID.parse() {
             Ok(num) => num,
             Err(err) => {
@@ -191,6 +198,11 @@ pub async fn command(
             }
             Err(err) => {
                 error!("Could not add role to user during verification - {:?}", err);
+                metrics()
+                    .role_grant_failures
+                    .with_label_values(&[&guild_id.to_string()])
+                    .inc();
+                metrics().dependency_error("discord");
 
                 let res = channel.id().send_message(&ctx.http, |message| {
                     message.embed(|embed| {
@@ -259,8 +271,7 @@ pub async fn command(
             }
         }
     }
-    let frontend_host = env::var("FRONTEND_HOST").expect("Need a frontend host in env.");
-    let verification_link: String = format!("{}/verify?code={}", frontend_host, rand_string);
+    let verification_link: String = format!("{}/verify?code={}", config.frontend_host, rand_string);
 
     let res: RedisResult<Value> = redis_conn
         .set_ex(
@@ -270,6 +281,13 @@ pub async fn command(
         )
         .await;
     debug!("Result from setting value - {:?}", res);
+    match res {
+        Ok(_) => metrics()
+            .verifications_started
+            .with_label_values(&[&guild_id.to_string()])
+            .inc(),
+        Err(_) => metrics().dependency_error("redis"),
+    }
 
     let _res = command.create_interaction_response(&ctx.http, |response| {
         response
@@ -307,7 +325,7 @@ pub async fn command(
                             button
                                 .style(ButtonStyle::Danger)
                                 .label("I need help!")
-                                .custom_id("HelpButton")
+                                .custom_id(HELP_BUTTON)
                         })
                     })
                 });
@@ -342,7 +360,7 @@ pub async fn command(
                 embed.color(Colour::GOLD);
                 embed.description("The user has initiated verification,");
                 embed.timestamp(Utc::now());
-                embed.thumbnail(&member_obj.face());
+                embed.thumbnail(member_obj.face());
                 embed.author(|author| {
                     author.name("Open/Alt.ID Logs");
                     author.url("https://github.com/omneex/OpenAltID");
@@ -372,7 +390,7 @@ pub async fn command(
 pub async fn help_callback(
     ctx: &Context,
     interaction: &MessageComponentInteraction,
-    _mongo_client: &mongodb::Client,
+    _repos: &Repositories,
 ) {
     info!("Creating response...");
     let _res = interaction.create_interaction_response(&ctx.http, |response| {
@@ -396,13 +414,52 @@ pub async fn help_callback(
     }).await;
 }
 
-pub async fn register(ctx: &Context) {
-    if let Err(err) = Command::create_global_application_command(&*ctx.http, |command| {
-        command.name("verify").description("Verify")
-    })
-    .await
-    {
-        error!("Could not register verify command! {}", err.to_string());
-        panic!()
+/// How long a user has to wait before asking for another verification link.
+const COOLDOWN: std::time::Duration = std::time::Duration::from_secs(10);
+
+pub struct Verify;
+
+#[async_trait]
+impl SlashCommand for Verify {
+    fn name(&self) -> &'static str {
+        "verify"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.description("Verify")
+    }
+
+    fn guards(&self) -> &'static [Guard] {
+        &[Guard::GuildOnly, Guard::Cooldown(COOLDOWN)]
+    }
+
+    fn components(&self) -> &'static [&'static str] {
+        &[HELP_BUTTON]
+    }
+
+    async fn run(
+        &self,
+        ctx: &Context,
+        interaction: &ApplicationCommandInteraction,
+        state: &AppState,
+    ) {
+        let mut conn = state.redis.clone();
+        command(ctx, interaction, &state.repos, &mut conn, &state.config).await;
+    }
+
+    async fn component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+        kind: &str,
+        state: &AppState,
+    ) {
+        match kind {
+            HELP_BUTTON => help_callback(ctx, component, &state.repos).await,
+            _ => warn!("{} has no handler for component {}.", self.name(), kind),
+        }
     }
 }
@@ -0,0 +1,656 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use tracing::warn;
+
+use crate::retention::DEFAULT_RETENTION_DAYS;
+
+/// Used when `CONFIG_FILE` is not set. The file is optional.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    Mongo,
+    /// Runs the bot without a database, nothing is kept between restarts.
+    Memory,
+}
+
+/// DNS resolver used for `mongodb+srv` lookups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MongoResolver {
+    /// Whatever the host system is configured with.
+    System,
+    Cloudflare,
+    Google,
+    Quad9,
+}
+
+/// Which Discord shards this process runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sharding {
+    /// Uses the shard count Discord recommends and runs every shard in this process.
+    Auto,
+    /// Runs shards `first..=last` out of `total`, other processes run the rest.
+    Range { first: u64, last: u64, total: u64 },
+}
+
+impl Sharding {
+    /// Whether the guild is on one of the shards run by this process, using Discord's
+    /// `(guild_id >> 22) % shard_count` formula.
+    pub fn owns_guild(&self, guild_id: u64) -> bool {
+        match *self {
+            Sharding::Auto => true,
+            Sharding::Range { first, last, total } => {
+                let shard = (guild_id >> 22) % total;
+                shard >= first && shard <= last
+            }
+        }
+    }
+}
+
+/// Client settings for MongoDB. Anything left unset keeps the value from the connection string,
+/// or the driver default.
+#[derive(Clone, Debug)]
+pub struct MongoConfig {
+    pub resolver: MongoResolver,
+    pub app_name: String,
+    pub max_pool_size: Option<u32>,
+    pub min_pool_size: Option<u32>,
+    pub connect_timeout_ms: Option<u64>,
+    pub server_selection_timeout_ms: Option<u64>,
+    pub tls: Option<bool>,
+    pub tls_ca_file: Option<String>,
+}
+
+/// Everything the bot is configured with, loaded once at startup.
+///
+/// Each setting is read from the environment variable of the same name, or from the lower case
+/// key in the TOML config file. The environment wins when both are set.
+#[derive(Clone)]
+pub struct Config {
+    pub discord_token: String,
+    pub application_id: u64,
+    pub storage_backend: StorageBackend,
+    /// Empty when the in-memory backend is used.
+    pub mongo_conn_str: String,
+    pub mongo: MongoConfig,
+    /// The bot's own database, holding guild settings and the privacy audit.
+    pub db_name: String,
+    /// The database shared with the frontend, holding accounts and verification history.
+    pub verification_db_name: String,
+    pub redis_host: String,
+    pub redis_port: u16,
+    /// Longest wait between redis reconnects and check loop restarts.
+    pub redis_max_backoff_secs: u64,
+    /// How long verifications can go unprocessed before an error is logged.
+    pub check_loop_alert_secs: u64,
+    /// How many guilds the check loop works on at the same time.
+    pub check_loop_concurrency: usize,
+    /// How long a completion can keep failing before it is dead lettered, 0 retries forever.
+    pub dead_letter_after_secs: u64,
+    pub frontend_host: String,
+    pub guild_retention_days: i64,
+    /// How long guild settings are cached, 0 turns the cache off.
+    pub guild_cache_ttl_secs: u64,
+    /// Asks for the privileged guild members intent, which has to be enabled for the application
+    /// first or Discord refuses the connection. It keeps members in the cache.
+    pub guild_members_intent: bool,
+    /// Required with the mongo backend. Must never change once accounts are stored.
+    pub account_hash_key: Option<String>,
+    pub account_encryption_key: Option<String>,
+    /// OAuth2 bearer token of a server manager with the `applications.commands.permissions.update`
+    /// scope. Discord only lets such tokens edit per-guild command permissions, not bot tokens.
+    pub command_permissions_token: Option<String>,
+    pub sharding: Sharding,
+    /// How long the leader lease lasts without being renewed.
+    pub leader_lease_secs: u64,
+    pub debug: bool,
+    /// How long a shutdown waits for the check loop to finish its current pass.
+    pub shutdown_timeout_secs: u64,
+    /// Where to serve the operational HTTP endpoints, disabled when unset.
+    pub http_addr: Option<SocketAddr>,
+    /// Registers the commands to this guild only, where changes show up right away.
+    pub dev_guild_id: Option<u64>,
+}
+
+impl Config {
+    /// Loads the config from the environment and the optional config file. Every problem found
+    /// is reported in the returned error, not just the first one.
+    pub fn load() -> Result<Config, String> {
+        Config::from_source(&Source::new()?)
+    }
+
+    fn from_source(source: &Source) -> Result<Config, String> {
+        let mut errors: Vec<String> = vec![];
+
+        let storage_backend = match source.get("STORAGE_BACKEND").as_deref() {
+            None | Some("mongo") => StorageBackend::Mongo,
+            Some("memory") => StorageBackend::Memory,
+            Some(other) => {
+                errors.push(format!(
+                    "STORAGE_BACKEND must be 'mongo' or 'memory', got '{}'.",
+                    other
+                ));
+                StorageBackend::Mongo
+            }
+        };
+        let uses_mongo = storage_backend == StorageBackend::Mongo;
+        if source.get("PLATFORM").is_some() {
+            warn!("PLATFORM is no longer used, set MONGO_RESOLVER=cloudflare for the old windows behaviour.");
+        }
+
+        let config = Config {
+            discord_token: source.required("DISCORD_TOKEN", &mut errors),
+            application_id: source.parsed("APPLICATION_ID", None, &mut errors),
+            storage_backend,
+            mongo_conn_str: if uses_mongo {
+                source.required("MONGO_CONN_STR", &mut errors)
+            } else {
+                String::new()
+            },
+            mongo: MongoConfig {
+                resolver: match source.get("MONGO_RESOLVER").as_deref() {
+                    None | Some("system") => MongoResolver::System,
+                    Some("cloudflare") => MongoResolver::Cloudflare,
+                    Some("google") => MongoResolver::Google,
+                    Some("quad9") => MongoResolver::Quad9,
+                    Some(other) => {
+                        errors.push(format!(
+                            "MONGO_RESOLVER must be 'system', 'cloudflare', 'google' or 'quad9', got '{}'.",
+                            other
+                        ));
+                        MongoResolver::System
+                    }
+                },
+                app_name: source.or_default("MONGO_APP_NAME", "ironic_bot"),
+                max_pool_size: source.optional("MONGO_MAX_POOL_SIZE", &mut errors),
+                min_pool_size: source.optional("MONGO_MIN_POOL_SIZE", &mut errors),
+                connect_timeout_ms: source.optional("MONGO_CONNECT_TIMEOUT_MS", &mut errors),
+                server_selection_timeout_ms: source
+                    .optional("MONGO_SERVER_SELECTION_TIMEOUT_MS", &mut errors),
+                tls: source.optional("MONGO_TLS", &mut errors),
+                tls_ca_file: source.get("MONGO_TLS_CA_FILE"),
+            },
+            db_name: source.or_default("DB_NAME", "botdb"),
+            verification_db_name: source.or_default("VERIFICATION_DB_NAME", "verification_data"),
+            redis_host: source.required("REDIS_HOST", &mut errors),
+            redis_port: source.parsed("REDIS_PORT", Some(6379), &mut errors),
+            redis_max_backoff_secs: source.parsed("REDIS_MAX_BACKOFF_SECS", Some(60), &mut errors),
+            check_loop_alert_secs: source.parsed("CHECK_LOOP_ALERT_SECS", Some(300), &mut errors),
+            check_loop_concurrency: source.parsed("CHECK_LOOP_CONCURRENCY", Some(4), &mut errors),
+            dead_letter_after_secs: source.parsed(
+                "DEAD_LETTER_AFTER_SECS",
+                Some(60 * 60),
+                &mut errors,
+            ),
+            frontend_host: source.required("FRONTEND_HOST", &mut errors),
+            guild_retention_days: source.parsed(
+                "GUILD_RETENTION_DAYS",
+                Some(DEFAULT_RETENTION_DAYS),
+                &mut errors,
+            ),
+            guild_cache_ttl_secs: source.parsed("GUILD_CACHE_TTL_SECS", Some(60), &mut errors),
+            guild_members_intent: source.parsed("GUILD_MEMBERS_INTENT", Some(false), &mut errors),
+            // Stored accounts can only be matched with the key they were hashed with.
+            account_hash_key: if uses_mongo {
+                Some(source.required("ACCOUNT_HASH_KEY", &mut errors))
+            } else {
+                source.get("ACCOUNT_HASH_KEY")
+            },
+            account_encryption_key: source.get("ACCOUNT_ENCRYPTION_KEY"),
+            command_permissions_token: source.get("COMMAND_PERMISSIONS_TOKEN"),
+            sharding: sharding(source, &mut errors),
+            leader_lease_secs: source.parsed("LEADER_LEASE_SECS", Some(10), &mut errors),
+            debug: source.parsed("DEBUG", Some(false), &mut errors),
+            shutdown_timeout_secs: source.parsed("SHUTDOWN_TIMEOUT_SECS", Some(20), &mut errors),
+            http_addr: source.optional("HTTP_ADDR", &mut errors),
+            dev_guild_id: source.optional("DEV_GUILD_ID", &mut errors),
+        };
+
+        if let (Some(min), Some(max)) = (config.mongo.min_pool_size, config.mongo.max_pool_size) {
+            if min > max {
+                errors.push(
+                    "MONGO_MIN_POOL_SIZE can not be larger than MONGO_MAX_POOL_SIZE.".to_string(),
+                );
+            }
+        }
+        // The CA file is only passed on with TLS options of our own, see `get_mongo_client`.
+        if config.mongo.tls_ca_file.is_some() && config.mongo.tls != Some(true) {
+            errors.push("MONGO_TLS_CA_FILE is set but MONGO_TLS is not enabled.".to_string());
+        }
+        if config.redis_max_backoff_secs == 0 {
+            errors.push("REDIS_MAX_BACKOFF_SECS must be at least 1.".to_string());
+        }
+        if config.check_loop_alert_secs == 0 {
+            errors.push("CHECK_LOOP_ALERT_SECS must be at least 1.".to_string());
+        }
+        if config.leader_lease_secs < 3 {
+            errors.push("LEADER_LEASE_SECS must be at least 3.".to_string());
+        }
+        if config.check_loop_concurrency == 0 {
+            errors.push("CHECK_LOOP_CONCURRENCY must be at least 1.".to_string());
+        }
+        if config.guild_retention_days < 0 {
+            errors.push("GUILD_RETENTION_DAYS can not be negative.".to_string());
+        }
+        if let Some(key) = &config.account_encryption_key {
+            if !matches!(STANDARD.decode(key), Ok(bytes) if bytes.len() == 32) {
+                errors
+                    .push("ACCOUNT_ENCRYPTION_KEY must be 32 bytes encoded as base64.".to_string());
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(format!(
+                "Invalid configuration:\n  - {}",
+                errors.join("\n  - ")
+            ))
+        }
+    }
+
+    pub fn redis_url(&self) -> String {
+        format!("redis://{}:{}/", self.redis_host, self.redis_port)
+    }
+}
+
+/// Reads `SHARD_COUNT` and `SHARD_RANGE`. Without a count the bot autoshards, and a count without
+/// a range runs every shard in this process.
+fn sharding(source: &Source, errors: &mut Vec<String>) -> Sharding {
+    let total: u64 = match source.optional("SHARD_COUNT", errors) {
+        Some(0) => {
+            errors.push("SHARD_COUNT must be at least 1.".to_string());
+            return Sharding::Auto;
+        }
+        Some(total) => total,
+        None => {
+            if source.get("SHARD_RANGE").is_some() {
+                errors.push("SHARD_RANGE is set but SHARD_COUNT is not.".to_string());
+            }
+            return Sharding::Auto;
+        }
+    };
+    let range = match source.get("SHARD_RANGE") {
+        Some(range) => range,
+        None => {
+            return Sharding::Range {
+                first: 0,
+                last: total - 1,
+                total,
+            }
+        }
+    };
+    let parsed = match range.split_once('-') {
+        Some((first, last)) => match (first.trim().parse(), last.trim().parse()) {
+            (Ok(first), Ok(last)) => Some((first, last)),
+            _ => None,
+        },
+        None => None,
+    };
+    match parsed {
+        Some((first, last)) if first <= last && last < total => {
+            Sharding::Range { first, last, total }
+        }
+        _ => {
+            errors.push(format!(
+                "SHARD_RANGE must look like 'first-last' with first <= last < SHARD_COUNT, got '{}'.",
+                range
+            ));
+            Sharding::Auto
+        }
+    }
+}
+
+/// Looks settings up in the environment first and then in the config file.
+struct Source {
+    env: HashMap<String, String>,
+    file: HashMap<String, toml::Value>,
+}
+
+impl Source {
+    fn new() -> Result<Source, String> {
+        // Variables that aren't valid unicode can't hold a valid setting anyway.
+        let env: HashMap<String, String> = env::vars_os()
+            .filter_map(|(name, val)| Some((name.into_string().ok()?, val.into_string().ok()?)))
+            .collect();
+        let path = match env.get("CONFIG_FILE") {
+            Some(path) => path.clone(),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => DEFAULT_CONFIG_FILE.to_string(),
+            None => {
+                return Ok(Source {
+                    env,
+                    file: HashMap::new(),
+                })
+            }
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => return Err(format!("Could not read config file {} - {}", path, err)),
+        };
+        match toml::from_str(&contents) {
+            Ok(file) => Ok(Source { env, file }),
+            Err(err) => Err(format!("Could not parse config file {} - {}", path, err)),
+        }
+    }
+
+    /// Empty values count as unset, so the blank entries of `.env.example` fall back to defaults.
+    fn get(&self, name: &str) -> Option<String> {
+        if let Some(val) = self.env.get(name) {
+            if !val.is_empty() {
+                return Some(val.clone());
+            }
+        }
+        match self.file.get(&name.to_lowercase()) {
+            Some(toml::Value::String(val)) if val.is_empty() => None,
+            Some(toml::Value::String(val)) => Some(val.clone()),
+            Some(val) => Some(val.to_string()),
+            None => None,
+        }
+    }
+
+    fn or_default(&self, name: &str, default: &str) -> String {
+        self.get(name).unwrap_or_else(|| default.to_string())
+    }
+
+    fn required(&self, name: &str, errors: &mut Vec<String>) -> String {
+        match self.get(name) {
+            Some(val) => val,
+            None => {
+                errors.push(format!("{} is not set.", name));
+                String::new()
+            }
+        }
+    }
+
+    /// Parses the setting if it is set.
+    fn optional<T: std::str::FromStr>(&self, name: &str, errors: &mut Vec<String>) -> Option<T> {
+        match self.get(name) {
+            Some(val) => match val.parse() {
+                Ok(parsed) => Some(parsed),
+                Err(_) => {
+                    errors.push(format!("{} has an invalid value '{}'.", name, val));
+                    None
+                }
+            },
+            None => None,
+        }
+    }
+
+    /// Parses the setting, falling back to `default` when it is unset. Without a default the
+    /// setting is required.
+    fn parsed<T: std::str::FromStr + Default>(
+        &self,
+        name: &str,
+        default: Option<T>,
+        errors: &mut Vec<String>,
+    ) -> T {
+        match (self.get(name), default) {
+            (Some(val), _) => match val.parse() {
+                Ok(parsed) => parsed,
+                Err(_) => {
+                    errors.push(format!("{} has an invalid value '{}'.", name, val));
+                    T::default()
+                }
+            },
+            (None, Some(default)) => default,
+            (None, None) => {
+                errors.push(format!("{} is not set.", name));
+                T::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: [(&str, &str); 6] = [
+        ("DISCORD_TOKEN", "token"),
+        ("APPLICATION_ID", "1234"),
+        ("MONGO_CONN_STR", "mongodb://localhost"),
+        ("ACCOUNT_HASH_KEY", "hash key"),
+        ("REDIS_HOST", "localhost"),
+        ("FRONTEND_HOST", "https://example.com"),
+    ];
+
+    fn source(env: &[(&str, &str)], file: &str) -> Source {
+        Source {
+            env: env
+                .iter()
+                .map(|(name, val)| (name.to_string(), val.to_string()))
+                .collect(),
+            file: toml::from_str(file).unwrap(),
+        }
+    }
+
+    /// The required settings plus `env`, which wins over them.
+    fn load(env: &[(&str, &str)], file: &str) -> Result<Config, String> {
+        let mut vars = REQUIRED.to_vec();
+        vars.extend_from_slice(env);
+        Config::from_source(&source(&vars, file))
+    }
+
+    fn load_err(env: &[(&str, &str)], file: &str) -> String {
+        match load(env, file) {
+            Ok(_) => panic!("expected the config to be rejected"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = load(&[], "").unwrap();
+        assert_eq!(config.storage_backend, StorageBackend::Mongo);
+        assert_eq!(config.redis_port, 6379);
+        assert_eq!(config.dead_letter_after_secs, 3600);
+        assert_eq!(config.guild_cache_ttl_secs, 60);
+        assert!(!config.guild_members_intent);
+        assert_eq!(config.leader_lease_secs, 10);
+        assert_eq!(config.guild_retention_days, DEFAULT_RETENTION_DAYS);
+        assert_eq!(config.sharding, Sharding::Auto);
+        assert_eq!(config.command_permissions_token, None);
+        assert_eq!(config.http_addr, None);
+    }
+
+    #[test]
+    fn reports_every_missing_setting() {
+        let err = match Config::from_source(&source(&[], "")) {
+            Ok(_) => panic!("expected the config to be rejected"),
+            Err(err) => err,
+        };
+        for (name, _) in REQUIRED {
+            assert!(err.contains(&format!("{} is not set.", name)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn memory_backend_needs_no_mongo() {
+        let env = [("STORAGE_BACKEND", "memory")];
+        let vars: Vec<(&str, &str)> = REQUIRED
+            .into_iter()
+            .filter(|(name, _)| !matches!(*name, "MONGO_CONN_STR" | "ACCOUNT_HASH_KEY"))
+            .chain(env)
+            .collect();
+        let config = Config::from_source(&source(&vars, "")).unwrap();
+        assert_eq!(config.storage_backend, StorageBackend::Memory);
+        assert_eq!(config.mongo_conn_str, "");
+        assert_eq!(config.account_hash_key, None);
+    }
+
+    #[test]
+    fn rejects_invalid_numbers() {
+        let err = load_err(
+            &[
+                ("REDIS_PORT", "not a port"),
+                ("DEAD_LETTER_AFTER_SECS", "-1"),
+                ("MONGO_MAX_POOL_SIZE", "lots"),
+            ],
+            "",
+        );
+        assert!(err.contains("REDIS_PORT has an invalid value 'not a port'."));
+        assert!(err.contains("DEAD_LETTER_AFTER_SECS has an invalid value '-1'."));
+        assert!(err.contains("MONGO_MAX_POOL_SIZE has an invalid value 'lots'."));
+    }
+
+    #[test]
+    fn enforces_minimums() {
+        assert!(load_err(&[("LEADER_LEASE_SECS", "2")], "")
+            .contains("LEADER_LEASE_SECS must be at least 3."));
+        assert_eq!(
+            load(&[("LEADER_LEASE_SECS", "3")], "")
+                .unwrap()
+                .leader_lease_secs,
+            3
+        );
+        assert!(load_err(&[("CHECK_LOOP_CONCURRENCY", "0")], "")
+            .contains("CHECK_LOOP_CONCURRENCY must be at least 1."));
+        assert!(load_err(&[("GUILD_RETENTION_DAYS", "-1")], "")
+            .contains("GUILD_RETENTION_DAYS can not be negative."));
+    }
+
+    #[test]
+    fn tls_ca_file_needs_tls_enabled() {
+        for tls in [None, Some("false")] {
+            let mut env = vec![("MONGO_TLS_CA_FILE", "/etc/ssl/ca.pem")];
+            env.extend(tls.map(|tls| ("MONGO_TLS", tls)));
+            assert!(load_err(&env, "")
+                .contains("MONGO_TLS_CA_FILE is set but MONGO_TLS is not enabled."));
+        }
+        let config = load(
+            &[
+                ("MONGO_TLS_CA_FILE", "/etc/ssl/ca.pem"),
+                ("MONGO_TLS", "true"),
+            ],
+            "",
+        )
+        .unwrap();
+        assert_eq!(config.mongo.tls, Some(true));
+        assert_eq!(config.mongo.tls_ca_file.as_deref(), Some("/etc/ssl/ca.pem"));
+    }
+
+    #[test]
+    fn reads_the_file() {
+        let config = load(
+            &[],
+            "guild_cache_ttl_secs = 5\ndb_name = \"other\"\ndebug = true",
+        )
+        .unwrap();
+        assert_eq!(config.guild_cache_ttl_secs, 5);
+        assert_eq!(config.db_name, "other");
+        assert!(config.debug);
+    }
+
+    #[test]
+    fn env_overrides_the_file() {
+        let config = load(
+            &[("GUILD_CACHE_TTL_SECS", "0"), ("DB_NAME", "")],
+            "guild_cache_ttl_secs = 5\ndb_name = \"other\"",
+        )
+        .unwrap();
+        assert_eq!(config.guild_cache_ttl_secs, 0);
+        // An empty variable counts as unset, so the file still applies.
+        assert_eq!(config.db_name, "other");
+    }
+
+    #[test]
+    fn shard_count_without_range_runs_every_shard() {
+        let config = load(&[("SHARD_COUNT", "4")], "").unwrap();
+        assert_eq!(
+            config.sharding,
+            Sharding::Range {
+                first: 0,
+                last: 3,
+                total: 4
+            }
+        );
+    }
+
+    #[test]
+    fn parses_shard_ranges() {
+        let config = load(&[("SHARD_COUNT", "4"), ("SHARD_RANGE", "2 - 3")], "").unwrap();
+        assert_eq!(
+            config.sharding,
+            Sharding::Range {
+                first: 2,
+                last: 3,
+                total: 4
+            }
+        );
+        let config = load(&[("SHARD_COUNT", "4"), ("SHARD_RANGE", "1-1")], "").unwrap();
+        assert_eq!(
+            config.sharding,
+            Sharding::Range {
+                first: 1,
+                last: 1,
+                total: 4
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_shard_settings() {
+        assert!(load_err(&[("SHARD_COUNT", "0")], "").contains("SHARD_COUNT must be at least 1."));
+        assert!(load_err(&[("SHARD_RANGE", "0-1")], "")
+            .contains("SHARD_RANGE is set but SHARD_COUNT is not."));
+        for range in ["2-1", "0-4", "3", "a-b", "-1-2"] {
+            let err = load_err(&[("SHARD_COUNT", "4"), ("SHARD_RANGE", range)], "");
+            assert!(
+                err.contains(&format!("got '{}'", range)),
+                "{} was accepted: {}",
+                range,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn owns_guilds_on_its_shards() {
+        let sharding = Sharding::Range {
+            first: 1,
+            last: 2,
+            total: 4,
+        };
+        let on_shard = |shard: u64| (shard << 22) | 12345;
+        assert!(!sharding.owns_guild(on_shard(0)));
+        assert!(sharding.owns_guild(on_shard(1)));
+        assert!(sharding.owns_guild(on_shard(2)));
+        assert!(!sharding.owns_guild(on_shard(3)));
+        // The shard wraps around the total.
+        assert!(sharding.owns_guild(on_shard(5)));
+        assert!(!sharding.owns_guild(on_shard(4)));
+        // The lowest 22 bits never decide the shard.
+        assert!(!sharding.owns_guild((1 << 22) - 1));
+        assert!(Sharding::Auto.owns_guild(0));
+    }
+
+    #[test]
+    fn every_guild_has_exactly_one_owner() {
+        let processes = [
+            Sharding::Range {
+                first: 0,
+                last: 1,
+                total: 5,
+            },
+            Sharding::Range {
+                first: 2,
+                last: 4,
+                total: 5,
+            },
+        ];
+        for shard in 0..20_u64 {
+            let guild_id = (shard << 22) | 99;
+            let owners = processes
+                .iter()
+                .filter(|sharding| sharding.owns_guild(guild_id))
+                .count();
+            assert_eq!(owners, 1, "guild on shard {}", shard);
+        }
+    }
+}
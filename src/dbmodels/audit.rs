@@ -0,0 +1,15 @@
+use mongodb::bson::DateTime;
+use serde::*;
+
+/// Record of a privacy request. It deliberately holds no user identifiers, only what was done
+/// and how much was removed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct PrivacyAuditRecord {
+    pub action: String,
+    pub guild_ID: Option<String>,
+    pub accounts_deleted: u64,
+    pub attempts_deleted: u64,
+    pub redis_keys_deleted: u64,
+    pub timestamp: DateTime,
+}
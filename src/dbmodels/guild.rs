@@ -1,6 +1,9 @@
+use std::collections::BTreeMap;
+
+use mongodb::bson::DateTime;
 use serde::*;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GuildSettings {
     pub zero_point: i64,
     pub difficulty_addition: i64,
@@ -9,26 +12,100 @@ pub struct GuildSettings {
     pub preferred_num_of_accounts: u8,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Guild {
     pub guild_ID: String,
     pub mod_channel_ID: String,
     pub verification_channel_ID: String,
     pub verification_role_ID: String,
+    /// The single mod role of older versions, "0" once it has been removed. Use `mod_roles`.
     pub mod_role_ID: String,
+    #[serde(default)]
+    pub mod_role_IDs: Vec<String>,
+    /// Roles and users allowed to use a mod command without being a mod, by command name.
+    #[serde(default)]
+    pub command_grants: BTreeMap<String, CommandGrant>,
     pub prefix_string: String,
     pub verification_age: u64,
     pub enabled: bool,
     pub verify_on_screening: bool,
     pub verification_logs_channel_ID: String,
     pub guild_settings: GuildSettings,
+    // Documents created before this field existed belong to guilds the bot is still in.
+    #[serde(default = "default_active")]
+    pub active: bool,
+    /// When the bot was removed from the guild, cleared again if it is re-added.
+    #[serde(default)]
+    pub left_at: Option<DateTime>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Grants are edited one list at a time, so either list can be missing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct CommandGrant {
+    #[serde(default)]
+    pub role_IDs: Vec<String>,
+    #[serde(default)]
+    pub user_IDs: Vec<String>,
+}
+
+impl CommandGrant {
+    pub fn is_empty(&self) -> bool {
+        self.role_IDs.is_empty() && self.user_IDs.is_empty()
+    }
+}
+
+impl Guild {
+    /// Every mod role, including the one set by older versions.
+    pub fn mod_roles(&self) -> Vec<&str> {
+        let mut roles: Vec<&str> = self.mod_role_IDs.iter().map(String::as_str).collect();
+        if self.mod_role_ID != "0" && !roles.contains(&self.mod_role_ID.as_str()) {
+            roles.push(&self.mod_role_ID);
+        }
+        roles
+    }
+}
+
+/// Builds the document a guild starts with when the bot first sees it.
+pub fn default_guild(guild_id: &str) -> Guild {
+    Guild {
+        guild_ID: guild_id.to_string(),
+        mod_channel_ID: "0".to_string(),
+        verification_channel_ID: "0".to_string(),
+        verification_role_ID: "0".to_string(),
+        mod_role_ID: "0".to_string(),
+        mod_role_IDs: vec![],
+        command_grants: BTreeMap::new(),
+        prefix_string: "~".to_string(),
+        verification_age: 0,
+        enabled: false,
+        verify_on_screening: false,
+        verification_logs_channel_ID: "0".to_string(),
+        guild_settings: GuildSettings {
+            zero_point: 0,
+            difficulty_addition: 0,
+            mfa_bonus: 0,
+            premium_bonus: 0,
+            preferred_num_of_accounts: 0,
+        },
+        active: true,
+        left_at: None,
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A social account linked to a Discord user. The account ID is only stored as a keyed hash,
+/// plus an encrypted copy when an encryption key is configured.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct SocialMediaAccounts {
+    pub user_ID: String,
     pub account_type: String,
-    pub account_ID: String,
-    pub discord_ID: String,
+    pub account_id_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id_enc: Option<String>,
 }
@@ -1 +1,3 @@
+pub mod audit;
 pub mod guild;
+pub mod verification;
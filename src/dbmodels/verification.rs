@@ -0,0 +1,16 @@
+use mongodb::bson::DateTime;
+use serde::*;
+
+/// One processed verification result, written when a completion is taken off the queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct VerificationAttempt {
+    pub user_ID: String,
+    pub guild_ID: String,
+    /// One of "passed", "failed" or "error".
+    pub outcome: String,
+    pub score: Option<String>,
+    pub min_score: Option<String>,
+    pub reason: Option<String>,
+    pub timestamp: DateTime,
+}
@@ -0,0 +1,221 @@
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, RedisError, Script};
+
+/// Completions that kept failing are moved under this prefix, out of the check loop's way.
+const DEAD_LETTER_PREFIX: &str = "deadletter:";
+
+/// What became of a completion after the check loop worked on it.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// It failed and stays in the queue for the next pass.
+    Retry,
+    /// It has been failing for too long and was moved to the dead letter queue.
+    DeadLettered,
+}
+
+/// A completion in the dead letter queue.
+pub struct DeadLetter {
+    /// The original `complete:{user}:{guild}` key.
+    pub key: String,
+    pub value: Option<String>,
+}
+
+pub fn dead_letter_key(key: &str) -> String {
+    format!("{}{}", DEAD_LETTER_PREFIX, key)
+}
+
+/// Holds when the completion first failed, so one that keeps failing can be told apart from a
+/// short outage.
+pub fn failing_key(key: &str) -> String {
+    format!("failing:{}", key)
+}
+
+/// Records how processing the completion went, judged by whether it is still in the queue. One
+/// that has been failing for `dead_letter_after_secs` is moved to the dead letter queue, 0 keeps
+/// retrying forever.
+pub async fn record_outcome(
+    conn: &mut ConnectionManager,
+    key: &str,
+    dead_letter_after_secs: u64,
+) -> Result<Outcome, String> {
+    let script = Script::new(
+        r"
+        if redis.call('EXISTS', KEYS[1]) == 0 then
+            redis.call('DEL', KEYS[2])
+            return 0
+        end
+        local first = redis.call('GET', KEYS[2])
+        if not first then
+            redis.call('SET', KEYS[2], ARGV[1], 'EX', ARGV[3])
+            return 1
+        end
+        if tonumber(ARGV[2]) > 0 and tonumber(ARGV[1]) - tonumber(first) >= tonumber(ARGV[2]) then
+            redis.call('RENAME', KEYS[1], KEYS[3])
+            redis.call('DEL', KEYS[2])
+            return 2
+        end
+        return 1
+        ",
+    );
+    let now = chrono::Utc::now().timestamp();
+    // Outlives the dead letter deadline, and goes away by itself if the completion vanishes.
+    let failing_ttl = dead_letter_after_secs.max(60 * 60) * 2;
+    let res: Result<i64, RedisError> = script
+        .key(key)
+        .key(failing_key(key))
+        .key(dead_letter_key(key))
+        .arg(now)
+        .arg(dead_letter_after_secs)
+        .arg(failing_ttl)
+        .invoke_async(conn)
+        .await;
+    match res {
+        Ok(0) => Ok(Outcome::Done),
+        Ok(2) => Ok(Outcome::DeadLettered),
+        Ok(_) => Ok(Outcome::Retry),
+        Err(err) => Err(format!("Could not record the outcome of {} - {}", key, err)),
+    }
+}
+
+pub async fn list(conn: &mut ConnectionManager) -> Result<Vec<DeadLetter>, String> {
+    let mut keys: Vec<String> = vec![];
+    match conn
+        .scan_match::<String, String>(format!("{}*", DEAD_LETTER_PREFIX))
+        .await
+    {
+        Ok(mut iter) => {
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+        }
+        Err(err) => return Err(format!("Redis error in scan - {:?}", err)),
+    }
+    keys.sort();
+
+    let mut dead_letters = vec![];
+    for key in keys {
+        let value = match conn.get::<&str, Option<String>>(&key).await {
+            Ok(value) => value,
+            Err(err) => return Err(format!("Could not read key {} - {:?}", key, err)),
+        };
+        dead_letters.push(DeadLetter {
+            key: key[DEAD_LETTER_PREFIX.len()..].to_string(),
+            value,
+        });
+    }
+    Ok(dead_letters)
+}
+
+/// Puts a dead letter back in the queue, where the check loop picks it up on its next pass.
+pub async fn replay(conn: &mut ConnectionManager, key: &str) -> Result<(), String> {
+    let script = Script::new(
+        r"
+        if redis.call('EXISTS', KEYS[1]) == 0 then return 0 end
+        if redis.call('EXISTS', KEYS[2]) == 1 then return -1 end
+        redis.call('RENAME', KEYS[1], KEYS[2])
+        redis.call('DEL', KEYS[3])
+        return 1
+        ",
+    );
+    let res: Result<i64, RedisError> = script
+        .key(dead_letter_key(key))
+        .key(key)
+        .key(failing_key(key))
+        .invoke_async(conn)
+        .await;
+    match res {
+        Ok(1) => Ok(()),
+        Ok(0) => Err(format!("{} is not in the dead letter queue.", key)),
+        Ok(_) => Err(format!("{} is already queued again.", key)),
+        Err(err) => Err(format!("Could not replay {} - {}", key, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Needs a redis server, at REDIS_URL or on localhost.
+    async fn conn() -> ConnectionManager {
+        let url =
+            std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379/".to_string());
+        let client = redis::Client::open(url).unwrap();
+        ConnectionManager::new(client).await.unwrap()
+    }
+
+    async fn exists(conn: &mut ConnectionManager, key: &str) -> bool {
+        conn.exists(key).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn failing_completions_are_dead_lettered_and_replayed() {
+        let mut conn = conn().await;
+        let key = format!("complete:test:{}", rand::random::<u32>());
+        let _: () = conn.set(&key, "value").await.unwrap();
+
+        // The first failure starts the clock.
+        assert_eq!(
+            record_outcome(&mut conn, &key, 60).await.unwrap(),
+            Outcome::Retry
+        );
+        let ttl: i64 = conn.ttl(failing_key(&key)).await.unwrap();
+        assert!(ttl > 0);
+        assert_eq!(
+            record_outcome(&mut conn, &key, 60).await.unwrap(),
+            Outcome::Retry
+        );
+
+        // 0 never gives up, however long it has been failing.
+        let long_ago = chrono::Utc::now().timestamp() - 120;
+        let _: () = conn.set(failing_key(&key), long_ago).await.unwrap();
+        assert_eq!(
+            record_outcome(&mut conn, &key, 0).await.unwrap(),
+            Outcome::Retry
+        );
+
+        assert_eq!(
+            record_outcome(&mut conn, &key, 60).await.unwrap(),
+            Outcome::DeadLettered
+        );
+        assert!(!exists(&mut conn, &key).await);
+        assert!(!exists(&mut conn, &failing_key(&key)).await);
+        let dead_letters = list(&mut conn).await.unwrap();
+        let dead_letter = dead_letters.iter().find(|dl| dl.key == key).unwrap();
+        assert_eq!(dead_letter.value.as_deref(), Some("value"));
+
+        replay(&mut conn, &key).await.unwrap();
+        let value: Option<String> = conn.get(&key).await.unwrap();
+        assert_eq!(value.as_deref(), Some("value"));
+        assert!(!exists(&mut conn, &dead_letter_key(&key)).await);
+        assert!(replay(&mut conn, &key).await.is_err());
+
+        // Once the completion is gone it counts as done and the clock is cleared.
+        assert_eq!(
+            record_outcome(&mut conn, &key, 60).await.unwrap(),
+            Outcome::Retry
+        );
+        let _: () = conn.del(&key).await.unwrap();
+        assert_eq!(
+            record_outcome(&mut conn, &key, 60).await.unwrap(),
+            Outcome::Done
+        );
+        assert!(!exists(&mut conn, &failing_key(&key)).await);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn replay_keeps_a_completion_that_was_queued_again() {
+        let mut conn = conn().await;
+        let key = format!("complete:test:{}", rand::random::<u32>());
+        let _: () = conn.set(dead_letter_key(&key), "old").await.unwrap();
+        let _: () = conn.set(&key, "new").await.unwrap();
+
+        assert!(replay(&mut conn, &key).await.is_err());
+        let value: Option<String> = conn.get(&key).await.unwrap();
+        assert_eq!(value.as_deref(), Some("new"));
+
+        let _: () = conn.del(&[&key, &dead_letter_key(&key)]).await.unwrap();
+    }
+}
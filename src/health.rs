@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use serenity::gateway::ConnectionStage;
+
+use crate::mongo_conn;
+use crate::state::AppState;
+
+/// How long a dependency gets to answer a health check ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The last known connection stage of every shard, kept up to date by the event handler.
+#[derive(Default)]
+pub struct GatewayHealth {
+    stages: Mutex<HashMap<u64, ConnectionStage>>,
+}
+
+impl GatewayHealth {
+    pub fn set_stage(&self, shard_id: u64, stage: ConnectionStage) {
+        if let Ok(mut stages) = self.stages.lock() {
+            stages.insert(shard_id, stage);
+        }
+    }
+
+    /// Ok when at least one shard is known and every known shard is connected.
+    fn check(&self) -> Result<String, String> {
+        let stages = match self.stages.lock() {
+            Ok(stages) => stages,
+            Err(_) => return Err("Gateway state lock was poisoned.".to_string()),
+        };
+        if stages.is_empty() {
+            return Err("No shard has connected yet.".to_string());
+        }
+        let mut down: Vec<String> = stages
+            .iter()
+            .filter(|(_, stage)| **stage != ConnectionStage::Connected)
+            .map(|(shard, stage)| format!("shard {} is {}", shard, stage))
+            .collect();
+        down.sort();
+        if down.is_empty() {
+            Ok(format!("{} shard(s) connected", stages.len()))
+        } else {
+            Err(down.join(", "))
+        }
+    }
+}
+
+/// Runs the checks and returns whether all of them passed, along with a JSON report.
+///
+/// Liveness only covers what a restart can fix, the gateway and the check loop. Readiness also
+/// requires Mongo and Redis to be reachable.
+pub async fn report(state: &AppState, readiness: bool) -> (bool, Value) {
+    let mut checks = vec![
+        ("gateway", state.gateway.check()),
+        ("check_loop", check_loop(state)),
+    ];
+    if readiness {
+        checks.push(("mongo", check_mongo(state).await));
+        checks.push(("redis", check_redis(state).await));
+    }
+
+    let healthy = checks.iter().all(|(_, res)| res.is_ok());
+    let mut details = serde_json::Map::new();
+    for (name, res) in checks {
+        let detail = match res {
+            Ok(detail) => json!({"ok": true, "detail": detail}),
+            Err(detail) => json!({"ok": false, "detail": detail}),
+        };
+        details.insert(name.to_string(), detail);
+    }
+    let status = if healthy { "ok" } else { "degraded" };
+    (healthy, json!({"status": status, "checks": details}))
+}
+
+async fn check_mongo(state: &AppState) -> Result<String, String> {
+    let client = match &state.mongo {
+        Some(client) => client,
+        None => return Ok("in-memory storage, no database in use".to_string()),
+    };
+    match tokio::time::timeout(PING_TIMEOUT, mongo_conn::ping(client)).await {
+        Ok(Ok(())) => Ok("reachable".to_string()),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err("ping timed out".to_string()),
+    }
+}
+
+async fn check_redis(state: &AppState) -> Result<String, String> {
+    let mut conn = state.redis.clone();
+    let ping = redis::cmd("PING");
+    match tokio::time::timeout(PING_TIMEOUT, ping.query_async::<_, String>(&mut conn)).await {
+        Ok(Ok(_)) => Ok("reachable".to_string()),
+        Ok(Err(err)) => Err(format!("Could not reach Redis - {}", err)),
+        Err(_) => Err("ping timed out".to_string()),
+    }
+}
+
+fn check_loop(state: &AppState) -> Result<String, String> {
+    let since = state.check_loop.seconds_since_success();
+    let detail = format!("last successful pass {} seconds ago", since);
+    if since >= state.config.check_loop_alert_secs as i64 {
+        Err(detail)
+    } else {
+        Ok(detail)
+    }
+}
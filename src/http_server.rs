@@ -0,0 +1,63 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tracing::*;
+
+use crate::health;
+use crate::metrics::metrics;
+use crate::state::AppState;
+
+/// Serves the operational endpoints until the process exits.
+pub async fn serve(addr: SocketAddr, state: Arc<AppState>) {
+    let make_service = make_service_fn(move |_conn| {
+        let state = Arc::clone(&state);
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(req, Arc::clone(&state)))) }
+    });
+
+    info!("Serving /metrics, /healthz and /readyz on http://{}", addr);
+    if let Err(err) = Server::bind(&addr).serve(make_service).await {
+        error!("HTTP server stopped - {}", err);
+    }
+}
+
+async fn handle(req: Request<Body>, state: Arc<AppState>) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => match metrics().gather() {
+            Ok(text) => Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(Body::from(text)),
+            Err(err) => {
+                error!("{}", err);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+            }
+        },
+        (&Method::GET, "/healthz") => health_response(&state, false).await,
+        (&Method::GET, "/readyz") => health_response(&state, true).await,
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    // The builder only fails on invalid headers, and every header above is static.
+    Ok(response.unwrap())
+}
+
+async fn health_response(
+    state: &AppState,
+    readiness: bool,
+) -> Result<Response<Body>, hyper::http::Error> {
+    let (healthy, report) = health::report(state, readiness).await;
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(report.to_string()))
+}
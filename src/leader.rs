@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use redis::{RedisError, Script};
+use tracing::*;
+
+use crate::config::Sharding;
+use crate::metrics::metrics;
+use crate::state::AppState;
+
+/// Extends the lease, but only while it is still held by this instance.
+const RENEW_SCRIPT: &str = r"if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('EXPIRE', KEYS[1], ARGV[2]) end return 0";
+/// Gives the lease up, but only while it is still held by this instance.
+const RELEASE_SCRIPT: &str =
+    r"if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) end return 0";
+
+/// Tracks whether this replica holds the Redis lease that lets it run the background jobs.
+pub struct Leadership {
+    /// Replicas only compete with others running the same shards.
+    key: String,
+    /// Identifies this replica as the lease holder.
+    token: String,
+    leader: AtomicBool,
+}
+
+impl Leadership {
+    pub fn new(sharding: Sharding) -> Leadership {
+        let scope = match sharding {
+            Sharding::Auto => "all".to_string(),
+            Sharding::Range { first, last, total } => format!("{}-{}of{}", first, last, total),
+        };
+        Leadership {
+            key: format!("leader:{}", scope),
+            token: format!("{:016x}", rand::random::<u64>()),
+            leader: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Relaxed)
+    }
+
+    fn set_leader(&self, leader: bool) {
+        if self.leader.swap(leader, Ordering::Relaxed) == leader {
+            return;
+        }
+        if leader {
+            info!("This replica is now the leader and runs the background jobs.");
+        } else {
+            warn!("This replica is no longer the leader, background jobs are paused.");
+        }
+        metrics().leader.set(leader as i64);
+        metrics().leader_changes.inc();
+    }
+}
+
+/// Keeps trying to take the lease, and keeps renewing it once held. Checks three times per lease
+/// so a standby takes over within seconds of the leader going away.
+pub async fn run_election(state: Arc<AppState>) {
+    let lease = state.config.leader_lease_secs;
+    let interval = Duration::from_secs(std::cmp::max(1, lease / 3));
+    let leadership = &state.leader;
+    let mut conn = state.redis.clone();
+    loop {
+        if state.shutdown.is_requested() {
+            return;
+        }
+        let res: Result<bool, RedisError> = if leadership.is_leader() {
+            Script::new(RENEW_SCRIPT)
+                .key(&leadership.key)
+                .arg(&leadership.token)
+                .arg(lease)
+                .invoke_async::<_, i64>(&mut conn)
+                .await
+                .map(|renewed| renewed == 1)
+        } else {
+            redis::cmd("SET")
+                .arg(&leadership.key)
+                .arg(&leadership.token)
+                .arg("NX")
+                .arg("EX")
+                .arg(lease)
+                .query_async::<_, Option<String>>(&mut conn)
+                .await
+                .map(|set| set.is_some())
+        };
+        match res {
+            Ok(leader) => leadership.set_leader(leader),
+            Err(err) => {
+                // The lease can't be confirmed, so stop before another replica may take over.
+                metrics().dependency_error("redis");
+                error!("Could not reach redis for leader election - {}", err);
+                leadership.set_leader(false);
+            }
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Hands the lease over on shutdown, so a standby doesn't have to wait for it to expire.
+pub async fn resign(state: &AppState) {
+    let leadership = &state.leader;
+    if !leadership.is_leader() {
+        return;
+    }
+    leadership.set_leader(false);
+    let mut conn = state.redis.clone();
+    let res: Result<i64, RedisError> = Script::new(RELEASE_SCRIPT)
+        .key(&leadership.key)
+        .arg(&leadership.token)
+        .invoke_async(&mut conn)
+        .await;
+    match res {
+        Ok(_) => info!("Released the leader lease."),
+        Err(err) => warn!("Could not release the leader lease - {}", err),
+    }
+}
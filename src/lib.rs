@@ -0,0 +1,21 @@
+//! Everything except the gateway event handler, shared by the bot and the `ironic_ctl` operator
+//! tool.
+
+pub mod account_crypto;
+pub mod application_commands;
+pub mod commands;
+pub mod config;
+pub mod dbmodels;
+pub mod dead_letters;
+pub mod health;
+pub mod http_server;
+pub mod leader;
+pub mod metrics;
+pub mod mongo_conn;
+pub mod redis_check_loop;
+pub mod redis_conn;
+pub mod repository;
+pub mod retention;
+pub mod shutdown;
+pub mod startup;
+pub mod state;
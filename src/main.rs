@@ -1,33 +1,44 @@
-mod application_commands;
-mod commands;
-mod dbmodels;
-mod mongo_conn;
-mod redis_check_loop;
-mod startup;
-
 use serenity::model::application::interaction::Interaction;
 
 use std::{
-    env,
+    process,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, RwLock,
     },
     time::Duration,
 };
 
-use mongo_conn::get_mongo_client;
 use serenity::{
-    async_trait, framework::StandardFramework, model::prelude::GuildId, model::prelude::*,
-    prelude::*,
+    async_trait, client::bridge::gateway::event::ShardStageUpdateEvent,
+    framework::StandardFramework, gateway::ConnectionStage, http::Http, model::prelude::GuildId,
+    model::prelude::*, prelude::*,
 };
-use tracing::{error, info, warn, debug};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
 
-use crate::{redis_check_loop::check_redis, startup::insert_guilds};
+use ironic_bot::{
+    account_crypto, application_commands,
+    application_commands::CommandScope,
+    commands::middleware::Cooldowns,
+    config::{Config, Sharding},
+    health::GatewayHealth,
+    http_server, leader,
+    leader::Leadership,
+    metrics::ErrorCountLayer,
+    redis_check_loop::{supervise_check_loop, CheckLoopHealth},
+    redis_conn,
+    repository::cached::{listen_for_invalidations, CachedGuildRepository},
+    repository::Repositories,
+    retention::run_retention,
+    shutdown,
+    shutdown::Shutdown,
+    startup::{insert_guilds, migrate_account_ids},
+    state::{app_state, AppState},
+};
 
 struct Handler {
-    mongodb_client: mongodb::Client,
-    redis_client: redis::Client,
     is_loop_running: AtomicBool,
 }
 
@@ -37,44 +48,14 @@ impl EventHandler for Handler {
     // case you have for this.
     async fn cache_ready(&self, ctx: Context, _guilds: Vec<GuildId>) {
         info!("Cache is ready, starting the redis-check-loop");
+        let state = app_state(&ctx).await;
         let ctx = Arc::new(ctx);
 
-        let mongo_conn_str = env::var("MONGO_CONN_STR").expect("Need a MongoDB connection string.");
-        let mongodb_client = match get_mongo_client(mongo_conn_str.as_str()).await {
-            Ok(client) => client,
-            Err(err) => {
-                panic!("Could not get mongoDB client. {:?}", err)
-            }
-        };
-
-        let redis_url = env::var("REDIS_HOST").expect("Need a Redis connection string.");
-        let redis_client = match redis::Client::open(format!("redis://{}/", redis_url)) {
-            Ok(client) => client,
-            Err(err) => {
-                panic!("Could not get redis client. {:?}", err)
-            }
-        };
-
-        let mongo_client = Arc::new(mongodb_client);
-        let redis_client = Arc::new(redis_client);
-
         if !self.is_loop_running.load(Ordering::Relaxed) {
             info!("Starting the redis check loop");
-            let ctx1 = Arc::clone(&ctx);
-            let mongo_client1 = Arc::clone(&mongo_client);
-            let redis_client1 = Arc::clone(&redis_client);
-
-            tokio::spawn(async move {
-                loop {
-                    check_redis(
-                        Arc::clone(&ctx1),
-                        Arc::clone(&mongo_client1),
-                        Arc::clone(&redis_client1),
-                    )
-                    .await;
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                }
-            });
+            tokio::spawn(supervise_check_loop(Arc::clone(&ctx), Arc::clone(&state)));
+
+            tokio::spawn(run_retention(Arc::clone(&state)));
 
             // Now that the loop is running, we set the bool to true
             self.is_loop_running.swap(true, Ordering::Relaxed);
@@ -85,84 +66,203 @@ impl EventHandler for Handler {
 
     async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
-        // let clear_commands = false;
-        // if clear_commands {
-        //     application_commands::clear(&ctx).await;
-        // }
-
-        let mongo_conn_str = env::var("MONGO_CONN_STR").expect("Need a MongoDB connection string.");
-        let client = match get_mongo_client(mongo_conn_str.as_str()).await {
-            Ok(client) => client,
-            Err(_) => {
-                panic!("Could not get mongoDB client.")
-            }
-        };
-        if let Err(err) = insert_guilds(&ctx, &client).await {
+        let state = app_state(&ctx).await;
+        state
+            .gateway
+            .set_stage(ctx.shard_id, ConnectionStage::Connected);
+        if let Err(err) = insert_guilds(&ctx, &state.repos).await {
+            warn!("{:?}", err)
+        }
+        if let Err(err) = migrate_account_ids(&state.repos).await {
             warn!("{:?}", err)
         }
 
-        application_commands::register(&ctx).await;
+        // Every shard gets a ready, but the commands only need syncing once.
+        if ctx.shard_id == 0 {
+            let registered =
+                application_commands::register(&ctx.http, CommandScope::from_config(&state.config))
+                    .await;
+            if let Some(registered) = registered {
+                application_commands::remember_command_ids(&state, &registered);
+            }
+        }
+    }
+
+    async fn shard_stage_update(&self, ctx: Context, event: ShardStageUpdateEvent) {
+        debug!(
+            "Shard {} went from {} to {}",
+            event.shard_id.0, event.old, event.new
+        );
+        let state = app_state(&ctx).await;
+        state.gateway.set_stage(event.shard_id.0, event.new);
+    }
+
+    async fn guild_create(&self, ctx: Context, guild: Guild, is_new: bool) {
+        // This also fires for every guild once the shard connects, the upsert makes that a no-op.
+        debug!("Guild create for {} (new: {})", guild.id.0, is_new);
+        let state = app_state(&ctx).await;
+        if let Err(err) = state.repos.guilds.activate(&guild.id.0.to_string()).await {
+            error!("{}", err);
+            return;
+        }
+        // Only reaches Discord when the guild's overrides changed since they were last synced,
+        // so reconnects don't resend them for every guild.
+        if let Err(err) =
+            application_commands::sync_command_permissions(&ctx, &state, guild.id).await
+        {
+            error!("{}", err);
+            application_commands::report_sync_failure(&ctx, &state, guild.id, &err).await;
+        }
+    }
+
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, _full: Option<Guild>) {
+        // An unavailable guild is a Discord outage, the bot has not actually been removed.
+        if incomplete.unavailable {
+            warn!("Guild {} became unavailable", incomplete.id.0);
+            return;
+        }
+        info!("Removed from guild {}", incomplete.id.0);
+        let state = app_state(&ctx).await;
+        if let Err(err) = state
+            .repos
+            .guilds
+            .deactivate(&incomplete.id.0.to_string())
+            .await
+        {
+            error!("{}", err)
+        }
     }
 
     async fn interaction_create(&self, _ctx: Context, _interaction: Interaction) {
         // If the interaction is an Application Command then name the interaction applicationCommand
         // and move on to the evaluate the block
-        application_commands::handle_interactions(
-            &_ctx,
-            _interaction,
-            &self.mongodb_client,
-            &self.redis_client,
-        )
-        .await
+        let state = app_state(&_ctx).await;
+        application_commands::handle_interactions(&_ctx, _interaction, &state).await
     }
 }
 
 #[tokio::main]
 async fn main() {
     // Initialize the tracing subscriber
-    tracing_subscriber::fmt().json().init();
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_filter(LevelFilter::INFO),
+        )
+        .with(ErrorCountLayer)
+        .init();
     info!("Starting the bot...");
 
-    let framework = StandardFramework::new().configure(|c| c.prefix("~")); // set the bot's prefix to "~"
-
-    // Login with a bot token from the environment
-    let token = env::var("DISCORD_TOKEN").expect("Missing token in env!");
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    };
+    account_crypto::configure(&config);
 
-    let application_id: u64 = env::var("APPLICATION_ID")
-        .expect("Expected an application id in the environment")
-        .parse()
-        .expect("application id is not a valid id");
+    let framework = StandardFramework::new().configure(|c| c.prefix("~")); // set the bot's prefix to "~"
 
-    let mongo_conn_str = env::var("MONGO_CONN_STR").expect("Need a MongoDB connection string.");
-    let mongodb_client = match get_mongo_client(mongo_conn_str.as_str()).await {
-        Ok(client) => client,
+    let (mut repos, mongo) = match Repositories::connect(&config).await {
+        Ok(connected) => connected,
         Err(err) => {
-            panic!("Could not get mongoDB client. {:?}", err)
+            error!("{}", err);
+            process::exit(1);
         }
     };
 
-    let redis_url = env::var("REDIS_HOST").expect("Need a MongoDB connection string.");
-    let redis_client = match redis::Client::open(format!("redis://{}/", redis_url)) {
+    let redis_client = match redis::Client::open(config.redis_url()) {
         Ok(client) => client,
-        Err(e) => panic!("Could not get redis client. {}", e),
+        Err(err) => {
+            error!("Invalid redis settings - {}", err);
+            process::exit(1);
+        }
     };
+    let max_backoff = Duration::from_secs(config.redis_max_backoff_secs);
+    let redis = redis_conn::connect(redis_client.clone(), max_backoff).await;
+
+    if config.guild_cache_ttl_secs > 0 {
+        let cache = Arc::new(CachedGuildRepository::new(
+            Arc::clone(&repos.guilds),
+            Duration::from_secs(config.guild_cache_ttl_secs),
+            redis.clone(),
+        ));
+        tokio::spawn(listen_for_invalidations(
+            redis_client,
+            Arc::clone(&cache),
+            max_backoff,
+        ));
+        repos.guilds = cache;
+    }
 
+    let token = config.discord_token.clone();
+    let application_id = config.application_id;
+    let http_addr = config.http_addr;
+    let sharding = config.sharding;
+    let guild_members_intent = config.guild_members_intent;
+    let command_permissions = config
+        .command_permissions_token
+        .as_ref()
+        .map(|token| Http::new_with_application_id(&format!("Bearer {}", token), application_id));
+    if command_permissions.is_none() {
+        warn!("COMMAND_PERMISSIONS_TOKEN is not set, so mod commands are shown to every member and only the bot checks the mod roles.");
+    } else {
+        warn!("Mod commands are only hidden in servers the COMMAND_PERMISSIONS_TOKEN user can manage, and only until the token expires.");
+    }
+    let state = Arc::new(AppState {
+        config: Arc::new(config),
+        repos,
+        mongo,
+        redis,
+        check_loop: CheckLoopHealth::new(),
+        gateway: GatewayHealth::default(),
+        leader: Leadership::new(sharding),
+        cooldowns: Cooldowns::default(),
+        command_permissions,
+        command_ids: RwLock::new(None),
+        shutdown: Shutdown::default(),
+    });
+    tokio::spawn(leader::run_election(Arc::clone(&state)));
+    if let Some(addr) = http_addr {
+        tokio::spawn(http_server::serve(addr, Arc::clone(&state)));
+    }
     let handler = Handler {
-        mongodb_client,
-        redis_client,
         is_loop_running: AtomicBool::new(false),
     };
-    let intents = GatewayIntents::GUILD_INTEGRATIONS
-    | GatewayIntents::GUILDS;
+    let mut intents = GatewayIntents::GUILD_INTEGRATIONS | GatewayIntents::GUILDS;
+    // Keeps members in the cache, which saves the check loop a request per completion. Without it
+    // members are fetched over HTTP.
+    if guild_members_intent {
+        intents |= GatewayIntents::GUILD_MEMBERS;
+    }
     let mut client = Client::builder(token, intents)
         .event_handler(handler)
         .framework(framework)
         .application_id(application_id)
+        .type_map_insert::<AppState>(Arc::clone(&state))
         .await
         .expect("Error creating client");
 
-    // start listening for events by starting a single shard
-    if let Err(why) = client.start().await {
+    tokio::spawn(shutdown::handle_signals(
+        state,
+        Arc::clone(&client.shard_manager),
+    ));
+
+    let res = match sharding {
+        Sharding::Auto => {
+            info!("Starting with the recommended number of shards");
+            client.start_autosharded().await
+        }
+        Sharding::Range { first, last, total } => {
+            info!("Starting shards {} to {} of {}", first, last, total);
+            client.start_shard_range([first, last], total).await
+        }
+    };
+    if let Err(why) = res {
         error!("An error occurred while running the client: {:?}", why);
     }
+    info!("The bot has shut down.");
+    shutdown::flush_logs();
 }
@@ -0,0 +1,194 @@
+use std::sync::LazyLock;
+
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent};
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Everything the bot exports to Prometheus.
+pub struct Metrics {
+    registry: Registry,
+    /// Verification links handed out by /verify, per guild.
+    pub verifications_started: IntCounterVec,
+    /// Processed completions per guild and outcome (passed, failed or error).
+    pub verifications: IntCounterVec,
+    /// Members that skipped verification because their account is old enough, per guild.
+    pub age_bypasses: IntCounterVec,
+    pub role_grant_failures: IntCounterVec,
+    /// Completion keys found in the last pass of the check loop.
+    pub queue_depth: IntGauge,
+    pub check_loop_seconds: Histogram,
+    pub dead_letters: IntCounter,
+    pub commands: IntCounterVec,
+    /// Commands stopped by one of their guards, per command and guard.
+    pub command_rejections: IntCounterVec,
+    pub command_seconds: HistogramVec,
+    /// Commands that panicked.
+    pub command_errors: IntCounterVec,
+    /// 1 while this replica holds the leader lease.
+    pub leader: IntGauge,
+    pub leader_changes: IntCounter,
+    /// Failed calls to Discord, Mongo or Redis.
+    pub dependency_errors: IntCounterVec,
+    /// Error level log events, per module.
+    pub logged_errors: IntCounterVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let metrics = Metrics {
+            registry: Registry::new(),
+            verifications_started: counter_vec(
+                "verifications_started_total",
+                "Verification links handed out.",
+                &["guild"],
+            ),
+            verifications: counter_vec(
+                "verifications_total",
+                "Processed verification results.",
+                &["guild", "outcome"],
+            ),
+            age_bypasses: counter_vec(
+                "verification_age_bypasses_total",
+                "Members verified by account age alone.",
+                &["guild"],
+            ),
+            role_grant_failures: counter_vec(
+                "role_grant_failures_total",
+                "Verified members that could not be given the verified role.",
+                &["guild"],
+            ),
+            queue_depth: IntGauge::new(
+                "completion_queue_depth",
+                "Completed verifications waiting to be processed.",
+            )
+            .unwrap(),
+            check_loop_seconds: Histogram::with_opts(HistogramOpts::new(
+                "check_loop_iteration_seconds",
+                "Time taken by one pass of the redis check loop.",
+            ))
+            .unwrap(),
+            dead_letters: IntCounter::new(
+                "completions_dead_lettered_total",
+                "Completions moved to the dead letter queue after failing for too long.",
+            )
+            .unwrap(),
+            commands: counter_vec(
+                "commands_total",
+                "Application commands invoked.",
+                &["command"],
+            ),
+            command_rejections: counter_vec(
+                "command_rejections_total",
+                "Commands and components stopped by a guard.",
+                &["command", "guard"],
+            ),
+            command_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    "command_duration_seconds",
+                    "Time taken to handle a command or component.",
+                ),
+                &["command"],
+            )
+            .unwrap(),
+            command_errors: counter_vec(
+                "command_errors_total",
+                "Commands and components that panicked.",
+                &["command"],
+            ),
+            leader: IntGauge::new("leader", "Whether this replica runs the background jobs.")
+                .unwrap(),
+            leader_changes: IntCounter::new(
+                "leader_changes_total",
+                "Times this replica became or stopped being the leader.",
+            )
+            .unwrap(),
+            dependency_errors: counter_vec(
+                "dependency_errors_total",
+                "Failed calls to Discord, Mongo or Redis.",
+                &["service"],
+            ),
+            logged_errors: counter_vec(
+                "logged_errors_total",
+                "Error level log events.",
+                &["target"],
+            ),
+        };
+
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(metrics.verifications_started.clone()),
+            Box::new(metrics.verifications.clone()),
+            Box::new(metrics.age_bypasses.clone()),
+            Box::new(metrics.role_grant_failures.clone()),
+            Box::new(metrics.queue_depth.clone()),
+            Box::new(metrics.check_loop_seconds.clone()),
+            Box::new(metrics.dead_letters.clone()),
+            Box::new(metrics.commands.clone()),
+            Box::new(metrics.command_rejections.clone()),
+            Box::new(metrics.command_seconds.clone()),
+            Box::new(metrics.command_errors.clone()),
+            Box::new(metrics.leader.clone()),
+            Box::new(metrics.leader_changes.clone()),
+            Box::new(metrics.dependency_errors.clone()),
+            Box::new(metrics.logged_errors.clone()),
+        ];
+        for collector in collectors {
+            // Registering only fails on duplicate names, which would be a bug in the list above.
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn gather(&self) -> Result<String, String> {
+        let mut buffer = vec![];
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            return Err(format!("Could not encode metrics - {:?}", err));
+        }
+        match String::from_utf8(buffer) {
+            Ok(text) => Ok(text),
+            Err(err) => Err(format!("Metrics are not valid UTF-8 - {:?}", err)),
+        }
+    }
+
+    pub fn dependency_error(&self, service: &str) {
+        self.dependency_errors.with_label_values(&[service]).inc();
+    }
+}
+
+fn counter_vec(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    IntCounterVec::new(Opts::new(name, help), labels).unwrap()
+}
+
+/// Counts every error logged through `tracing`, so existing `error!` calls show up as metrics.
+pub struct ErrorCountLayer;
+
+impl<S: Subscriber> Layer<S> for ErrorCountLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() == Level::ERROR {
+            metrics()
+                .logged_errors
+                .with_label_values(&[event.metadata().target()])
+                .inc();
+        }
+    }
+}
+
+/// Counts failed Mongo commands, whichever repository issued them.
+pub struct MongoErrorCounter;
+
+impl CommandEventHandler for MongoErrorCounter {
+    fn handle_command_failed_event(&self, _event: CommandFailedEvent) {
+        metrics().dependency_error("mongo");
+    }
+}
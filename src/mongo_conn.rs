@@ -1,41 +1,65 @@
-use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 
-use mongodb::options::{ClientOptions, CollectionOptions, ResolverConfig};
+use mongodb::bson::doc;
+use mongodb::options::{ClientOptions, ResolverConfig, Tls, TlsOptions};
 use mongodb::*;
-pub async fn get_mongo_client(connection_str: &str) -> mongodb::error::Result<Client> {
-    let platform = env::var("PLATFORM").expect("No PLATFORM env set.");
 
-    match platform.as_str() {
-        "windows" => {
-            let client_options = ClientOptions::parse_with_resolver_config(
-                connection_str,
-                ResolverConfig::cloudflare(),
-            )
-            .await?;
-            Client::with_options(client_options)
-        }
-        "linux" => {
-            let client_options = ClientOptions::parse(connection_str).await?;
-            Client::with_options(client_options)
+use crate::config::{Config, MongoResolver};
+use crate::metrics::MongoErrorCounter;
+
+/// Builds the client from the connection string and the mongo settings in the config.
+pub async fn get_mongo_client(config: &Config) -> mongodb::error::Result<Client> {
+    let settings = &config.mongo;
+    let mut client_options = match settings.resolver {
+        MongoResolver::System => ClientOptions::parse(&config.mongo_conn_str).await?,
+        resolver => {
+            let resolver_config = match resolver {
+                MongoResolver::Cloudflare => ResolverConfig::cloudflare(),
+                MongoResolver::Google => ResolverConfig::google(),
+                _ => ResolverConfig::quad9(),
+            };
+            ClientOptions::parse_with_resolver_config(&config.mongo_conn_str, resolver_config)
+                .await?
         }
-        _ => {
-            panic!("Invalid PLATFORM env value.")
+    };
+
+    client_options.app_name = Some(settings.app_name.clone());
+    client_options.command_event_handler = Some(Arc::new(MongoErrorCounter));
+    if settings.max_pool_size.is_some() {
+        client_options.max_pool_size = settings.max_pool_size;
+    }
+    if settings.min_pool_size.is_some() {
+        client_options.min_pool_size = settings.min_pool_size;
+    }
+    if let Some(ms) = settings.connect_timeout_ms {
+        client_options.connect_timeout = Some(Duration::from_millis(ms));
+    }
+    if let Some(ms) = settings.server_selection_timeout_ms {
+        client_options.server_selection_timeout = Some(Duration::from_millis(ms));
+    }
+    match settings.tls {
+        Some(true) => {
+            let tls_options = TlsOptions::builder()
+                .ca_file_path(settings.tls_ca_file.as_ref().map(Into::into))
+                .build();
+            client_options.tls = Some(Tls::Enabled(tls_options));
         }
+        Some(false) => client_options.tls = Some(Tls::Disabled),
+        None => {}
     }
-}
 
-pub async fn get_db(client: &Client, db_name: &str) -> Database {
-    client.database(db_name)
+    Client::with_options(client_options)
 }
 
-pub async fn get_collection<T>(
-    db: &Database,
-    collection_str: &str,
-    options: Option<CollectionOptions>,
-) -> Collection<T> {
-    let col: Collection<T> = match options {
-        None => db.collection(collection_str),
-        Some(options) => db.collection_with_options(collection_str, options),
-    };
-    col
+/// Checks that the server can actually be reached. Creating the client does not connect.
+pub async fn ping(client: &Client) -> Result<(), String> {
+    match client
+        .database("admin")
+        .run_command(doc! {"ping": 1}, None)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Could not reach MongoDB - {}", err)),
+    }
 }
@@ -1,35 +1,148 @@
+use crate::config::Sharding;
 use crate::dbmodels::guild::Guild as GuildDoc;
+use crate::dbmodels::verification::VerificationAttempt;
+use crate::dead_letters::{self, Outcome};
+use crate::metrics::metrics;
+use crate::redis_conn::Backoff;
+use crate::repository::Repositories;
+use crate::state::AppState;
 use chrono::Utc;
-use mongodb::bson::doc;
-use redis::{AsyncCommands, RedisError};
-use serenity::{client::Context, utils::Colour};
-use std::{env, sync::Arc};
+use mongodb::bson::DateTime;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, RedisError, Script};
+use serenity::model::prelude::{Channel, Member};
+use serenity::{client::Context, utils::Colour, Result as SerenityResult};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tracing::*;
 
-pub async fn check_redis(
-    ctx: Arc<Context>,
-    mongo_client: Arc<mongodb::Client>,
-    redis_client: Arc<redis::Client>,
-) {
-    // Format for completed verification keys is: complete:{userid}:{guildid}
-    // Value must be either 'true' or 'false'
-    let mut conn = match redis_client.get_async_connection().await {
-        Ok(conn) => {
-            info!("Connection to Redis has been established.");
-            conn
+/// How long a claimed completion stays reserved. Covers an instance that dies mid way, after
+/// which the completion is picked up again.
+const CLAIM_TTL_SECS: usize = 120;
+
+/// Tracks when the check loop last got through a full pass, so an outage can be noticed.
+pub struct CheckLoopHealth {
+    last_success: AtomicI64,
+}
+
+impl CheckLoopHealth {
+    pub fn new() -> CheckLoopHealth {
+        CheckLoopHealth {
+            last_success: AtomicI64::new(Utc::now().timestamp()),
         }
-        Err(err) => {
-            error!("Error getting connection to redis - {:?}", err);
-            panic!("Error getting connection to redis - {:?}", err);
+    }
+
+    fn mark_success(&self) {
+        self.last_success
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn seconds_since_success(&self) -> i64 {
+        Utc::now().timestamp() - self.last_success.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for CheckLoopHealth {
+    fn default() -> CheckLoopHealth {
+        CheckLoopHealth::new()
+    }
+}
+
+/// Runs the check loop, restarting the task whenever it stops or panics.
+pub async fn supervise_check_loop(ctx: Arc<Context>, state: Arc<AppState>) {
+    tokio::spawn(watch_check_loop(Arc::clone(&state)));
+
+    let max_backoff = Duration::from_secs(state.config.redis_max_backoff_secs);
+    let mut backoff = Backoff::new(max_backoff);
+    loop {
+        let started = Instant::now();
+        let task = tokio::spawn(run_check_loop(Arc::clone(&ctx), Arc::clone(&state)));
+        if let Err(err) = task.await {
+            error!("The redis check loop died - {}", err);
         }
-    };
-    let is_debug = if let Ok(val) = env::var("DEBUG") {
-        val.parse().unwrap_or(false)
-    } else {
-        false
-    };
+        if state.shutdown.is_requested() {
+            info!("The redis check loop has stopped for shutdown.");
+            return;
+        }
+        // A loop that was healthy for a while before dying is restarted right away.
+        if started.elapsed() > max_backoff {
+            backoff.reset();
+        }
+        let delay = backoff.next_delay();
+        warn!("Restarting the redis check loop in {}s", delay.as_secs());
+        tokio::time::sleep(delay).await;
+    }
+}
 
-    if is_debug {
+async fn run_check_loop(ctx: Arc<Context>, state: Arc<AppState>) {
+    let mut backoff = Backoff::new(Duration::from_secs(state.config.redis_max_backoff_secs));
+    loop {
+        let pass = match state.shutdown.begin_pass().await {
+            Some(pass) => pass,
+            None => return,
+        };
+        if !state.leader.is_leader() {
+            // A standby has nothing to process, which still counts as a healthy pass.
+            drop(pass);
+            state.check_loop.mark_success();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        let timer = metrics().check_loop_seconds.start_timer();
+        let res = check_redis(Arc::clone(&ctx), Arc::clone(&state)).await;
+        timer.observe_duration();
+        drop(pass);
+        match res {
+            Ok(()) => {
+                state.check_loop.mark_success();
+                backoff.reset();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(err) => {
+                metrics().dependency_error("redis");
+                // The connection manager reconnects in the background, this just paces the retries.
+                let delay = backoff.next_delay();
+                error!("{}, retrying in {}s", err, delay.as_secs());
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Logs an error once processing has been down for longer than `CHECK_LOOP_ALERT_SECS`, and again
+/// when it recovers.
+async fn watch_check_loop(state: Arc<AppState>) {
+    let threshold = state.config.check_loop_alert_secs as i64;
+    let interval = Duration::from_secs(std::cmp::min(30, state.config.check_loop_alert_secs));
+    let mut alerted = false;
+    loop {
+        tokio::time::sleep(interval).await;
+        let down_for = state.check_loop.seconds_since_success();
+        if down_for >= threshold {
+            if !alerted {
+                error!(
+                    "Verifications have not been processed for {} seconds.",
+                    down_for
+                );
+                alerted = true;
+            }
+        } else if alerted {
+            info!("Verification processing has recovered.");
+            alerted = false;
+        }
+    }
+}
+
+/// One pass over the completed verifications. Only fails when redis itself can't be read.
+pub async fn check_redis(ctx: Arc<Context>, state: Arc<AppState>) -> Result<(), String> {
+    // Format for completed verification keys is: complete:{userid}:{guildid}
+    // Value must be either 'true' or 'false'
+    let mut conn = state.redis.clone();
+    if state.config.debug {
         if let Err(err) = conn
             .set::<String, String, String>(
                 "complete:155149108183695360:416407744246054912:3".to_string(),
@@ -87,255 +200,320 @@ pub async fn check_redis(
             keys
         }
         Err(err) => {
-            error!("Redis error in scan - {:?}", err);
-            return;
+            return Err(format!("Redis error in scan - {:?}", err));
         }
     };
 
+    // Completions for guilds on another process's shards are left for that process.
+    let keys: Vec<String> = keys
+        .into_iter()
+        .filter(|key| owns_key(&state.config.sharding, key))
+        .collect();
+
+    metrics().queue_depth.set(keys.len() as i64);
     if keys.is_empty() {
         debug!("No keys found!");
     }
 
+    // Each guild's completions are handled in order by one task, so per user ordering is kept and
+    // each guild's rate limit buckets see one request at a time.
+    let mut groups: Vec<Vec<String>> = vec![];
+    let mut group_of_guild: HashMap<String, usize> = HashMap::new();
     for key in keys {
-        debug!("KEY FOUND FROM SCAN: {}", &key);
-        // get the value from redis
+        let guild = key.split(':').nth(2).unwrap_or_default().to_string();
+        match group_of_guild.get(&guild) {
+            Some(index) => groups[*index].push(key),
+            None => {
+                group_of_guild.insert(guild, groups.len());
+                groups.push(vec![key]);
+            }
+        }
+    }
 
-        let val = match conn.get::<String, String>(key.clone().to_string()).await {
-            Ok(val) => val,
+    let limit = Arc::new(Semaphore::new(state.config.check_loop_concurrency));
+    let mut tasks = JoinSet::new();
+    for keys in groups {
+        let permit = match Arc::clone(&limit).acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => break,
+        };
+        let ctx = Arc::clone(&ctx);
+        let state = Arc::clone(&state);
+        tasks.spawn(async move {
+            process_guild_completions(&ctx, &state, keys).await;
+            drop(permit);
+        });
+    }
+    while let Some(res) = tasks.join_next().await {
+        if let Err(err) = res {
+            error!("Processing completions for a guild panicked - {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Works through one guild's completions, claiming each before it is processed.
+async fn process_guild_completions(ctx: &Context, state: &AppState, keys: Vec<String>) {
+    let mut conn = state.redis.clone();
+    let mut lookups = BatchLookups::default();
+    for key in keys {
+        // Whatever is left stays in redis and gets processed after the restart.
+        if state.shutdown.is_requested() {
+            info!("Shutdown requested, leaving the remaining completions in the queue.");
+            break;
+        }
+        let claim = match claim_key(&mut conn, &key).await {
+            Ok(Some(claim)) => claim,
+            Ok(None) => {
+                debug!("{} is being processed by another instance.", key);
+                continue;
+            }
             Err(err) => {
+                error!("{}", err);
+                continue;
+            }
+        };
+        process_completion(ctx, state, &mut conn, &mut lookups, &key).await;
+        let dead_letter_after = state.config.dead_letter_after_secs;
+        match dead_letters::record_outcome(&mut conn, &key, dead_letter_after).await {
+            Ok(Outcome::DeadLettered) => {
+                metrics().dead_letters.inc();
                 error!(
-                    "Failed to get value from db with a key {} from iterator - {:?}",
-                    key, err
+                    "{} kept failing for {}s and was moved to the dead letter queue.",
+                    key, dead_letter_after
                 );
-                continue;
+            }
+            Ok(_) => {}
+            Err(err) => error!("{}", err),
+        }
+        // A completion that failed part way is still in redis, releasing the claim retries it on
+        // the next pass. One that finished has been deleted already.
+        if let Err(err) = release_claim(&mut conn, &key, &claim).await {
+            error!("{}", err);
+        }
+    }
+}
+
+/// Discord objects looked up while working through one guild's completions, so a burst of
+/// completions shares them instead of fetching them again for every key.
+#[derive(Default)]
+struct BatchLookups {
+    channels: HashMap<u64, Channel>,
+}
+
+/// Takes the member from the cache when the gateway has sent it, otherwise asks Discord.
+async fn resolve_member(ctx: &Context, guild_id: u64, user_id: u64) -> SerenityResult<Member> {
+    if let Some(member) = ctx.cache.member(guild_id, user_id) {
+        return Ok(member);
+    }
+    ctx.http.get_member(guild_id, user_id).await
+}
+
+/// Takes the channel from the batch or the cache, falling back to asking Discord.
+async fn resolve_channel(
+    ctx: &Context,
+    lookups: &mut BatchLookups,
+    channel_id: u64,
+) -> SerenityResult<Channel> {
+    if let Some(channel) = lookups.channels.get(&channel_id) {
+        return Ok(channel.clone());
+    }
+    let channel = match ctx.cache.guild_channel(channel_id) {
+        Some(channel) => Channel::Guild(channel),
+        None => ctx.http.get_channel(channel_id).await?,
+    };
+    lookups.channels.insert(channel_id, channel.clone());
+    Ok(channel)
+}
+
+/// Handles one completion. The key is only deleted once its result has been applied, so an
+/// early return leaves it in the queue.
+async fn process_completion(
+    ctx: &Context,
+    state: &AppState,
+    conn: &mut ConnectionManager,
+    lookups: &mut BatchLookups,
+    key: &str,
+) {
+    let repos = &state.repos;
+    debug!("KEY FOUND FROM SCAN: {}", &key);
+    // get the value from redis
+
+    let val = match conn.get::<&str, Option<String>>(key).await {
+        Ok(Some(val)) => val,
+        Ok(None) => {
+            debug!("{} was already processed by another instance.", key);
+            return;
+        }
+        Err(err) => {
+            error!(
+                "Failed to get value from db with a key {} from iterator - {:?}",
+                key, err
+            );
+            return;
+        }
+    };
+
+    debug!("Value from iter key ({}) - {}", &key, val);
+
+    // check for "true*"
+    if val.starts_with("true") {
+        debug!("Value starts with true.");
+        // split key on ':'
+        let key_split = key.split(':').collect::<Vec<&str>>().clone();
+        debug!("{:?}", key_split);
+        // user_id is index 1
+        let user_id = match key_split.get(1) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid key found when splitting - {}", key);
+                return;
+            }
+        };
+        debug!("{:?}", user_id);
+        // guild_id is index 2
+        let guild_id = match key_split.get(2) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid val found when splitting - {}", val);
+                return;
             }
         };
+        debug!("{:?}", guild_id);
 
-        debug!("Value from iter key ({}) - {}", &key, val);
+        // split val on ':'
+        // score is index 1
+        // minscore is index 2
+        let val_split: Vec<&str> = val.split(':').collect();
+        debug!("{:?}", val_split);
 
-        // check for "true*"
-        if val.starts_with("true") {
-            debug!("Value starts with true.");
-            // split key on ':'
-            let key_split = key.split(':').collect::<Vec<&str>>().clone();
-            debug!("{:?}", key_split);
-            // user_id is index 1
-            let user_id = match key_split.get(1) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid key found when splitting - {}", key);
-                    return;
-                }
-            };
-            debug!("{:?}", user_id);
-            // guild_id is index 2
-            let guild_id = match key_split.get(2) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid val found when splitting - {}", val);
-                    return;
-                }
-            };
-            debug!("{:?}", guild_id);
-
-            // split val on ':'
-            // score is index 1
-            // minscore is index 2
-            let val_split: Vec<&str> = val.split(':').collect();
-            debug!("{:?}", val_split);
-
-            let score = match val_split.get(1) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid val found when splitting - {}", val);
-                    return;
-                }
-            };
+        let score = match val_split.get(1) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid val found when splitting - {}", val);
+                return;
+            }
+        };
 
-            let minscore = match val_split.get(2) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid val found when splitting - {}", val);
-                    return;
-                }
-            };
-
-            // get guild obj
-            let guild_id: u64 = match guild_id.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            debug!("{:?}", guild_id);
-            let _guild_obj = match ctx.http.get_guild(guild_id).await {
-                Ok(chn) => chn,
-                Err(err) => {
-                    error!("Error getting guild - {:?}", err);
-                    return;
-                }
-            };
-
-            // get member obj
-            let user_id: u64 = match user_id.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            debug!("{:?}", user_id);
-            let mut member_obj = match ctx.http.get_member(guild_id, user_id).await {
-                Ok(mem) => mem,
-                Err(err) => {
-                    error!("Error getting member obj - {:?}", err);
-                    return;
-                }
-            };
-
-            // get guild settings from mongodb
-            // if the server has no verification role set, log an error and return.
-            let guild_doc_opt: Option<GuildDoc> = match mongo_client
-                .database("botdb")
-                .collection("guilds")
-                .find_one(doc! {"guild_ID": guild_id.to_string()}, None)
-                .await
-            {
-                Ok(col_opt) => col_opt,
-                Err(err) => {
-                    error!("Mongo error - {:?}", err);
-                    return;
-                }
-            };
-            debug!("{:?}", guild_doc_opt);
-
-            // Try to extract the guild doc from the option.
-            let guild_doc: GuildDoc = match guild_doc_opt {
-                None => {
-                    error!("Could not retrieve guild - guild_doc_opt was None");
-                    return;
-                }
-                Some(doc) => doc,
-            };
-            debug!("{:?}", guild_doc);
-
-            // get the role obj from the guild settings
-            let verification_role_id: u64 = match guild_doc.verification_role_ID.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_role_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            // add the role to the user
-
-            let channel_id: u64 = match guild_doc.verification_logs_channel_ID.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            debug!("{:?}", channel_id);
-            let channel = match ctx.http.get_channel(channel_id).await {
-                Ok(chn) => chn,
-                Err(err) => {
-                    error!("Getting channel - {:?}", err);
-                    return;
-                }
-            };
+        let minscore = match val_split.get(2) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid val found when splitting - {}", val);
+                return;
+            }
+        };
 
-            match member_obj.add_role(&ctx.http, verification_role_id).await {
-                Ok(_) => {
-                    debug!("Added role {} to user {}", verification_role_id, user_id)
-                }
-                Err(err) => {
-                    error!("Could not add role to user during verification - {:?}", err);
-
-                    let res = channel.id().send_message(&ctx.http, |message| {
-                        message.embed(|embed| {
-                            embed.title("Error during verification!");
-                            embed.color(Colour::DARK_RED);
-                            embed.description("The role could not be added to the user and will need to be added manually.\n\n The user did however pass verification successfully.");
-                            embed.timestamp(Utc::now());
-                            embed.thumbnail(&member_obj.face());
-                            embed.author(|author| {
-                                author.name("Open/Alt.ID Logs");
-                                author.url("https://github.com/omneex/OpenAltID");
-                                author
-                            });
-                            embed.field("Error Message", format!("{:?}", err), false);
-                            embed.field("User Mention", format!("<@{}>", user_id), false);
-                            embed.field("User ID", format!("{}", user_id), false);
-                            embed.field("Score", format!("**{}** / {}", score, minscore), false);
-                            embed.footer(|footer| {
-                                footer.text("Powered by Open/Alt.ID");
-                                footer
-                            });
-                            embed
-                        })
-                    }).await;
-
-                    match res {
-                        Ok(_) => {
-                            debug!("Embed message was sent successfully.")
-                        }
-                        Err(err) => {
-                            warn!("Could not send embed - {:?}", err)
-                        }
-                    }
-                }
+        // get guild obj
+        let guild_id: u64 = match guild_id.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
             }
+        };
+        debug!("{:?}", guild_id);
 
-            // delete the key from redis
-            // log the info
-            let _del_res: u16 = match conn.del(&key).await {
-                Ok(val) => val,
-                Err(err) => {
-                    error!("Failed to delete key: {} - {}", key, err);
-                    return;
-                }
-            };
-            // check if the verification logs channel is set up
-            // if it is set up then send the log info to the channel in an embed
-            // log that the user encountered an error with the reason
-            info!(
-                "User: {} was verified in {} Score: {} / {}",
-                user_id, guild_id, score, minscore
-            );
-            // check if the server has a logs channel
-            // if it is set up then send the log info to the channel in an embed
-            debug!(
-                "Will now send the info to the logs channel which is {} with the Score: {} / {}.",
-                channel_id, score, minscore
-            );
-            let res = channel
-                .id()
-                .send_message(&ctx.http, |message| {
+        // get member obj
+        let user_id: u64 = match user_id.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        debug!("{:?}", user_id);
+        let mut member_obj = match resolve_member(ctx, guild_id, user_id).await {
+            Ok(mem) => mem,
+            Err(err) => {
+                error!("Error getting member obj - {:?}", err);
+                return;
+            }
+        };
+
+        // get guild settings from mongodb
+        // if the server has no verification role set, log an error and return.
+        let guild_doc_opt: Option<GuildDoc> = match repos.guilds.get(&guild_id.to_string()).await {
+            Ok(col_opt) => col_opt,
+            Err(err) => {
+                error!("Mongo error - {}", err);
+                return;
+            }
+        };
+        debug!("{:?}", guild_doc_opt);
+
+        // Try to extract the guild doc from the option.
+        let guild_doc: GuildDoc = match guild_doc_opt {
+            None => {
+                error!("Could not retrieve guild - guild_doc_opt was None");
+                return;
+            }
+            Some(doc) => doc,
+        };
+        debug!("{:?}", guild_doc);
+
+        // get the role obj from the guild settings
+        let verification_role_id: u64 = match guild_doc.verification_role_ID.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_role_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        // add the role to the user
+
+        let channel_id: u64 = match guild_doc.verification_logs_channel_ID.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        debug!("{:?}", channel_id);
+        let channel = match resolve_channel(ctx, lookups, channel_id).await {
+            Ok(chn) => chn,
+            Err(err) => {
+                error!("Getting channel - {:?}", err);
+                return;
+            }
+        };
+
+        match member_obj.add_role(&ctx.http, verification_role_id).await {
+            Ok(_) => {
+                debug!("Added role {} to user {}", verification_role_id, user_id)
+            }
+            Err(err) => {
+                error!("Could not add role to user during verification - {:?}", err);
+                metrics()
+                    .role_grant_failures
+                    .with_label_values(&[&guild_id.to_string()])
+                    .inc();
+                metrics().dependency_error("discord");
+
+                let res = channel.id().send_message(&ctx.http, |message| {
                     message.embed(|embed| {
-                        embed.title("Verification Passed");
-                        embed.color(Colour::BLUE);
-                        embed.description("The user passed verification.");
+                        embed.title("Error during verification!");
+                        embed.color(Colour::DARK_RED);
+                        embed.description("The role could not be added to the user and will need to be added manually.\n\n The user did however pass verification successfully.");
                         embed.timestamp(Utc::now());
-                        embed.thumbnail(&member_obj.face());
+                        embed.thumbnail(member_obj.face());
                         embed.author(|author| {
                             author.name("Open/Alt.ID Logs");
                             author.url("https://github.com/omneex/OpenAltID");
                             author
                         });
+                        embed.field("Error Message", format!("{:?}", err), false);
                         embed.field("User Mention", format!("<@{}>", user_id), false);
                         embed.field("User ID", format!("{}", user_id), false);
                         embed.field("Score", format!("**{}** / {}", score, minscore), false);
@@ -345,379 +523,547 @@ pub async fn check_redis(
                         });
                         embed
                     })
-                })
-                .await;
+                }).await;
 
-            match res {
-                Ok(_) => {
-                    debug!("Embed message was sent successfully.")
-                }
-                Err(err) => {
-                    warn!("Could not send embed - {:?}", err)
+                match res {
+                    Ok(_) => {
+                        debug!("Embed message was sent successfully.")
+                    }
+                    Err(err) => {
+                        metrics().dependency_error("discord");
+                        warn!("Could not send embed - {:?}", err)
+                    }
                 }
             }
         }
-        // check for "false*"
-        else if val.starts_with("false") {
-            debug!("Value starts with false.");
-            // split key on ':'
-            let key_split = key.split(':').collect::<Vec<&str>>().clone();
-            debug!("{:?}", key_split);
-            // user_id is index 1
-            let user_id = match key_split.get(1) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid key found when splitting - {}", key);
-                    return;
-                }
-            };
-            debug!("{:?}", user_id);
-            // guild_id is index 2
-            let guild_id = match key_split.get(2) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid val found when splitting - {}", val);
-                    return;
-                }
-            };
-            debug!("{:?}", guild_id);
-
-            // split val on ':'
-            // score is index 1
-            // minscore is index 2
-            let val_split: Vec<&str> = val.split(':').collect();
-            debug!("{:?}", val_split);
-
-            let score = match val_split.get(1) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid val found when splitting - {}", val);
-                    return;
-                }
-            };
 
-            let minscore = match val_split.get(2) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid val found when splitting - {}", val);
-                    return;
-                }
-            };
-
-            // get member obj
-            let user_id: u64 = match user_id.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            let guild_id: u64 = match guild_id.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            debug!("{:?}", user_id);
-            let member_obj = match ctx.http.get_member(guild_id, user_id).await {
-                Ok(mem) => mem,
-                Err(err) => {
-                    error!("Cant get member_obj - {:?} - Removing from the queue!", err);
-                    let del_res: u16 = match conn.del(&key).await {
-                        Ok(val) => val,
-                        Err(err) => {
-                            error!("Failed to delete key: {} - {}", key, err);
-                            return;
-                        }
-                    };
-                    debug!("{:?}", del_res);
-                    return;
-                }
-            };
-
-            // get guild settings from mongodb
-            // if the server has no verification role set, log an error and return.
-            // get guild settings from mongodb
-            let guild_doc_opt: Option<GuildDoc> = match mongo_client
-                .database("botdb")
-                .collection("guilds")
-                .find_one(doc! {"guild_ID": guild_id.to_string()}, None)
-                .await
-            {
-                Ok(col_opt) => col_opt,
-                Err(err) => {
-                    error!("Error gettting doc opt - {:?}", err);
-                    return;
-                }
-            };
-            debug!("{:?}", guild_doc_opt);
-
-            // Try to extract the guild doc from the option.
-            let guild_doc: GuildDoc = match guild_doc_opt {
-                None => {
-                    error!("Could not retrieve guild - guild_doc_opt was None");
-                    return;
-                }
-                Some(doc) => doc,
-            };
-            debug!("{:?}", guild_doc);
-
-            // delete the key from redis
-            // log the info
-            let del_res: u16 = match conn.del(&key).await {
-                Ok(val) => val,
-                Err(err) => {
-                    error!("Failed to delete key: {} - {}", key, err);
-                    return;
-                }
-            };
-            debug!("{:?}", del_res);
-
-            // check if the verification logs channel is set up
-            let channel_id: u64 = match guild_doc.verification_logs_channel_ID.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            debug!("{:?}", channel_id);
-            let channel = match ctx.http.get_channel(channel_id).await {
-                Ok(chn) => chn,
-                Err(err) => {
-                    error!("Error getting channel - {:?}", err);
-                    return;
-                }
-            };
-            debug!("{:?}", channel_id);
-            // if it is set up then send the log info to the channel in an embed
-            // log that the user encountered an error with the reason
-            info!(
-                "User: {} was NOT verified in {} Score: {} / {}",
-                user_id, guild_id, score, minscore
-            );
+        // delete the key from redis
+        // log the info
+        let _del_res: u16 = match conn.del(key).await {
+            Ok(val) => val,
+            Err(err) => {
+                error!("Failed to delete key: {} - {}", key, err);
+                return;
+            }
+        };
+        record_attempt(
+            repos,
+            VerificationAttempt {
+                user_ID: user_id.to_string(),
+                guild_ID: guild_id.to_string(),
+                outcome: "passed".to_string(),
+                score: Some(score.to_string()),
+                min_score: Some(minscore.to_string()),
+                reason: None,
+                timestamp: DateTime::now(),
+            },
+        )
+        .await;
+        // check if the verification logs channel is set up
+        // if it is set up then send the log info to the channel in an embed
+        // log that the user encountered an error with the reason
+        info!(
+            "User: {} was verified in {} Score: {} / {}",
+            user_id, guild_id, score, minscore
+        );
+        // check if the server has a logs channel
+        // if it is set up then send the log info to the channel in an embed
+        debug!(
+            "Will now send the info to the logs channel which is {} with the Score: {} / {}.",
+            channel_id, score, minscore
+        );
+        let res = channel
+            .id()
+            .send_message(&ctx.http, |message| {
+                message.embed(|embed| {
+                    embed.title("Verification Passed");
+                    embed.color(Colour::BLUE);
+                    embed.description("The user passed verification.");
+                    embed.timestamp(Utc::now());
+                    embed.thumbnail(member_obj.face());
+                    embed.author(|author| {
+                        author.name("Open/Alt.ID Logs");
+                        author.url("https://github.com/omneex/OpenAltID");
+                        author
+                    });
+                    embed.field("User Mention", format!("<@{}>", user_id), false);
+                    embed.field("User ID", format!("{}", user_id), false);
+                    embed.field("Score", format!("**{}** / {}", score, minscore), false);
+                    embed.footer(|footer| {
+                        footer.text("Powered by Open/Alt.ID");
+                        footer
+                    });
+                    embed
+                })
+            })
+            .await;
 
-            // check if the server has a logs channel
-            // if it is set up then send the log info to the channel in an embed
-            debug!(
-                "Will now send the info to the logs channel which is {} with the Score: {} / {}.",
-                channel_id, score, minscore
-            );
-            let res = channel
-                .id()
-                .send_message(&ctx.http, |message| {
-                    message.embed(|embed| {
-                        embed.title("Verification Failed");
-                        embed.color(Colour::ORANGE);
-                        embed.description("The user did not pass verification.");
-                        embed.timestamp(Utc::now());
-                        embed.thumbnail(&member_obj.face());
-                        embed.author(|author| {
-                            author.name("Open/Alt.ID Logs");
-                            author.url("https://github.com/omneex/OpenAltID");
-                            author
-                        });
-                        embed.field("User Mention", format!("<@{}>", user_id), false);
-                        embed.field("User ID", user_id.to_string(), false);
-                        embed.field("Score", format!("**{}** / {}", score, minscore), false);
-                        embed.footer(|footer| {
-                            footer.text("Powered by Open/Alt.ID");
-                            footer
-                        });
-                        embed
-                    })
+        match res {
+            Ok(_) => {
+                debug!("Embed message was sent successfully.")
+            }
+            Err(err) => {
+                metrics().dependency_error("discord");
+                warn!("Could not send embed - {:?}", err)
+            }
+        }
+    }
+    // check for "false*"
+    else if val.starts_with("false") {
+        debug!("Value starts with false.");
+        // split key on ':'
+        let key_split = key.split(':').collect::<Vec<&str>>().clone();
+        debug!("{:?}", key_split);
+        // user_id is index 1
+        let user_id = match key_split.get(1) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid key found when splitting - {}", key);
+                return;
+            }
+        };
+        debug!("{:?}", user_id);
+        // guild_id is index 2
+        let guild_id = match key_split.get(2) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid val found when splitting - {}", val);
+                return;
+            }
+        };
+        debug!("{:?}", guild_id);
+
+        // split val on ':'
+        // score is index 1
+        // minscore is index 2
+        let val_split: Vec<&str> = val.split(':').collect();
+        debug!("{:?}", val_split);
+
+        let score = match val_split.get(1) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid val found when splitting - {}", val);
+                return;
+            }
+        };
+
+        let minscore = match val_split.get(2) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid val found when splitting - {}", val);
+                return;
+            }
+        };
+
+        // get member obj
+        let user_id: u64 = match user_id.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        let guild_id: u64 = match guild_id.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        debug!("{:?}", user_id);
+        let member_obj = match resolve_member(ctx, guild_id, user_id).await {
+            Ok(mem) => mem,
+            Err(err) => {
+                error!("Cant get member_obj - {:?} - Removing from the queue!", err);
+                let del_res: u16 = match conn.del(key).await {
+                    Ok(val) => val,
+                    Err(err) => {
+                        error!("Failed to delete key: {} - {}", key, err);
+                        return;
+                    }
+                };
+                debug!("{:?}", del_res);
+                return;
+            }
+        };
+
+        // get guild settings from mongodb
+        // if the server has no verification role set, log an error and return.
+        // get guild settings from mongodb
+        let guild_doc_opt: Option<GuildDoc> = match repos.guilds.get(&guild_id.to_string()).await {
+            Ok(col_opt) => col_opt,
+            Err(err) => {
+                error!("Error gettting doc opt - {}", err);
+                return;
+            }
+        };
+        debug!("{:?}", guild_doc_opt);
+
+        // Try to extract the guild doc from the option.
+        let guild_doc: GuildDoc = match guild_doc_opt {
+            None => {
+                error!("Could not retrieve guild - guild_doc_opt was None");
+                return;
+            }
+            Some(doc) => doc,
+        };
+        debug!("{:?}", guild_doc);
+
+        // delete the key from redis
+        // log the info
+        let del_res: u16 = match conn.del(key).await {
+            Ok(val) => val,
+            Err(err) => {
+                error!("Failed to delete key: {} - {}", key, err);
+                return;
+            }
+        };
+        debug!("{:?}", del_res);
+        record_attempt(
+            repos,
+            VerificationAttempt {
+                user_ID: user_id.to_string(),
+                guild_ID: guild_id.to_string(),
+                outcome: "failed".to_string(),
+                score: Some(score.to_string()),
+                min_score: Some(minscore.to_string()),
+                reason: None,
+                timestamp: DateTime::now(),
+            },
+        )
+        .await;
+
+        // check if the verification logs channel is set up
+        let channel_id: u64 = match guild_doc.verification_logs_channel_ID.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        debug!("{:?}", channel_id);
+        let channel = match resolve_channel(ctx, lookups, channel_id).await {
+            Ok(chn) => chn,
+            Err(err) => {
+                error!("Error getting channel - {:?}", err);
+                return;
+            }
+        };
+        debug!("{:?}", channel_id);
+        // if it is set up then send the log info to the channel in an embed
+        // log that the user encountered an error with the reason
+        info!(
+            "User: {} was NOT verified in {} Score: {} / {}",
+            user_id, guild_id, score, minscore
+        );
+
+        // check if the server has a logs channel
+        // if it is set up then send the log info to the channel in an embed
+        debug!(
+            "Will now send the info to the logs channel which is {} with the Score: {} / {}.",
+            channel_id, score, minscore
+        );
+        let res = channel
+            .id()
+            .send_message(&ctx.http, |message| {
+                message.embed(|embed| {
+                    embed.title("Verification Failed");
+                    embed.color(Colour::ORANGE);
+                    embed.description("The user did not pass verification.");
+                    embed.timestamp(Utc::now());
+                    embed.thumbnail(member_obj.face());
+                    embed.author(|author| {
+                        author.name("Open/Alt.ID Logs");
+                        author.url("https://github.com/omneex/OpenAltID");
+                        author
+                    });
+                    embed.field("User Mention", format!("<@{}>", user_id), false);
+                    embed.field("User ID", user_id.to_string(), false);
+                    embed.field("Score", format!("**{}** / {}", score, minscore), false);
+                    embed.footer(|footer| {
+                        footer.text("Powered by Open/Alt.ID");
+                        footer
+                    });
+                    embed
                 })
-                .await;
+            })
+            .await;
 
-            match res {
-                Ok(_) => {
-                    debug!("Embed message was sent successfully.")
-                }
-                Err(err) => {
-                    warn!("Could not send embed - {:?}", err)
-                }
+        match res {
+            Ok(_) => {
+                debug!("Embed message was sent successfully.")
+            }
+            Err(err) => {
+                metrics().dependency_error("discord");
+                warn!("Could not send embed - {:?}", err)
             }
         }
-        // check for "error*"
-        else if val.starts_with("error") {
-            debug!("Value starts with error.");
-            // split key on ':'
-            let key_split = key.split(':').collect::<Vec<&str>>().clone();
-            debug!("{:?}", key_split);
-            // user_id is index 1
-            let user_id = match key_split.get(1) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid key found when splitting - {}", key);
-                    return;
-                }
-            };
-            debug!("{:?}", user_id);
-            // guild_id is index 2
-            let guild_id = match key_split.get(2) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid val found when splitting - {}", val);
-                    return;
-                }
-            };
-            debug!("{:?}", guild_id);
-
-            // split val on ':'
-            // reason is index 1
-            let val_split: Vec<&str> = val.split(':').collect();
-            debug!("{:?}", val_split);
-            // user_id is index 1
-            let reason = match val_split.get(1) {
-                Some(val) => <&str>::clone(val),
-                None => {
-                    error!("Invalid val found when splitting - {}", val);
-                    return;
-                }
-            };
-
-            // get member obj
-            let user_id: u64 = match user_id.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            let guild_id: u64 = match guild_id.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            debug!("{:?}", user_id);
-            let member_obj = match ctx.http.get_member(guild_id, user_id).await {
-                Ok(mem) => mem,
-                Err(err) => {
-                    error!("Error getting member - {:?}", err);
-                    return;
-                }
-            };
-
-            debug!("{:?}", reason);
-            // get guild settings from mongodb
-            let guild_doc_opt: Option<GuildDoc> = match mongo_client
-                .database("botdb")
-                .collection("guilds")
-                .find_one(doc! {"guild_ID": guild_id.to_string()}, None)
-                .await
-            {
-                Ok(col_opt) => col_opt,
-                Err(err) => {
-                    error!("Error getting doc - {:?}", err);
-                    return;
-                }
-            };
-            debug!("{:?}", guild_doc_opt);
-
-            // Try to extract the guild doc from the option.
-            let guild_doc: GuildDoc = match guild_doc_opt {
-                None => {
-                    error!("Could not retrieve guild - guild_doc_opt was None");
-                    return;
-                }
-                Some(doc) => doc,
-            };
-            debug!("{:?}", guild_doc);
-
-            let channel_id: u64 = match guild_doc.verification_logs_channel_ID.parse() {
-                Ok(num) => num,
-                Err(err) => {
-                    error!(
-                        "Could not parse number from verification_logs_channel_ID - {:?}",
-                        err
-                    );
-                    return;
-                }
-            };
-            debug!("{:?}", channel_id);
-            let channel = match ctx.http.get_channel(channel_id).await {
-                Ok(chn) => chn,
-                Err(err) => {
-                    error!("Error getting channel - {:?}", err);
-                    return;
-                }
-            };
-
-            // delete the key from redis
-            let del_res: u16 = match conn.del(&key).await {
-                Ok(val) => val,
-                Err(err) => {
-                    error!("Failed to delete key: {} - {}", key, err);
-                    return;
-                }
-            };
-            debug!("{:?}", del_res);
-            // log that the user encountered an error with the reason
-            info!(
-                "User: {} was NOT verified in {} Reason: {}",
-                user_id, guild_id, reason
-            );
-            // check if the server has a logs channel
-            // if it is set up then send the log info to the channel in an embed
-            debug!(
-                "Will now send the info to the logs channel which is {} with the reason of '{}'.",
-                channel_id, reason
-            );
+    }
+    // check for "error*"
+    else if val.starts_with("error") {
+        debug!("Value starts with error.");
+        // split key on ':'
+        let key_split = key.split(':').collect::<Vec<&str>>().clone();
+        debug!("{:?}", key_split);
+        // user_id is index 1
+        let user_id = match key_split.get(1) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid key found when splitting - {}", key);
+                return;
+            }
+        };
+        debug!("{:?}", user_id);
+        // guild_id is index 2
+        let guild_id = match key_split.get(2) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid val found when splitting - {}", val);
+                return;
+            }
+        };
+        debug!("{:?}", guild_id);
 
-            let res = channel
-                .id()
-                .send_message(&ctx.http, |message| {
-                    message.embed(|embed| {
-                        embed.title("Verification Failed");
-                        embed.color(Colour::RED);
-                        embed.description("The user could not be verified.");
-                        embed.timestamp(Utc::now());
-                        embed.thumbnail(&member_obj.face());
-                        embed.author(|author| {
-                            author.name("Open/Alt.ID Logs");
-                            author.url("https://github.com/omneex/OpenAltID");
-                            author
-                        });
-                        embed.field("User Mention", format!("<@{}>", user_id), false);
-                        embed.field("User ID", user_id.to_string(), false);
-                        embed.field("Reason", format!("__{}__", reason), false);
-                        embed.footer(|footer| {
-                            footer.text("Powered by Open/Alt.ID");
-                            footer
-                        });
-                        embed
-                    })
+        // split val on ':'
+        // reason is index 1
+        let val_split: Vec<&str> = val.split(':').collect();
+        debug!("{:?}", val_split);
+        // user_id is index 1
+        let reason = match val_split.get(1) {
+            Some(val) => <&str>::clone(val),
+            None => {
+                error!("Invalid val found when splitting - {}", val);
+                return;
+            }
+        };
+
+        // get member obj
+        let user_id: u64 = match user_id.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        let guild_id: u64 = match guild_id.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        debug!("{:?}", user_id);
+        let member_obj = match resolve_member(ctx, guild_id, user_id).await {
+            Ok(mem) => mem,
+            Err(err) => {
+                error!("Error getting member - {:?}", err);
+                return;
+            }
+        };
+
+        debug!("{:?}", reason);
+        // get guild settings from mongodb
+        let guild_doc_opt: Option<GuildDoc> = match repos.guilds.get(&guild_id.to_string()).await {
+            Ok(col_opt) => col_opt,
+            Err(err) => {
+                error!("Error getting doc - {}", err);
+                return;
+            }
+        };
+        debug!("{:?}", guild_doc_opt);
+
+        // Try to extract the guild doc from the option.
+        let guild_doc: GuildDoc = match guild_doc_opt {
+            None => {
+                error!("Could not retrieve guild - guild_doc_opt was None");
+                return;
+            }
+            Some(doc) => doc,
+        };
+        debug!("{:?}", guild_doc);
+
+        let channel_id: u64 = match guild_doc.verification_logs_channel_ID.parse() {
+            Ok(num) => num,
+            Err(err) => {
+                error!(
+                    "Could not parse number from verification_logs_channel_ID - {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        debug!("{:?}", channel_id);
+        let channel = match resolve_channel(ctx, lookups, channel_id).await {
+            Ok(chn) => chn,
+            Err(err) => {
+                error!("Error getting channel - {:?}", err);
+                return;
+            }
+        };
+
+        // delete the key from redis
+        let del_res: u16 = match conn.del(key).await {
+            Ok(val) => val,
+            Err(err) => {
+                error!("Failed to delete key: {} - {}", key, err);
+                return;
+            }
+        };
+        debug!("{:?}", del_res);
+        record_attempt(
+            repos,
+            VerificationAttempt {
+                user_ID: user_id.to_string(),
+                guild_ID: guild_id.to_string(),
+                outcome: "error".to_string(),
+                score: None,
+                min_score: None,
+                reason: Some(reason.to_string()),
+                timestamp: DateTime::now(),
+            },
+        )
+        .await;
+        // log that the user encountered an error with the reason
+        info!(
+            "User: {} was NOT verified in {} Reason: {}",
+            user_id, guild_id, reason
+        );
+        // check if the server has a logs channel
+        // if it is set up then send the log info to the channel in an embed
+        debug!(
+            "Will now send the info to the logs channel which is {} with the reason of '{}'.",
+            channel_id, reason
+        );
+
+        let res = channel
+            .id()
+            .send_message(&ctx.http, |message| {
+                message.embed(|embed| {
+                    embed.title("Verification Failed");
+                    embed.color(Colour::RED);
+                    embed.description("The user could not be verified.");
+                    embed.timestamp(Utc::now());
+                    embed.thumbnail(member_obj.face());
+                    embed.author(|author| {
+                        author.name("Open/Alt.ID Logs");
+                        author.url("https://github.com/omneex/OpenAltID");
+                        author
+                    });
+                    embed.field("User Mention", format!("<@{}>", user_id), false);
+                    embed.field("User ID", user_id.to_string(), false);
+                    embed.field("Reason", format!("__{}__", reason), false);
+                    embed.footer(|footer| {
+                        footer.text("Powered by Open/Alt.ID");
+                        footer
+                    });
+                    embed
                 })
-                .await;
+            })
+            .await;
 
-            match res {
-                Ok(_) => {
-                    debug!("Embed message was sent successfully.")
-                }
-                Err(err) => {
-                    warn!("Could not send embed - {:?}", err)
-                }
+        match res {
+            Ok(_) => {
+                debug!("Embed message was sent successfully.")
+            }
+            Err(err) => {
+                metrics().dependency_error("discord");
+                warn!("Could not send embed - {:?}", err)
             }
-        } else {
-            warn!("Value did not start with a supported string. {}", val)
         }
+    } else {
+        warn!("Value did not start with a supported string. {}", val)
+    }
+}
+
+/// Keeps a history of processed verifications so it can be exported or purged per guild.
+async fn record_attempt(repos: &Repositories, attempt: VerificationAttempt) {
+    metrics()
+        .verifications
+        .with_label_values(&[&attempt.guild_ID, &attempt.outcome])
+        .inc();
+    if let Err(err) = repos.attempts.record(attempt).await {
+        error!("{}", err);
+    }
+}
+
+/// Whether the guild in a `complete:{user}:{guild}` key belongs to this process. Malformed keys
+/// are handled by whoever runs shard 0, so they still get logged once.
+fn owns_key(sharding: &Sharding, key: &str) -> bool {
+    match key.split(':').nth(2).map(|id| id.parse::<u64>()) {
+        Some(Ok(guild_id)) => sharding.owns_guild(guild_id),
+        _ => sharding.owns_guild(0),
+    }
+}
+
+/// Reserves a completion for this instance so no other replica, or overlapping pass, applies it
+/// twice. Returns the token needed to release it, or None when someone else holds the claim.
+async fn claim_key(conn: &mut ConnectionManager, key: &str) -> Result<Option<String>, String> {
+    let token = format!("{:016x}", rand::random::<u64>());
+    let res: Result<Option<String>, RedisError> = redis::cmd("SET")
+        .arg(format!("claim:{}", key))
+        .arg(&token)
+        .arg("NX")
+        .arg("EX")
+        .arg(CLAIM_TTL_SECS)
+        .query_async(conn)
+        .await;
+    match res {
+        Ok(Some(_)) => Ok(Some(token)),
+        Ok(None) => Ok(None),
+        Err(err) => Err(format!("Could not claim {} - {}", key, err)),
+    }
+}
+
+/// Drops the claim, unless it expired and was taken over by another instance in the meantime.
+async fn release_claim(conn: &mut ConnectionManager, key: &str, token: &str) -> Result<(), String> {
+    let script = Script::new(
+        r"if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) end return 0",
+    );
+    let res: Result<i64, RedisError> = script
+        .key(format!("claim:{}", key))
+        .arg(token)
+        .invoke_async(conn)
+        .await;
+    match res {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Could not release the claim on {} - {}", key, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owns_key_splits_by_guild() {
+        let sharding = Sharding::Range {
+            first: 1,
+            last: 1,
+            total: 2,
+        };
+        // Guild IDs carry the shard in the bits above the lowest 22.
+        assert!(owns_key(&sharding, &format!("complete:7:{}", 1_u64 << 22)));
+        assert!(owns_key(
+            &sharding,
+            &format!("complete:7:{}:2", 3_u64 << 22)
+        ));
+        assert!(!owns_key(&sharding, &format!("complete:7:{}", 2_u64 << 22)));
+        assert!(!owns_key(&sharding, "complete:7:not-a-guild"));
+        assert!(owns_key(&Sharding::Auto, "complete:7:not-a-guild"));
     }
 }
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use redis::aio::ConnectionManager;
+use tracing::*;
+
+use crate::metrics::metrics;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Exponential backoff, doubling from one second up to `max`.
+pub struct Backoff {
+    current: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(max: Duration) -> Backoff {
+        Backoff {
+            current: INITIAL_BACKOFF,
+            max,
+        }
+    }
+
+    /// Returns how long to wait before the next try, and increases the wait for the one after.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = std::cmp::min(self.current * 2, self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = INITIAL_BACKOFF;
+    }
+}
+
+/// Connects to redis, retrying with backoff until it succeeds. Once connected the
+/// `ConnectionManager` reconnects by itself whenever the connection drops.
+pub async fn connect(client: redis::Client, max_backoff: Duration) -> ConnectionManager {
+    let mut backoff = Backoff::new(max_backoff);
+    loop {
+        match ConnectionManager::new(client.clone()).await {
+            Ok(conn) => {
+                info!("Connected to Redis.");
+                return conn;
+            }
+            Err(err) => {
+                metrics().dependency_error("redis");
+                let delay = backoff.next_delay();
+                error!(
+                    "Could not connect to redis, retrying in {}s - {}",
+                    delay.as_secs(),
+                    err
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mongodb::bson::{DateTime, Document};
+use redis::aio::ConnectionManager;
+use serenity::async_trait;
+use serenity::futures::StreamExt;
+use tracing::*;
+
+use super::GuildRepository;
+use crate::dbmodels::guild::Guild;
+use crate::metrics::metrics;
+use crate::redis_conn::Backoff;
+
+/// Redis pub/sub channel carrying the IDs of guilds whose settings changed.
+const INVALIDATION_CHANNEL: &str = "guild_invalidations";
+
+/// Tells the other replicas that a guild changed.
+#[async_trait]
+pub trait InvalidationPublisher: Send + Sync {
+    async fn publish(&self, guild_id: &str);
+}
+
+/// Publishes invalidations on `INVALIDATION_CHANNEL`, where `listen_for_invalidations` picks
+/// them up. A failed publish only means the other replicas see the change once their entry
+/// expires.
+pub struct RedisPublisher {
+    redis: ConnectionManager,
+}
+
+#[async_trait]
+impl InvalidationPublisher for RedisPublisher {
+    async fn publish(&self, guild_id: &str) {
+        let mut conn = self.redis.clone();
+        let res: Result<i64, redis::RedisError> = redis::cmd("PUBLISH")
+            .arg(INVALIDATION_CHANNEL)
+            .arg(guild_id)
+            .query_async(&mut conn)
+            .await;
+        if let Err(err) = res {
+            metrics().dependency_error("redis");
+            warn!(
+                "Could not publish the cache invalidation for guild {} - {}",
+                guild_id, err
+            );
+        }
+    }
+}
+
+/// Keeps recently read guild settings in memory for `ttl`. Every write drops the entry here and
+/// tells the other replicas to drop theirs.
+pub struct CachedGuildRepository {
+    inner: Arc<dyn GuildRepository>,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+    publisher: Box<dyn InvalidationPublisher>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Guilds that don't exist are cached too, as `None`.
+    guilds: HashMap<String, (Instant, Option<Guild>)>,
+    /// Bumped whenever a guild is invalidated, so a read that was in flight at the time doesn't
+    /// put the old settings back.
+    generations: HashMap<String, u64>,
+    /// Bumped when the whole cache is cleared.
+    epoch: u64,
+}
+
+impl Entries {
+    fn generation(&self, guild_id: &str) -> (u64, u64) {
+        (
+            self.epoch,
+            self.generations.get(guild_id).copied().unwrap_or(0),
+        )
+    }
+}
+
+impl CachedGuildRepository {
+    pub fn new(
+        inner: Arc<dyn GuildRepository>,
+        ttl: Duration,
+        redis: ConnectionManager,
+    ) -> CachedGuildRepository {
+        CachedGuildRepository::with_publisher(inner, ttl, Box::new(RedisPublisher { redis }))
+    }
+
+    pub fn with_publisher(
+        inner: Arc<dyn GuildRepository>,
+        ttl: Duration,
+        publisher: Box<dyn InvalidationPublisher>,
+    ) -> CachedGuildRepository {
+        CachedGuildRepository {
+            inner,
+            ttl,
+            entries: Mutex::new(Entries::default()),
+            publisher,
+        }
+    }
+
+    /// The cached guild, or the generation to pass to `store` once it has been read.
+    fn cached(&self, guild_id: &str) -> Result<Option<Guild>, Option<(u64, u64)>> {
+        let entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return Err(None),
+        };
+        match entries.guilds.get(guild_id) {
+            Some((stored, guild)) if stored.elapsed() < self.ttl => Ok(guild.clone()),
+            _ => Err(Some(entries.generation(guild_id))),
+        }
+    }
+
+    /// Caches the guild unless it was invalidated since `generation`.
+    fn store(&self, guild_id: &str, generation: (u64, u64), guild: Option<Guild>) {
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.generation(guild_id) == generation {
+                entries
+                    .guilds
+                    .insert(guild_id.to_string(), (Instant::now(), guild));
+            }
+        }
+    }
+
+    fn invalidate_local(&self, guild_id: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.guilds.remove(guild_id);
+            *entries.generations.entry(guild_id.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.guilds.clear();
+            // The epoch covers every guild, so the generations can start over.
+            entries.generations.clear();
+            entries.epoch += 1;
+        }
+    }
+
+    /// Drops the guild here and on every other replica.
+    async fn invalidate(&self, guild_id: &str) {
+        self.invalidate_local(guild_id);
+        self.publisher.publish(guild_id).await;
+    }
+}
+
+#[async_trait]
+impl GuildRepository for CachedGuildRepository {
+    async fn ensure_indexes(&self) -> Result<(), String> {
+        self.inner.ensure_indexes().await
+    }
+
+    async fn get(&self, guild_id: &str) -> Result<Option<Guild>, String> {
+        let generation = match self.cached(guild_id) {
+            Ok(guild) => return Ok(guild),
+            Err(generation) => generation,
+        };
+        let guild = self.inner.get(guild_id).await?;
+        if let Some(generation) = generation {
+            self.store(guild_id, generation, guild.clone());
+        }
+        Ok(guild)
+    }
+
+    async fn list(&self) -> Result<Vec<Guild>, String> {
+        self.inner.list().await
+    }
+
+    async fn save(&self, guild: &Guild) -> Result<(), String> {
+        let res = self.inner.save(guild).await;
+        self.invalidate(&guild.guild_ID).await;
+        res
+    }
+
+    async fn set_fields(&self, guild_id: &str, fields: Document) -> Result<Option<Guild>, String> {
+        let res = self.inner.set_fields(guild_id, fields).await;
+        self.invalidate(guild_id).await;
+        res
+    }
+
+    async fn add_to_list(
+        &self,
+        guild_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<Guild>, String> {
+        let res = self.inner.add_to_list(guild_id, field, value).await;
+        self.invalidate(guild_id).await;
+        res
+    }
+
+    async fn remove_from_list(
+        &self,
+        guild_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<Guild>, String> {
+        let res = self.inner.remove_from_list(guild_id, field, value).await;
+        self.invalidate(guild_id).await;
+        res
+    }
+
+    async fn activate(&self, guild_id: &str) -> Result<(), String> {
+        let res = self.inner.activate(guild_id).await;
+        self.invalidate(guild_id).await;
+        res
+    }
+
+    async fn deactivate(&self, guild_id: &str) -> Result<(), String> {
+        let res = self.inner.deactivate(guild_id).await;
+        self.invalidate(guild_id).await;
+        res
+    }
+
+    async fn expired(&self, cutoff: DateTime) -> Result<Vec<String>, String> {
+        self.inner.expired(cutoff).await
+    }
+
+    async fn delete_inactive(&self, guild_id: &str) -> Result<bool, String> {
+        let res = self.inner.delete_inactive(guild_id).await;
+        self.invalidate(guild_id).await;
+        res
+    }
+}
+
+/// Drops cache entries when another replica changes a guild. Pub/sub needs a connection of its
+/// own, so this reconnects by itself and clears the whole cache after every reconnect, as
+/// messages sent in the meantime are lost.
+pub async fn listen_for_invalidations(
+    client: redis::Client,
+    cache: Arc<CachedGuildRepository>,
+    max_backoff: Duration,
+) {
+    let mut backoff = Backoff::new(max_backoff);
+    loop {
+        match subscribe(&client).await {
+            Ok(mut pubsub) => {
+                backoff.reset();
+                cache.clear();
+                debug!("Listening for guild cache invalidations.");
+                let mut messages = pubsub.on_message();
+                while let Some(msg) = messages.next().await {
+                    match msg.get_payload::<String>() {
+                        Ok(guild_id) => cache.invalidate_local(&guild_id),
+                        Err(err) => warn!("Invalid cache invalidation message - {}", err),
+                    }
+                }
+                warn!("Lost the guild cache invalidation subscription.");
+            }
+            Err(err) => {
+                metrics().dependency_error("redis");
+                error!("{}", err);
+            }
+        }
+        tokio::time::sleep(backoff.next_delay()).await;
+    }
+}
+
+async fn subscribe(client: &redis::Client) -> Result<redis::aio::PubSub, String> {
+    let conn = match client.get_async_connection().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(format!(
+                "Could not connect to redis for cache invalidations - {}",
+                err
+            ))
+        }
+    };
+    let mut pubsub = conn.into_pubsub();
+    match pubsub.subscribe(INVALIDATION_CHANNEL).await {
+        Ok(()) => Ok(pubsub),
+        Err(err) => Err(format!(
+            "Could not subscribe to {} - {}",
+            INVALIDATION_CHANNEL, err
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use mongodb::bson::doc;
+    use tokio::sync::{oneshot, Notify};
+
+    use super::*;
+    use crate::repository::memory::MemoryGuildRepository;
+
+    #[derive(Default)]
+    struct RecordingPublisher {
+        published: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl InvalidationPublisher for RecordingPublisher {
+        async fn publish(&self, guild_id: &str) {
+            self.published.lock().unwrap().push(guild_id.to_string());
+        }
+    }
+
+    /// Counts reads, and can hold the next read after it loaded the guild until told to go on.
+    #[derive(Default)]
+    struct SlowRepository {
+        inner: MemoryGuildRepository,
+        reads: AtomicUsize,
+        loaded: Notify,
+        gate: tokio::sync::Mutex<Option<oneshot::Receiver<()>>>,
+    }
+
+    #[async_trait]
+    impl GuildRepository for SlowRepository {
+        async fn ensure_indexes(&self) -> Result<(), String> {
+            Ok(())
+        }
+        async fn get(&self, guild_id: &str) -> Result<Option<Guild>, String> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let guild = self.inner.get(guild_id).await?;
+            if let Some(gate) = self.gate.lock().await.take() {
+                self.loaded.notify_one();
+                let _ = gate.await;
+            }
+            Ok(guild)
+        }
+        async fn list(&self) -> Result<Vec<Guild>, String> {
+            self.inner.list().await
+        }
+        async fn save(&self, guild: &Guild) -> Result<(), String> {
+            self.inner.save(guild).await
+        }
+        async fn set_fields(
+            &self,
+            guild_id: &str,
+            fields: Document,
+        ) -> Result<Option<Guild>, String> {
+            self.inner.set_fields(guild_id, fields).await
+        }
+        async fn add_to_list(
+            &self,
+            guild_id: &str,
+            field: &str,
+            value: &str,
+        ) -> Result<Option<Guild>, String> {
+            self.inner.add_to_list(guild_id, field, value).await
+        }
+        async fn remove_from_list(
+            &self,
+            guild_id: &str,
+            field: &str,
+            value: &str,
+        ) -> Result<Option<Guild>, String> {
+            self.inner.remove_from_list(guild_id, field, value).await
+        }
+        async fn activate(&self, guild_id: &str) -> Result<(), String> {
+            self.inner.activate(guild_id).await
+        }
+        async fn deactivate(&self, guild_id: &str) -> Result<(), String> {
+            self.inner.deactivate(guild_id).await
+        }
+        async fn expired(&self, cutoff: DateTime) -> Result<Vec<String>, String> {
+            self.inner.expired(cutoff).await
+        }
+        async fn delete_inactive(&self, guild_id: &str) -> Result<bool, String> {
+            self.inner.delete_inactive(guild_id).await
+        }
+    }
+
+    struct Setup {
+        inner: Arc<SlowRepository>,
+        cache: Arc<CachedGuildRepository>,
+        published: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn setup(ttl: Duration) -> Setup {
+        let inner = Arc::new(SlowRepository::default());
+        inner.activate("1").await.unwrap();
+        let publisher = RecordingPublisher::default();
+        let published = Arc::clone(&publisher.published);
+        let cache = Arc::new(CachedGuildRepository::with_publisher(
+            Arc::clone(&inner) as Arc<dyn GuildRepository>,
+            ttl,
+            Box::new(publisher),
+        ));
+        Setup {
+            inner,
+            cache,
+            published,
+        }
+    }
+
+    async fn age(repo: &dyn GuildRepository) -> u64 {
+        repo.get("1").await.unwrap().unwrap().verification_age
+    }
+
+    #[tokio::test]
+    async fn reads_are_cached_until_the_ttl_expires() {
+        let setup = setup(Duration::from_millis(50)).await;
+        age(setup.cache.as_ref()).await;
+        age(setup.cache.as_ref()).await;
+        assert_eq!(setup.inner.reads.load(Ordering::SeqCst), 1);
+
+        // Changed behind the cache's back, so the old value is served until it expires.
+        setup
+            .inner
+            .set_fields("1", doc! {"verification_age": 3_i64})
+            .await
+            .unwrap();
+        assert_eq!(age(setup.cache.as_ref()).await, 0);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(age(setup.cache.as_ref()).await, 3);
+        assert_eq!(setup.inner.reads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn missing_guilds_are_cached_too() {
+        let setup = setup(Duration::from_secs(60)).await;
+        assert!(setup.cache.get("2").await.unwrap().is_none());
+        assert!(setup.cache.get("2").await.unwrap().is_none());
+        assert_eq!(setup.inner.reads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn writes_invalidate_here_and_on_other_replicas() {
+        let setup = setup(Duration::from_secs(60)).await;
+        assert_eq!(age(setup.cache.as_ref()).await, 0);
+        setup
+            .cache
+            .set_fields("1", doc! {"verification_age": 3_i64})
+            .await
+            .unwrap();
+        assert_eq!(age(setup.cache.as_ref()).await, 3);
+        assert_eq!(*setup.published.lock().unwrap(), vec!["1"]);
+    }
+
+    #[tokio::test]
+    async fn invalidations_from_other_replicas_drop_the_entry() {
+        let setup = setup(Duration::from_secs(60)).await;
+        assert_eq!(age(setup.cache.as_ref()).await, 0);
+        setup
+            .inner
+            .set_fields("1", doc! {"verification_age": 3_i64})
+            .await
+            .unwrap();
+        setup.cache.invalidate_local("1");
+        assert_eq!(age(setup.cache.as_ref()).await, 3);
+
+        setup
+            .inner
+            .set_fields("1", doc! {"verification_age": 4_i64})
+            .await
+            .unwrap();
+        setup.cache.clear();
+        assert_eq!(age(setup.cache.as_ref()).await, 4);
+    }
+
+    /// Starts a read that loads the guild and then waits, runs `change` and lets the read finish.
+    async fn race(setup: &Setup, change: impl std::future::Future<Output = ()>) {
+        let (release, gate) = oneshot::channel();
+        *setup.inner.gate.lock().await = Some(gate);
+        let cache = Arc::clone(&setup.cache);
+        let read = tokio::spawn(async move { age(cache.as_ref()).await });
+        setup.inner.loaded.notified().await;
+
+        change.await;
+        release.send(()).unwrap();
+        // The read itself may return what it loaded, it just must not be cached.
+        assert_eq!(read.await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn read_racing_a_write_does_not_cache_stale_settings() {
+        let setup = setup(Duration::from_secs(60)).await;
+        race(&setup, async {
+            setup
+                .cache
+                .set_fields("1", doc! {"verification_age": 3_i64})
+                .await
+                .unwrap();
+        })
+        .await;
+        assert_eq!(age(setup.cache.as_ref()).await, 3);
+    }
+
+    #[tokio::test]
+    async fn read_racing_a_clear_does_not_cache_stale_settings() {
+        let setup = setup(Duration::from_secs(60)).await;
+        race(&setup, async {
+            setup
+                .inner
+                .set_fields("1", doc! {"verification_age": 3_i64})
+                .await
+                .unwrap();
+            setup.cache.clear();
+        })
+        .await;
+        assert_eq!(age(setup.cache.as_ref()).await, 3);
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{self, Bson, DateTime, Document};
+use serenity::async_trait;
+
+use super::{AttemptRepository, AuditRepository, ConnectionRepository, GuildRepository};
+use crate::dbmodels::audit::PrivacyAuditRecord;
+use crate::dbmodels::guild::{default_guild, Guild, SocialMediaAccounts};
+use crate::dbmodels::verification::VerificationAttempt;
+
+// The locks are never held across an await, so a std Mutex is enough.
+
+#[derive(Default)]
+pub struct MemoryGuildRepository {
+    guilds: Mutex<HashMap<String, Guild>>,
+}
+
+impl MemoryGuildRepository {
+    /// Runs `change` on the guild as a document, the way mongo would apply an update to it.
+    fn update(
+        &self,
+        guild_id: &str,
+        change: impl FnOnce(&mut Document) -> Result<(), String>,
+    ) -> Result<Option<Guild>, String> {
+        let mut guilds = lock(&self.guilds)?;
+        let guild = match guilds.get_mut(guild_id) {
+            Some(guild) => guild,
+            None => return Ok(None),
+        };
+        let mut doc = match bson::to_document(&*guild) {
+            Ok(doc) => doc,
+            Err(err) => return Err(format!("{:?}", err)),
+        };
+        change(&mut doc)?;
+        *guild = match bson::from_document(doc) {
+            Ok(updated) => updated,
+            Err(err) => return Err(format!("Invalid update of guild {} - {:?}", guild_id, err)),
+        };
+        Ok(Some(guild.clone()))
+    }
+}
+
+#[async_trait]
+impl GuildRepository for MemoryGuildRepository {
+    async fn ensure_indexes(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn get(&self, guild_id: &str) -> Result<Option<Guild>, String> {
+        Ok(lock(&self.guilds)?.get(guild_id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<Guild>, String> {
+        let mut guilds: Vec<Guild> = lock(&self.guilds)?.values().cloned().collect();
+        guilds.sort_by(|a, b| a.guild_ID.cmp(&b.guild_ID));
+        Ok(guilds)
+    }
+
+    async fn save(&self, guild: &Guild) -> Result<(), String> {
+        lock(&self.guilds)?.insert(guild.guild_ID.clone(), guild.clone());
+        Ok(())
+    }
+
+    async fn set_fields(&self, guild_id: &str, fields: Document) -> Result<Option<Guild>, String> {
+        self.update(guild_id, |doc| {
+            for (path, value) in fields {
+                at_path(doc, &path, true, |field| *field = value)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn add_to_list(
+        &self,
+        guild_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<Guild>, String> {
+        self.update(guild_id, |doc| {
+            at_path(doc, field, true, |list| match list {
+                Bson::Array(items) => {
+                    if !items.iter().any(|item| item.as_str() == Some(value)) {
+                        items.push(Bson::String(value.to_string()));
+                    }
+                }
+                _ => *list = Bson::Array(vec![Bson::String(value.to_string())]),
+            })
+        })
+    }
+
+    async fn remove_from_list(
+        &self,
+        guild_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<Guild>, String> {
+        self.update(guild_id, |doc| {
+            at_path(doc, field, false, |list| {
+                if let Bson::Array(items) = list {
+                    items.retain(|item| item.as_str() != Some(value));
+                }
+            })
+        })
+    }
+
+    async fn activate(&self, guild_id: &str) -> Result<(), String> {
+        let mut guilds = lock(&self.guilds)?;
+        let guild = guilds
+            .entry(guild_id.to_string())
+            .or_insert_with(|| default_guild(guild_id));
+        guild.active = true;
+        guild.left_at = None;
+        Ok(())
+    }
+
+    async fn deactivate(&self, guild_id: &str) -> Result<(), String> {
+        if let Some(guild) = lock(&self.guilds)?.get_mut(guild_id) {
+            guild.active = false;
+            guild.left_at = Some(DateTime::now());
+        }
+        Ok(())
+    }
+
+    async fn expired(&self, cutoff: DateTime) -> Result<Vec<String>, String> {
+        Ok(lock(&self.guilds)?
+            .values()
+            .filter(|guild| !guild.active && matches!(guild.left_at, Some(left) if left <= cutoff))
+            .map(|guild| guild.guild_ID.clone())
+            .collect())
+    }
+
+    async fn delete_inactive(&self, guild_id: &str) -> Result<bool, String> {
+        let mut guilds = lock(&self.guilds)?;
+        if matches!(guilds.get(guild_id), Some(guild) if !guild.active) {
+            guilds.remove(guild_id);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryConnectionRepository {
+    accounts: Mutex<HashMap<String, SocialMediaAccounts>>,
+}
+
+#[async_trait]
+impl ConnectionRepository for MemoryConnectionRepository {
+    async fn add(&self, account: SocialMediaAccounts) -> Result<String, String> {
+        let id = ObjectId::new().to_hex();
+        lock(&self.accounts)?.insert(id.clone(), account);
+        Ok(id)
+    }
+
+    async fn take_by_id(&self, id: &str) -> Result<Option<SocialMediaAccounts>, String> {
+        Ok(lock(&self.accounts)?.remove(id))
+    }
+
+    async fn take_matching(
+        &self,
+        user_id: &str,
+        account_type: &str,
+        account_id_hash: &str,
+    ) -> Result<Vec<SocialMediaAccounts>, String> {
+        let mut accounts = lock(&self.accounts)?;
+        let ids: Vec<String> = accounts
+            .iter()
+            .filter(|(_, acc)| {
+                acc.user_ID == user_id
+                    && acc.account_type == account_type
+                    && acc.account_id_hash == account_id_hash
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        Ok(ids.iter().filter_map(|id| accounts.remove(id)).collect())
+    }
+
+    async fn restore(&self, restored: Vec<SocialMediaAccounts>) -> Result<(), String> {
+        let mut accounts = lock(&self.accounts)?;
+        for account in restored {
+            accounts.insert(ObjectId::new().to_hex(), account);
+        }
+        Ok(())
+    }
+
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<SocialMediaAccounts>, String> {
+        Ok(lock(&self.accounts)?
+            .values()
+            .filter(|acc| acc.user_ID == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_for_user(&self, user_id: &str) -> Result<u64, String> {
+        let mut accounts = lock(&self.accounts)?;
+        let before = accounts.len();
+        accounts.retain(|_, acc| acc.user_ID != user_id);
+        Ok((before - accounts.len()) as u64)
+    }
+
+    async fn migrate_plaintext_ids(&self) -> Result<u64, String> {
+        // Nothing older than this version can be in memory.
+        Ok(0)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryAttemptRepository {
+    attempts: Mutex<Vec<VerificationAttempt>>,
+}
+
+#[async_trait]
+impl AttemptRepository for MemoryAttemptRepository {
+    async fn record(&self, attempt: VerificationAttempt) -> Result<(), String> {
+        lock(&self.attempts)?.push(attempt);
+        Ok(())
+    }
+
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<VerificationAttempt>, String> {
+        Ok(lock(&self.attempts)?
+            .iter()
+            .filter(|attempt| attempt.user_ID == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_for_user(&self, user_id: &str) -> Result<u64, String> {
+        let mut attempts = lock(&self.attempts)?;
+        let before = attempts.len();
+        attempts.retain(|attempt| attempt.user_ID != user_id);
+        Ok((before - attempts.len()) as u64)
+    }
+
+    async fn delete_for_guild(&self, guild_id: &str) -> Result<u64, String> {
+        let mut attempts = lock(&self.attempts)?;
+        let before = attempts.len();
+        attempts.retain(|attempt| attempt.guild_ID != guild_id);
+        Ok((before - attempts.len()) as u64)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryAuditRepository {
+    records: Mutex<Vec<PrivacyAuditRecord>>,
+}
+
+#[async_trait]
+impl AuditRepository for MemoryAuditRepository {
+    async fn record(&self, record: PrivacyAuditRecord) -> Result<(), String> {
+        lock(&self.records)?.push(record);
+        Ok(())
+    }
+}
+
+/// Runs `change` on the value at the dotted `path`. With `create` missing documents on the way
+/// are created and a missing value starts as null, like mongo's `$set` and `$addToSet` do.
+/// Otherwise nothing happens if the path doesn't exist.
+fn at_path(
+    doc: &mut Document,
+    path: &str,
+    create: bool,
+    change: impl FnOnce(&mut Bson),
+) -> Result<(), String> {
+    let (key, rest) = match path.split_once('.') {
+        Some((key, rest)) => (key, Some(rest)),
+        None => (path, None),
+    };
+    if !create && !doc.contains_key(key) {
+        return Ok(());
+    }
+    match (doc.entry(key.to_string()).or_insert(Bson::Null), rest) {
+        (value, None) => {
+            change(value);
+            Ok(())
+        }
+        (Bson::Document(inner), Some(rest)) => at_path(inner, rest, create, change),
+        (value @ Bson::Null, Some(rest)) => {
+            let mut inner = Document::new();
+            at_path(&mut inner, rest, create, change)?;
+            *value = Bson::Document(inner);
+            Ok(())
+        }
+        (_, Some(_)) => Err(format!("Can't update {}, {} is not a document", path, key)),
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<std::sync::MutexGuard<'_, T>, String> {
+    match mutex.lock() {
+        Ok(guard) => Ok(guard),
+        Err(_) => Err("In-memory store lock was poisoned.".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::*;
+
+    fn account(user_id: &str, account_type: &str, hash: &str) -> SocialMediaAccounts {
+        SocialMediaAccounts {
+            user_ID: user_id.to_string(),
+            account_type: account_type.to_string(),
+            account_id_hash: hash.to_string(),
+            account_id_enc: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn save_and_get_guild() {
+        let repo = MemoryGuildRepository::default();
+        assert!(repo.get("1").await.unwrap().is_none());
+
+        let mut guild = default_guild("1");
+        guild.verification_age = 7;
+        repo.save(&guild).await.unwrap();
+        assert_eq!(repo.get("1").await.unwrap().unwrap().verification_age, 7);
+    }
+
+    #[tokio::test]
+    async fn activate_keeps_existing_settings() {
+        let repo = MemoryGuildRepository::default();
+        repo.activate("1").await.unwrap();
+        let guild = repo.get("1").await.unwrap().unwrap();
+        assert!(guild.active);
+        assert_eq!(guild.verification_role_ID, "0");
+
+        repo.set_fields("1", doc! {"verification_role_ID": "5"})
+            .await
+            .unwrap();
+        repo.deactivate("1").await.unwrap();
+        let guild = repo.get("1").await.unwrap().unwrap();
+        assert!(!guild.active);
+        assert!(guild.left_at.is_some());
+
+        repo.activate("1").await.unwrap();
+        let guild = repo.get("1").await.unwrap().unwrap();
+        assert!(guild.active);
+        assert!(guild.left_at.is_none());
+        assert_eq!(guild.verification_role_ID, "5");
+    }
+
+    #[tokio::test]
+    async fn expired_only_lists_inactive_guilds_past_the_cutoff() {
+        let repo = MemoryGuildRepository::default();
+        repo.activate("active").await.unwrap();
+        repo.activate("left").await.unwrap();
+        repo.deactivate("left").await.unwrap();
+
+        let past = DateTime::from_millis(DateTime::now().timestamp_millis() - 60_000);
+        let future = DateTime::from_millis(DateTime::now().timestamp_millis() + 60_000);
+        assert!(repo.expired(past).await.unwrap().is_empty());
+        assert_eq!(repo.expired(future).await.unwrap(), vec!["left"]);
+    }
+
+    #[tokio::test]
+    async fn delete_inactive_leaves_active_guilds() {
+        let repo = MemoryGuildRepository::default();
+        repo.activate("1").await.unwrap();
+        assert!(!repo.delete_inactive("1").await.unwrap());
+        assert!(repo.get("1").await.unwrap().is_some());
+
+        repo.deactivate("1").await.unwrap();
+        assert!(repo.delete_inactive("1").await.unwrap());
+        assert!(repo.get("1").await.unwrap().is_none());
+        assert!(!repo.delete_inactive("1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn set_fields_only_touches_the_given_fields() {
+        let repo = MemoryGuildRepository::default();
+        assert!(repo
+            .set_fields("1", doc! {"verification_age": 3_i64})
+            .await
+            .unwrap()
+            .is_none());
+
+        repo.activate("1").await.unwrap();
+        repo.deactivate("1").await.unwrap();
+        let guild = repo
+            .set_fields(
+                "1",
+                doc! {"verification_age": 3_i64, "guild_settings.mfa_bonus": 2_i64},
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(guild.verification_age, 3);
+        assert_eq!(guild.guild_settings.mfa_bonus, 2);
+        assert_eq!(guild.guild_settings.zero_point, 0);
+        // Settings changes must not bring back a guild that left.
+        assert!(!guild.active);
+        assert!(guild.left_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn lists_hold_each_value_once() {
+        let repo = MemoryGuildRepository::default();
+        repo.activate("1").await.unwrap();
+        repo.add_to_list("1", "mod_role_IDs", "5").await.unwrap();
+        repo.add_to_list("1", "mod_role_IDs", "6").await.unwrap();
+        let guild = repo
+            .add_to_list("1", "mod_role_IDs", "5")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(guild.mod_role_IDs, vec!["5", "6"]);
+
+        let guild = repo
+            .remove_from_list("1", "mod_role_IDs", "5")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(guild.mod_role_IDs, vec!["6"]);
+    }
+
+    #[tokio::test]
+    async fn grants_are_edited_one_list_at_a_time() {
+        let repo = MemoryGuildRepository::default();
+        repo.activate("1").await.unwrap();
+        let guild = repo
+            .add_to_list("1", "command_grants.setminage.role_IDs", "5")
+            .await
+            .unwrap()
+            .unwrap();
+        let grant = &guild.command_grants["setminage"];
+        assert_eq!(grant.role_IDs, vec!["5"]);
+        assert!(grant.user_IDs.is_empty());
+
+        let guild = repo
+            .remove_from_list("1", "command_grants.setminage.role_IDs", "5")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(guild.command_grants["setminage"].is_empty());
+        // Nothing is created when there is nothing to remove from.
+        let guild = repo
+            .remove_from_list("1", "command_grants.other.user_IDs", "5")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!guild.command_grants.contains_key("other"));
+    }
+
+    #[tokio::test]
+    async fn take_matching_and_restore_accounts() {
+        let repo = MemoryConnectionRepository::default();
+        repo.add(account("1", "twitter", "a")).await.unwrap();
+        repo.add(account("1", "twitter", "a")).await.unwrap();
+        repo.add(account("1", "reddit", "a")).await.unwrap();
+        repo.add(account("2", "twitter", "a")).await.unwrap();
+
+        let taken = repo.take_matching("1", "twitter", "a").await.unwrap();
+        assert_eq!(taken.len(), 2);
+        assert_eq!(repo.list_for_user("1").await.unwrap().len(), 1);
+        assert!(repo
+            .take_matching("1", "twitter", "a")
+            .await
+            .unwrap()
+            .is_empty());
+
+        repo.restore(taken).await.unwrap();
+        assert_eq!(repo.list_for_user("1").await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn take_by_id_removes_one_account() {
+        let repo = MemoryConnectionRepository::default();
+        let id = repo.add(account("1", "twitter", "a")).await.unwrap();
+        assert!(repo.take_by_id(&id).await.unwrap().is_some());
+        assert!(repo.take_by_id(&id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_for_user_leaves_other_users() {
+        let repo = MemoryConnectionRepository::default();
+        repo.add(account("1", "twitter", "a")).await.unwrap();
+        repo.add(account("1", "reddit", "b")).await.unwrap();
+        repo.add(account("2", "twitter", "a")).await.unwrap();
+
+        assert_eq!(repo.delete_for_user("1").await.unwrap(), 2);
+        assert!(repo.list_for_user("1").await.unwrap().is_empty());
+        assert_eq!(repo.list_for_user("2").await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,154 @@
+pub mod cached;
+pub mod memory;
+pub mod mongo;
+
+use std::sync::Arc;
+
+use mongodb::bson::{DateTime, Document};
+use serenity::async_trait;
+use tracing::{info, warn};
+
+use crate::config::{Config, StorageBackend};
+use crate::dbmodels::audit::PrivacyAuditRecord;
+use crate::dbmodels::guild::{Guild, SocialMediaAccounts};
+use crate::dbmodels::verification::VerificationAttempt;
+use crate::mongo_conn::{get_mongo_client, ping};
+
+/// Storage for the per guild settings documents.
+#[async_trait]
+pub trait GuildRepository: Send + Sync {
+    /// Creates whatever indexes the backend needs, called once at startup.
+    async fn ensure_indexes(&self) -> Result<(), String>;
+    async fn get(&self, guild_id: &str) -> Result<Option<Guild>, String>;
+    /// Every stored guild, sorted by ID.
+    async fn list(&self) -> Result<Vec<Guild>, String>;
+    /// Replaces the stored settings of `guild.guild_ID` with `guild`. Only meant for restoring a
+    /// dumped guild, settings are changed with the targeted updates below.
+    async fn save(&self, guild: &Guild) -> Result<(), String>;
+    /// Sets the given fields, dotted paths reach into nested documents. Returns the updated guild,
+    /// `None` if it doesn't exist.
+    async fn set_fields(&self, guild_id: &str, fields: Document) -> Result<Option<Guild>, String>;
+    /// Adds `value` to the list at `field` unless it is in there already.
+    async fn add_to_list(
+        &self,
+        guild_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<Guild>, String>;
+    /// Removes `value` from the list at `field`.
+    async fn remove_from_list(
+        &self,
+        guild_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<Guild>, String>;
+    /// Creates the guild with default settings if it is new and marks it as active.
+    /// Existing settings are left untouched.
+    async fn activate(&self, guild_id: &str) -> Result<(), String>;
+    /// Marks the guild as inactive and records when the bot left.
+    async fn deactivate(&self, guild_id: &str) -> Result<(), String>;
+    /// IDs of inactive guilds that were left at or before `cutoff`.
+    async fn expired(&self, cutoff: DateTime) -> Result<Vec<String>, String>;
+    /// Deletes the guild, but only if it is still inactive. Returns whether it was deleted.
+    async fn delete_inactive(&self, guild_id: &str) -> Result<bool, String>;
+}
+
+/// Storage for the social accounts linked to Discord users.
+#[async_trait]
+pub trait ConnectionRepository: Send + Sync {
+    /// Stores the account and returns its ID.
+    async fn add(&self, account: SocialMediaAccounts) -> Result<String, String>;
+    /// Deletes the account with the given ID and returns it.
+    async fn take_by_id(&self, id: &str) -> Result<Option<SocialMediaAccounts>, String>;
+    /// Deletes every account matching the user, type and hashed ID, and returns them.
+    async fn take_matching(
+        &self,
+        user_id: &str,
+        account_type: &str,
+        account_id_hash: &str,
+    ) -> Result<Vec<SocialMediaAccounts>, String>;
+    /// Puts previously removed accounts back.
+    async fn restore(&self, accounts: Vec<SocialMediaAccounts>) -> Result<(), String>;
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<SocialMediaAccounts>, String>;
+    async fn delete_for_user(&self, user_id: &str) -> Result<u64, String>;
+    /// Converts accounts stored by older versions with a plain text ID, returns how many.
+    async fn migrate_plaintext_ids(&self) -> Result<u64, String>;
+}
+
+/// Storage for the history of processed verifications.
+#[async_trait]
+pub trait AttemptRepository: Send + Sync {
+    async fn record(&self, attempt: VerificationAttempt) -> Result<(), String>;
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<VerificationAttempt>, String>;
+    async fn delete_for_user(&self, user_id: &str) -> Result<u64, String>;
+    async fn delete_for_guild(&self, guild_id: &str) -> Result<u64, String>;
+}
+
+/// Storage for privacy audit records.
+#[async_trait]
+pub trait AuditRepository: Send + Sync {
+    async fn record(&self, record: PrivacyAuditRecord) -> Result<(), String>;
+}
+
+/// The set of repositories the commands and background jobs run against.
+#[derive(Clone)]
+pub struct Repositories {
+    pub guilds: Arc<dyn GuildRepository>,
+    pub connections: Arc<dyn ConnectionRepository>,
+    pub attempts: Arc<dyn AttemptRepository>,
+    pub audit: Arc<dyn AuditRepository>,
+}
+
+impl Repositories {
+    pub fn mongo(client: mongodb::Client, config: &Config) -> Repositories {
+        Repositories {
+            guilds: Arc::new(mongo::MongoGuildRepository::new(
+                client.clone(),
+                &config.db_name,
+            )),
+            connections: Arc::new(mongo::MongoConnectionRepository::new(
+                client.clone(),
+                &config.verification_db_name,
+            )),
+            attempts: Arc::new(mongo::MongoAttemptRepository::new(
+                client.clone(),
+                &config.verification_db_name,
+            )),
+            audit: Arc::new(mongo::MongoAuditRepository::new(client, &config.db_name)),
+        }
+    }
+
+    /// Connects to the configured storage backend. The Mongo client is handed back as well, for
+    /// the health checks.
+    pub async fn connect(
+        config: &Config,
+    ) -> Result<(Repositories, Option<mongodb::Client>), String> {
+        match config.storage_backend {
+            StorageBackend::Memory => {
+                warn!("Using the in-memory storage backend.");
+                Ok((Repositories::in_memory(), None))
+            }
+            StorageBackend::Mongo => {
+                let client = match get_mongo_client(config).await {
+                    Ok(client) => client,
+                    Err(err) => {
+                        return Err(format!("Could not create the MongoDB client - {}", err))
+                    }
+                };
+                ping(&client).await?;
+                info!("Connected to MongoDB.");
+                Ok((Repositories::mongo(client.clone(), config), Some(client)))
+            }
+        }
+    }
+
+    /// Keeps everything in process memory, nothing survives a restart.
+    pub fn in_memory() -> Repositories {
+        Repositories {
+            guilds: Arc::new(memory::MemoryGuildRepository::default()),
+            connections: Arc::new(memory::MemoryConnectionRepository::default()),
+            attempts: Arc::new(memory::MemoryAttemptRepository::default()),
+            audit: Arc::new(memory::MemoryAuditRepository::default()),
+        }
+    }
+}
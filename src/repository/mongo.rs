@@ -0,0 +1,413 @@
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{self, doc, DateTime, Document};
+use mongodb::options::{
+    FindOneAndUpdateOptions, FindOptions, IndexOptions, ReplaceOptions, ReturnDocument,
+    UpdateOptions,
+};
+use mongodb::{Collection, Cursor, IndexModel};
+use serde::de::DeserializeOwned;
+use serenity::async_trait;
+use tracing::*;
+
+use super::{AttemptRepository, AuditRepository, ConnectionRepository, GuildRepository};
+use crate::account_crypto::{encrypt_account_id, hash_account_id};
+use crate::dbmodels::audit::PrivacyAuditRecord;
+use crate::dbmodels::guild::{default_guild, Guild, SocialMediaAccounts};
+use crate::dbmodels::verification::VerificationAttempt;
+
+// The trailing space is part of the collection name that existing deployments already use.
+const ACCOUNTS_COLLECTION: &str = "socialmediaaccounts ";
+
+pub struct MongoGuildRepository {
+    col: Collection<Guild>,
+}
+
+impl MongoGuildRepository {
+    pub fn new(client: mongodb::Client, db_name: &str) -> MongoGuildRepository {
+        MongoGuildRepository {
+            col: client.database(db_name).collection("guilds"),
+        }
+    }
+
+    /// Applies `update` to the guild in one operation and returns the result.
+    async fn update(&self, guild_id: &str, update: Document) -> Result<Option<Guild>, String> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        match self
+            .col
+            .find_one_and_update(doc! {"guild_ID": guild_id}, update, options)
+            .await
+        {
+            Ok(guild) => Ok(guild),
+            Err(err) => Err(format!("Could not update guild {} - {:?}", guild_id, err)),
+        }
+    }
+}
+
+#[async_trait]
+impl GuildRepository for MongoGuildRepository {
+    async fn ensure_indexes(&self) -> Result<(), String> {
+        let model = IndexModel::builder()
+            .keys(doc! {"guild_ID": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        match self.col.create_index(model, None).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Could not create guild index - {:?}", err)),
+        }
+    }
+
+    async fn get(&self, guild_id: &str) -> Result<Option<Guild>, String> {
+        match self.col.find_one(doc! {"guild_ID": guild_id}, None).await {
+            Ok(guild) => Ok(guild),
+            Err(err) => Err(format!("Could not get guild {} - {:?}", guild_id, err)),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<Guild>, String> {
+        let options = FindOptions::builder().sort(doc! {"guild_ID": 1}).build();
+        match self.col.find(None, options).await {
+            Ok(cursor) => collect(cursor).await,
+            Err(err) => Err(format!("Could not list guilds - {:?}", err)),
+        }
+    }
+
+    async fn save(&self, guild: &Guild) -> Result<(), String> {
+        match self
+            .col
+            .replace_one(
+                doc! {"guild_ID": &guild.guild_ID},
+                guild,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!(
+                "Could not save guild {} - {:?}",
+                guild.guild_ID, err
+            )),
+        }
+    }
+
+    async fn set_fields(&self, guild_id: &str, fields: Document) -> Result<Option<Guild>, String> {
+        self.update(guild_id, doc! {"$set": fields}).await
+    }
+
+    async fn add_to_list(
+        &self,
+        guild_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<Guild>, String> {
+        self.update(guild_id, doc! {"$addToSet": {field: value}})
+            .await
+    }
+
+    async fn remove_from_list(
+        &self,
+        guild_id: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Option<Guild>, String> {
+        self.update(guild_id, doc! {"$pull": {field: value}}).await
+    }
+
+    async fn activate(&self, guild_id: &str) -> Result<(), String> {
+        let mut defaults = match bson::to_document(&default_guild(guild_id)) {
+            Ok(doc) => doc,
+            Err(err) => return Err(format!("{:?}", err)),
+        };
+        // `active` and `left_at` are always written below, and mongo rejects the same path in
+        // both operators.
+        defaults.remove("active");
+        defaults.remove("left_at");
+
+        match self
+            .col
+            .update_one(
+                doc! {"guild_ID": guild_id},
+                doc! {
+                    "$setOnInsert": defaults,
+                    "$set": {"active": true},
+                    "$unset": {"left_at": ""},
+                },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Could not upsert guild {} - {:?}", guild_id, err)),
+        }
+    }
+
+    async fn deactivate(&self, guild_id: &str) -> Result<(), String> {
+        match self
+            .col
+            .update_one(
+                doc! {"guild_ID": guild_id},
+                doc! {"$set": {"active": false, "left_at": DateTime::now()}},
+                None,
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!(
+                "Could not deactivate guild {} - {:?}",
+                guild_id, err
+            )),
+        }
+    }
+
+    async fn expired(&self, cutoff: DateTime) -> Result<Vec<String>, String> {
+        match self
+            .col
+            .find(doc! {"active": false, "left_at": {"$lte": cutoff}}, None)
+            .await
+        {
+            Ok(cursor) => Ok(collect(cursor)
+                .await?
+                .into_iter()
+                .map(|guild| guild.guild_ID)
+                .collect()),
+            Err(err) => Err(format!("Could not query expired guilds - {:?}", err)),
+        }
+    }
+
+    async fn delete_inactive(&self, guild_id: &str) -> Result<bool, String> {
+        match self
+            .col
+            .delete_one(doc! {"guild_ID": guild_id, "active": false}, None)
+            .await
+        {
+            Ok(res) => Ok(res.deleted_count > 0),
+            Err(err) => Err(format!("Could not delete guild {} - {:?}", guild_id, err)),
+        }
+    }
+}
+
+pub struct MongoConnectionRepository {
+    col: Collection<SocialMediaAccounts>,
+}
+
+impl MongoConnectionRepository {
+    pub fn new(client: mongodb::Client, db_name: &str) -> MongoConnectionRepository {
+        MongoConnectionRepository {
+            col: client.database(db_name).collection(ACCOUNTS_COLLECTION),
+        }
+    }
+}
+
+#[async_trait]
+impl ConnectionRepository for MongoConnectionRepository {
+    async fn add(&self, account: SocialMediaAccounts) -> Result<String, String> {
+        match self.col.insert_one(account, None).await {
+            Ok(res) => match res.inserted_id.as_object_id() {
+                Some(oid) => Ok(oid.to_hex()),
+                None => Err("Inserted account did not get an ObjectId.".to_string()),
+            },
+            Err(err) => Err(format!("Could not insert account - {:?}", err)),
+        }
+    }
+
+    async fn take_by_id(&self, id: &str) -> Result<Option<SocialMediaAccounts>, String> {
+        let oid = match ObjectId::parse_str(id) {
+            Ok(oid) => oid,
+            Err(err) => return Err(format!("Invalid account ID {} - {:?}", id, err)),
+        };
+        match self.col.find_one_and_delete(doc! {"_id": oid}, None).await {
+            Ok(account) => Ok(account),
+            Err(err) => Err(format!("Could not remove account {} - {:?}", id, err)),
+        }
+    }
+
+    async fn take_matching(
+        &self,
+        user_id: &str,
+        account_type: &str,
+        account_id_hash: &str,
+    ) -> Result<Vec<SocialMediaAccounts>, String> {
+        let filter = doc! {
+            "user_ID": user_id,
+            "account_type": account_type,
+            "account_id_hash": account_id_hash,
+        };
+        // One at a time, so every deleted account is also returned and can be restored.
+        let mut accounts = vec![];
+        loop {
+            match self.col.find_one_and_delete(filter.clone(), None).await {
+                Ok(Some(account)) => accounts.push(account),
+                Ok(None) => return Ok(accounts),
+                Err(err) => return Err(format!("Could not remove accounts - {:?}", err)),
+            }
+        }
+    }
+
+    async fn restore(&self, accounts: Vec<SocialMediaAccounts>) -> Result<(), String> {
+        if accounts.is_empty() {
+            return Ok(());
+        }
+        match self.col.insert_many(accounts, None).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Could not reinsert accounts - {:?}", err)),
+        }
+    }
+
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<SocialMediaAccounts>, String> {
+        match self.col.find(doc! {"user_ID": user_id}, None).await {
+            Ok(cursor) => collect(cursor).await,
+            Err(err) => Err(format!("Could not query social media accounts - {:?}", err)),
+        }
+    }
+
+    async fn delete_for_user(&self, user_id: &str) -> Result<u64, String> {
+        match self.col.delete_many(doc! {"user_ID": user_id}, None).await {
+            Ok(res) => Ok(res.deleted_count),
+            Err(err) => Err(format!(
+                "Could not delete social media accounts - {:?}",
+                err
+            )),
+        }
+    }
+
+    async fn migrate_plaintext_ids(&self) -> Result<u64, String> {
+        // Older versions of the bot stored `account_id` next to `user_ID`. The `SocialMediaAccounts`
+        // model they declared used `account_ID` and `discord_ID` instead, so accounts in that shape
+        // are converted as well.
+        let col: Collection<Document> = self.col.clone_with_type();
+        let filter = doc! {"$or": [
+            {"account_id": {"$exists": true}},
+            {"account_ID": {"$exists": true}},
+        ]};
+        let legacy = match col.find(filter, None).await {
+            Ok(cursor) => collect(cursor).await?,
+            Err(err) => return Err(format!("Could not query accounts to migrate - {:?}", err)),
+        };
+
+        let mut migrated = 0;
+        for account in legacy {
+            let oid = match account.get_object_id("_id") {
+                Ok(oid) => oid,
+                Err(_) => {
+                    warn!("Skipping account document without an ObjectId");
+                    continue;
+                }
+            };
+            let (account_id, mut set, unset) = match (
+                account.get_str("account_id"),
+                account.get_str("account_ID"),
+                account.get_str("discord_ID"),
+            ) {
+                (Ok(account_id), _, _) => (account_id, doc! {}, doc! {"account_id": ""}),
+                (_, Ok(account_id), Ok(user_id)) => (
+                    account_id,
+                    doc! {"user_ID": user_id},
+                    doc! {"account_ID": "", "discord_ID": ""},
+                ),
+                _ => {
+                    warn!("Skipping malformed account document {}", oid);
+                    continue;
+                }
+            };
+
+            set.insert("account_id_hash", hash_account_id(account_id)?);
+            if let Some(enc) = encrypt_account_id(account_id)? {
+                set.insert("account_id_enc", enc);
+            }
+
+            if let Err(err) = col
+                .update_one(doc! {"_id": oid}, doc! {"$set": set, "$unset": unset}, None)
+                .await
+            {
+                return Err(format!("Could not migrate account {} - {:?}", oid, err));
+            }
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+}
+
+pub struct MongoAttemptRepository {
+    col: Collection<VerificationAttempt>,
+}
+
+impl MongoAttemptRepository {
+    pub fn new(client: mongodb::Client, db_name: &str) -> MongoAttemptRepository {
+        MongoAttemptRepository {
+            col: client.database(db_name).collection("verificationattempts"),
+        }
+    }
+}
+
+#[async_trait]
+impl AttemptRepository for MongoAttemptRepository {
+    async fn record(&self, attempt: VerificationAttempt) -> Result<(), String> {
+        match self.col.insert_one(attempt, None).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Could not record verification attempt - {:?}", err)),
+        }
+    }
+
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<VerificationAttempt>, String> {
+        match self.col.find(doc! {"user_ID": user_id}, None).await {
+            Ok(cursor) => collect(cursor).await,
+            Err(err) => Err(format!("Could not query verification history - {:?}", err)),
+        }
+    }
+
+    async fn delete_for_user(&self, user_id: &str) -> Result<u64, String> {
+        match self.col.delete_many(doc! {"user_ID": user_id}, None).await {
+            Ok(res) => Ok(res.deleted_count),
+            Err(err) => Err(format!("Could not delete verification history - {:?}", err)),
+        }
+    }
+
+    async fn delete_for_guild(&self, guild_id: &str) -> Result<u64, String> {
+        match self
+            .col
+            .delete_many(doc! {"guild_ID": guild_id}, None)
+            .await
+        {
+            Ok(res) => Ok(res.deleted_count),
+            Err(err) => Err(format!("Could not delete attempt history - {:?}", err)),
+        }
+    }
+}
+
+pub struct MongoAuditRepository {
+    col: Collection<PrivacyAuditRecord>,
+}
+
+impl MongoAuditRepository {
+    pub fn new(client: mongodb::Client, db_name: &str) -> MongoAuditRepository {
+        MongoAuditRepository {
+            col: client.database(db_name).collection("privacyaudit"),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditRepository for MongoAuditRepository {
+    async fn record(&self, record: PrivacyAuditRecord) -> Result<(), String> {
+        match self.col.insert_one(record, None).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Could not write privacy audit record - {:?}", err)),
+        }
+    }
+}
+
+async fn collect<T: DeserializeOwned>(mut cursor: Cursor<T>) -> Result<Vec<T>, String> {
+    let mut items: Vec<T> = vec![];
+    loop {
+        match cursor.advance().await {
+            Ok(true) => match cursor.deserialize_current() {
+                Ok(item) => items.push(item),
+                Err(err) => return Err(format!("Could not deserialize document - {:?}", err)),
+            },
+            Ok(false) => break,
+            Err(err) => return Err(format!("Error while reading documents - {:?}", err)),
+        }
+    }
+    Ok(items)
+}
@@ -0,0 +1,305 @@
+use crate::application_commands::command_permission_keys;
+use crate::config::Sharding;
+use crate::dead_letters::{dead_letter_key, failing_key};
+use crate::repository::Repositories;
+use crate::state::AppState;
+use chrono::Utc;
+use mongodb::bson::DateTime;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::*;
+
+/// Number of days a guild's data is kept after the bot is removed, unless
+/// `GUILD_RETENTION_DAYS` says otherwise.
+pub const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+/// How often the retention job looks for expired guilds.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Purges expired guilds every hour while this replica is the leader, until shutdown. A purge
+/// cut short by shutdown is picked up again by the next run, the guild itself is deleted last.
+pub async fn run_retention(state: Arc<AppState>) {
+    let retention_days = state.config.guild_retention_days;
+    info!(
+        "Starting the retention job with a window of {} days",
+        retention_days
+    );
+    loop {
+        if state.leader.is_leader() {
+            let mut redis_conn = state.redis.clone();
+            tokio::select! {
+                _ = purge_expired_guilds(
+                    &state.repos,
+                    &mut redis_conn,
+                    retention_days,
+                    state.config.sharding,
+                ) => {}
+                _ = state.shutdown.requested() => break,
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RETENTION_INTERVAL) => {}
+            _ = state.shutdown.requested() => break,
+        }
+    }
+    info!("Stopped the retention job.");
+}
+
+/// Purges every guild on this process's shards that has been inactive for longer than
+/// `retention_days`. Each shard range has a leader of its own, so the ranges never purge the
+/// same guild.
+#[instrument(skip(repos, redis_conn))]
+pub async fn purge_expired_guilds(
+    repos: &Repositories,
+    redis_conn: &mut ConnectionManager,
+    retention_days: i64,
+    sharding: Sharding,
+) {
+    let cutoff = DateTime::from_millis(
+        (Utc::now() - chrono::Duration::days(retention_days)).timestamp_millis(),
+    );
+
+    let expired = match repos.guilds.expired(cutoff).await {
+        Ok(expired) => owned_guilds(expired, sharding),
+        Err(err) => {
+            error!("{}", err);
+            return;
+        }
+    };
+
+    if expired.is_empty() {
+        debug!("No guilds past the retention window.");
+        return;
+    }
+
+    for guild_id in expired {
+        if let Err(err) = purge_guild(repos, redis_conn, &guild_id).await {
+            error!("{}", err);
+        }
+    }
+}
+
+fn owned_guilds(guild_ids: Vec<String>, sharding: Sharding) -> Vec<String> {
+    guild_ids
+        .into_iter()
+        .filter(|guild_id| match guild_id.parse() {
+            Ok(id) => sharding.owns_guild(id),
+            Err(_) => {
+                warn!("Invalid guild ID {}", guild_id);
+                false
+            }
+        })
+        .collect()
+}
+
+/// Removes the guild's settings, its verification attempt history and any pending Redis keys.
+/// Nothing more is removed once the guild is no longer inactive.
+#[instrument(skip(repos, redis_conn))]
+pub async fn purge_guild(
+    repos: &Repositories,
+    redis_conn: &mut ConnectionManager,
+    guild_id: &str,
+) -> Result<(), String> {
+    purge_guild_with(repos, guild_id, || purge_redis_keys(redis_conn, guild_id)).await
+}
+
+async fn purge_guild_with<F, Fut>(
+    repos: &Repositories,
+    guild_id: &str,
+    purge_redis: F,
+) -> Result<(), String>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    info!("Purging data for guild {}", guild_id);
+
+    // The guild document goes last, so if a step fails the guild is still listed by `expired` and
+    // the next run tries again. If the bot was re-added in the meantime the guild keeps whatever
+    // history and pending verifications are left.
+    if !still_inactive(repos, guild_id).await? {
+        return Ok(());
+    }
+    purge_redis().await?;
+
+    if !still_inactive(repos, guild_id).await? {
+        return Ok(());
+    }
+    repos.attempts.delete_for_guild(guild_id).await?;
+
+    if !repos.guilds.delete_inactive(guild_id).await? {
+        info!("Guild {} is active again, keeping its settings", guild_id);
+        return Ok(());
+    }
+
+    info!("Purged data for guild {}", guild_id);
+    Ok(())
+}
+
+async fn still_inactive(repos: &Repositories, guild_id: &str) -> Result<bool, String> {
+    match repos.guilds.get(guild_id).await? {
+        Some(guild) if !guild.active => Ok(true),
+        Some(_) => {
+            info!("Guild {} is active again, keeping its data", guild_id);
+            Ok(false)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Deletes the guild's completion, dead letter, command permission and pending code keys.
+async fn purge_redis_keys(
+    redis_conn: &mut ConnectionManager,
+    guild_id: &str,
+) -> Result<(), String> {
+    let mut keys: Vec<String> = command_permission_keys(guild_id);
+    for pattern in [
+        format!("complete:*:{}", guild_id),
+        format!("complete:*:{}:*", guild_id),
+        dead_letter_key(&format!("complete:*:{}", guild_id)),
+        dead_letter_key(&format!("complete:*:{}:*", guild_id)),
+        failing_key(&format!("complete:*:{}", guild_id)),
+        failing_key(&format!("complete:*:{}:*", guild_id)),
+    ] {
+        match redis_conn.scan_match::<String, String>(pattern).await {
+            Ok(mut iter) => {
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+            }
+            Err(err) => return Err(format!("Redis error in scan - {:?}", err)),
+        }
+    }
+
+    // Pending codes are keyed by the code, the guild is only in the value `{user}:{guild}`.
+    let mut code_keys: Vec<String> = vec![];
+    match redis_conn.scan_match::<&str, String>("uuid:*").await {
+        Ok(mut iter) => {
+            while let Some(key) = iter.next_item().await {
+                code_keys.push(key);
+            }
+        }
+        Err(err) => return Err(format!("Redis error in scan - {:?}", err)),
+    }
+    let suffix = format!(":{}", guild_id);
+    for key in code_keys {
+        match redis_conn.get::<&str, Option<String>>(&key).await {
+            Ok(Some(val)) if val.ends_with(&suffix) => keys.push(key),
+            Ok(_) => {}
+            Err(err) => return Err(format!("Could not read key {} - {:?}", key, err)),
+        }
+    }
+
+    if !keys.is_empty() {
+        debug!("Deleting {} redis keys", keys.len());
+        if let Err(err) = redis_conn.del::<&Vec<String>, u64>(&keys).await {
+            return Err(format!("Could not delete redis keys - {:?}", err));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serenity::async_trait;
+
+    use super::*;
+    use crate::dbmodels::verification::VerificationAttempt;
+    use crate::repository::AttemptRepository;
+
+    struct FailingAttempts;
+
+    #[async_trait]
+    impl AttemptRepository for FailingAttempts {
+        async fn record(&self, _attempt: VerificationAttempt) -> Result<(), String> {
+            Ok(())
+        }
+        async fn list_for_user(&self, _user_id: &str) -> Result<Vec<VerificationAttempt>, String> {
+            Ok(vec![])
+        }
+        async fn delete_for_user(&self, _user_id: &str) -> Result<u64, String> {
+            Ok(0)
+        }
+        async fn delete_for_guild(&self, _guild_id: &str) -> Result<u64, String> {
+            Err("attempts unavailable".to_string())
+        }
+    }
+
+    async fn left_guild(repos: &Repositories, guild_id: &str) {
+        repos.guilds.activate(guild_id).await.unwrap();
+        repos.guilds.deactivate(guild_id).await.unwrap();
+    }
+
+    async fn expired_now(repos: &Repositories) -> Vec<String> {
+        let cutoff = DateTime::from_millis(DateTime::now().timestamp_millis() + 60_000);
+        repos.guilds.expired(cutoff).await.unwrap()
+    }
+
+    #[test]
+    fn only_guilds_on_this_processs_shards_are_purged() {
+        let on_shard = |shard: u64| ((shard << 22) | 7).to_string();
+        let expired = vec![on_shard(0), on_shard(1), on_shard(2), "invalid".to_string()];
+        let sharding = Sharding::Range {
+            first: 1,
+            last: 1,
+            total: 2,
+        };
+        assert_eq!(owned_guilds(expired.clone(), sharding), vec![on_shard(1)]);
+        assert_eq!(
+            owned_guilds(expired, Sharding::Auto),
+            vec![on_shard(0), on_shard(1), on_shard(2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn purge_removes_an_inactive_guild() {
+        let repos = Repositories::in_memory();
+        left_guild(&repos, "1").await;
+
+        purge_guild_with(&repos, "1", || async { Ok(()) })
+            .await
+            .unwrap();
+        assert!(repos.guilds.get("1").await.unwrap().is_none());
+        assert!(expired_now(&repos).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_redis_purge_keeps_the_guild_for_the_next_run() {
+        let repos = Repositories::in_memory();
+        left_guild(&repos, "1").await;
+
+        let res = purge_guild_with(&repos, "1", || async { Err("redis down".to_string()) }).await;
+        assert!(res.is_err());
+        assert_eq!(expired_now(&repos).await, vec!["1"]);
+    }
+
+    #[tokio::test]
+    async fn failed_attempt_purge_keeps_the_guild_for_the_next_run() {
+        let mut repos = Repositories::in_memory();
+        repos.attempts = Arc::new(FailingAttempts);
+        left_guild(&repos, "1").await;
+
+        let res = purge_guild_with(&repos, "1", || async { Ok(()) }).await;
+        assert!(res.is_err());
+        assert_eq!(expired_now(&repos).await, vec!["1"]);
+    }
+
+    #[tokio::test]
+    async fn guild_re_added_during_the_purge_is_kept() {
+        let repos = Repositories::in_memory();
+        left_guild(&repos, "1").await;
+
+        let guilds = repos.guilds.clone();
+        purge_guild_with(&repos, "1", || async move { guilds.activate("1").await })
+            .await
+            .unwrap();
+        let guild = repos.guilds.get("1").await.unwrap().unwrap();
+        assert!(guild.active);
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::prelude::Mutex;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::*;
+
+use crate::leader;
+use crate::state::AppState;
+
+const GATEWAY_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Coordinates a graceful shutdown between the signal handler and the check loop.
+#[derive(Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    /// Held by the check loop for the whole of a pass, so shutdown can wait for it to finish.
+    pass: tokio::sync::Mutex<()>,
+    /// Wakes the background jobs waiting in `requested`.
+    notify: tokio::sync::Notify,
+}
+
+impl Shutdown {
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+
+    /// Waits until shutdown has been requested, for `select!`ing against a job's work.
+    pub async fn requested(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // Registered before the flag is checked, so a request in between isn't missed.
+        notified.as_mut().enable();
+        if self.is_requested() {
+            return;
+        }
+        notified.await;
+    }
+
+    /// Locks out shutdown until the returned guard is dropped, or returns `None` if shutdown has
+    /// already started and no new pass should begin.
+    pub async fn begin_pass(&self) -> Option<tokio::sync::MutexGuard<'_, ()>> {
+        let guard = self.pass.lock().await;
+        if self.is_requested() {
+            return None;
+        }
+        Some(guard)
+    }
+}
+
+/// Waits for SIGTERM or Ctrl-C.
+async fn wait_for_signal() {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(err) => {
+            error!("Could not listen for SIGTERM - {}", err);
+            if let Err(err) = tokio::signal::ctrl_c().await {
+                error!("Could not listen for Ctrl-C - {}", err);
+            }
+            return;
+        }
+    };
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM."),
+        _ = tokio::signal::ctrl_c() => info!("Received Ctrl-C."),
+    }
+}
+
+/// On a signal, stops the check loop from taking new completions, waits for the current pass
+/// (up to `SHUTDOWN_TIMEOUT_SECS`) and then disconnects every shard, which makes
+/// `Client::start` return.
+pub async fn handle_signals(state: Arc<AppState>, shard_manager: Arc<Mutex<ShardManager>>) {
+    wait_for_signal().await;
+    info!("Shutting down...");
+    state.shutdown.requested.store(true, Ordering::Relaxed);
+    state.shutdown.notify.notify_waiters();
+
+    let timeout = Duration::from_secs(state.config.shutdown_timeout_secs);
+    match tokio::time::timeout(timeout, state.shutdown.pass.lock()).await {
+        Ok(_guard) => info!("The check loop has finished its current pass."),
+        Err(_) => warn!(
+            "The check loop did not finish within {} seconds, stopping anyway.",
+            timeout.as_secs()
+        ),
+    }
+
+    leader::resign(&state).await;
+    shard_manager.lock().await.shutdown_all().await;
+
+    // shutdown_all does nothing while no shard is running, e.g. while the gateway is unreachable,
+    // and then Client::start never returns. Main exits first whenever the shards close normally.
+    tokio::time::sleep(GATEWAY_CLOSE_TIMEOUT).await;
+    warn!("The gateway did not close in time, exiting anyway.");
+    flush_logs();
+    std::process::exit(0);
+}
+
+/// Makes sure the last log lines are written out before the process exits.
+pub fn flush_logs() {
+    if let Err(err) = std::io::stdout().flush() {
+        eprintln!("Could not flush logs - {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(shutdown: &Shutdown) {
+        shutdown.requested.store(true, Ordering::Relaxed);
+        shutdown.notify.notify_waiters();
+    }
+
+    #[tokio::test]
+    async fn requested_wakes_waiting_jobs() {
+        let shutdown = Arc::new(Shutdown::default());
+        let waiter = tokio::spawn({
+            let shutdown = Arc::clone(&shutdown);
+            async move { shutdown.requested().await }
+        });
+        tokio::task::yield_now().await;
+        request(&shutdown);
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn requested_returns_right_away_after_shutdown() {
+        let shutdown = Shutdown::default();
+        request(&shutdown);
+        tokio::time::timeout(Duration::from_secs(1), shutdown.requested())
+            .await
+            .unwrap();
+    }
+}
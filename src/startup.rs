@@ -1,60 +1,36 @@
-use crate::dbmodels::guild::{Guild, GuildSettings};
-use crate::mongo_conn::{get_collection, get_db};
-use mongodb::bson::doc;
-use mongodb::options::IndexOptions;
-use mongodb::*;
+use crate::repository::Repositories;
 use serenity::prelude::*;
 use tracing::*;
 
-#[instrument(skip(ctx, client))]
-pub async fn insert_guilds(ctx: &Context, client: &mongodb::Client) -> Result<(), String> {
-    let db = get_db(client, "botdb").await;
-    let col: Collection<Guild> = get_collection(&db, "guilds", None).await;
-    let guilds = ctx.cache.guilds();
-    for guild in guilds {
-        info!("Inserting ({}) into MongoDB", guild.0);
-        let res = col
-            .insert_one(
-                Guild {
-                    guild_ID: guild.0.to_string(),
-                    mod_channel_ID: "0".to_string(),
-                    verification_channel_ID: "0".to_string(),
-                    verification_role_ID: "0".to_string(),
-                    mod_role_ID: "0".to_string(),
-                    prefix_string: "~".to_string(),
-                    verification_age: 0,
-                    enabled: false,
-                    verify_on_screening: false,
-                    verification_logs_channel_ID: "0".to_string(),
-                    guild_settings: GuildSettings {
-                        zero_point: 0,
-                        difficulty_addition: 0,
-                        mfa_bonus: 0,
-                        premium_bonus: 0,
-                        preferred_num_of_accounts: 0,
-                    },
-                },
-                None,
-            )
-            .await;
+#[instrument(skip(ctx, repos))]
+pub async fn insert_guilds(ctx: &Context, repos: &Repositories) -> Result<(), String> {
+    if let Err(err) = repos.guilds.ensure_indexes().await {
+        error!("{}", err)
+    }
 
-        if let Err(err) = res {
-            return Err(format!("{:?}", err));
+    // Keep going on failures so one bad guild doesn't stop the rest from being upserted.
+    let mut failed: Vec<u64> = vec![];
+    for guild in ctx.cache.guilds() {
+        info!("Upserting ({}) into the database", guild.0);
+        if let Err(err) = repos.guilds.activate(&guild.0.to_string()).await {
+            error!("{}", err);
+            failed.push(guild.0);
         }
+    }
 
-        let model = IndexModel::builder()
-            .keys(doc! {"guild_ID": 1})
-            .options(IndexOptions::builder().unique(true).build())
-            .build();
-
-        let res = col.create_index(model, None).await;
+    if !failed.is_empty() {
+        return Err(format!("Failed to upsert guilds: {:?}", failed));
+    }
+    Ok(())
+}
 
-        match res {
-            Ok(_) => {}
-            Err(e) => {
-                error!("{:?}", e)
-            }
-        }
+/// Replaces plain text `account_id` fields left by older versions with the keyed hash and,
+/// if configured, the encrypted ID.
+#[instrument(skip(repos))]
+pub async fn migrate_account_ids(repos: &Repositories) -> Result<(), String> {
+    let migrated = repos.connections.migrate_plaintext_ids().await?;
+    if migrated > 0 {
+        info!("Migrated {} social media accounts to hashed IDs", migrated);
     }
     Ok(())
 }
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use redis::aio::ConnectionManager;
+use serenity::http::Http;
+use serenity::model::id::CommandId;
+use serenity::prelude::{Context, TypeMapKey};
+
+use crate::commands::middleware::Cooldowns;
+use crate::config::Config;
+use crate::health::GatewayHealth;
+use crate::leader::Leadership;
+use crate::redis_check_loop::CheckLoopHealth;
+use crate::repository::Repositories;
+use crate::shutdown::Shutdown;
+
+/// Everything shared between the event handler, the commands and the background jobs.
+/// Built once in `main` and stored in serenity's `TypeMap`.
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub repos: Repositories,
+    /// Only set when the Mongo storage backend is used.
+    pub mongo: Option<mongodb::Client>,
+    /// Cheap to clone, every clone shares the same multiplexed connection.
+    pub redis: ConnectionManager,
+    pub check_loop: CheckLoopHealth,
+    pub gateway: GatewayHealth,
+    pub leader: Leadership,
+    pub cooldowns: Cooldowns,
+    /// Authenticated with `command_permissions_token`, unset without one.
+    pub command_permissions: Option<Http>,
+    /// The IDs of the registered commands by name, once they have been synced or looked up.
+    pub command_ids: RwLock<Option<HashMap<String, CommandId>>>,
+    pub shutdown: Shutdown,
+}
+
+impl TypeMapKey for AppState {
+    type Value = Arc<AppState>;
+}
+
+/// Gets the shared state out of the context.
+pub async fn app_state(ctx: &Context) -> Arc<AppState> {
+    let data = ctx.data.read().await;
+    match data.get::<AppState>() {
+        Some(state) => Arc::clone(state),
+        // main inserts the state before the client is started.
+        None => panic!("AppState is missing from the TypeMap."),
+    }
+}